@@ -4,8 +4,25 @@
 use std::path::Path;
 
 use super::compression;
+#[cfg(feature = "unverified-pages")]
+use super::csv;
 use super::des;
-use super::xml::{self, ScriptType};
+use super::encoding::{self, EncodingError, Normalized};
+#[cfg(feature = "unverified-pages")]
+use super::graph_spec::{GraphSpec, GraphSpecError, SpecFormat};
+#[cfg(feature = "unverified-pages")]
+use super::image::{self, ImageError, ResourceFile};
+use super::math_check;
+use super::math_render::RenderOptions;
+use super::notes;
+use super::python::{self, PythonModule};
+#[cfg(feature = "unverified-pages")]
+use super::tibasic::{self, TiBasicError};
+#[cfg(feature = "unverified-pages")]
+use super::variables::DocVariable;
+use super::xml::{self, ScriptType, Widget};
+#[cfg(feature = "unverified-pages")]
+use super::xml::ScriptResource;
 use super::tns_writer::{self, TnsFileEntry};
 
 /// Errors that can occur during conversion
@@ -20,24 +37,112 @@ pub enum ConversionError {
     #[error("DES encryption error: {0}")]
     Des(#[from] des::DESError),
 
+    #[cfg(feature = "unverified-pages")]
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::CsvError),
+
+    #[cfg(feature = "unverified-pages")]
+    #[error("Graph spec error: {0}")]
+    GraphSpec(#[from] GraphSpecError),
+
+    #[cfg(feature = "unverified-pages")]
+    #[error("TI-Basic error: {0}")]
+    TiBasic(#[from] TiBasicError),
+
+    #[cfg(feature = "unverified-pages")]
+    #[error("Image error: {0}")]
+    Image(#[from] ImageError),
+
+    #[error("Encoding error: {0}")]
+    Encoding(#[from] EncodingError),
+
     #[error("ZIP error: {0}")]
     Zip(String),
 
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 
-    #[allow(dead_code)]
     #[error("Invalid input: {0}")]
     InvalidInput(String),
+
+    #[error("LaTeX problems (--strict):\n{}", .0.join("\n"))]
+    Latex(Vec<String>),
+}
+
+/// Options that change how input is converted
+#[derive(Debug, Clone, Default)]
+pub struct ConversionOptions {
+    /// Emit text as a native TI Notes page instead of a Lua viewer script
+    pub notes_app: bool,
+    /// Field delimiter for CSV input (`,` when unset)
+    #[cfg(feature = "unverified-pages")]
+    pub csv_delimiter: Option<char>,
+    /// Variables stored in every generated document (see `--vars`)
+    #[cfg(feature = "unverified-pages")]
+    pub variables: Vec<DocVariable>,
+    /// Images embedded in Lua script documents (see `--resource`)
+    #[cfg(feature = "unverified-pages")]
+    pub resources: Vec<ResourceFile>,
+    /// Rename Python files the calculator would reject instead of failing
+    pub sanitize_filenames: bool,
+    /// Whether and where to add a Python Shell page running the main script
+    #[cfg(feature = "unverified-pages")]
+    pub python_shell: ShellPage,
+    /// Store Lua scripts as plain UTF-8 instead of TI's character encoding
+    pub raw_utf8: bool,
+    /// Expand tab indentation in Python sources to this many columns
+    pub tab_width: Option<usize>,
+    /// How LaTeX math in text is rendered by the Lua viewer
+    pub math: RenderOptions,
+    /// Fail on LaTeX problems in text instead of reporting them as warnings
+    pub strict: bool,
+}
+
+/// Placement of the Python Shell page in Python documents
+#[cfg(feature = "unverified-pages")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ShellPage {
+    /// Editor pages only, as upstream Luna writes them
+    #[default]
+    None,
+    /// Shell page after the editor pages
+    Last,
+    /// Shell page first, so the document opens in the running program
+    First,
+}
+
+/// Outcome of a successful conversion
+#[derive(Debug, Clone, Default)]
+pub struct ConversionReport {
+    /// Problems that didn't stop the conversion but changed the output
+    pub warnings: Vec<String>,
 }
 
 /// Main converter that orchestrates the conversion process
-pub struct Converter;
+pub struct Converter {
+    options: ConversionOptions,
+}
 
 impl Converter {
     /// Create a new converter instance
     pub fn new() -> Self {
-        Self
+        Self::with_options(ConversionOptions::default())
+    }
+
+    /// Create a converter with the given options
+    pub fn with_options(options: ConversionOptions) -> Self {
+        Self { options }
+    }
+
+    /// Decode and normalize an input file before conversion
+    ///
+    /// Strips byte order marks, transcodes UTF-16 and Windows-1252 input
+    /// and converts line endings to `\n`. Python sources also get their tab
+    /// indentation expanded when `tab_width` is set. The returned changes
+    /// describe everything that was altered.
+    pub fn load_source(&self, bytes: &[u8], python: bool) -> Result<Normalized, ConversionError> {
+        let tab_width = if python { self.options.tab_width } else { None };
+        Ok(encoding::normalize_source(bytes, tab_width)?)
     }
 
     /// Convert a script file to .tns format
@@ -54,10 +159,10 @@ impl Converter {
         output_path: &Path,
         script_type: ScriptType,
         _encrypt: bool,
-    ) -> Result<(), ConversionError> {
-        // Read the script content
-        let script_content = std::fs::read_to_string(input_path)
-            .map_err(|e| ConversionError::Io(e))?;
+    ) -> Result<ConversionReport, ConversionError> {
+        // Read and normalize the script content
+        let bytes = std::fs::read(input_path)?;
+        let script_content = self.load_source(&bytes, script_type == ScriptType::Python)?.text;
         
         // Convert based on script type
         match script_type {
@@ -85,36 +190,36 @@ impl Converter {
         lua_script: &str,
         output_path: &Path,
         document_name: &str,
-    ) -> Result<(), ConversionError> {
-        // 1. Wrap Lua script in XML
-        let script_xml = xml::wrap_lua_script(lua_script, document_name)?;
-        
-        // 2. Compress the XML
-        let compressed = compression::compress_xml(&script_xml)?;
-        
-        // 3. Pad to 8-byte boundary for DES
-        let mut padded = pad_to_8_bytes(compressed);
-        
-        // 4. Encrypt with DES
-        des::encrypt_document(&mut padded)?;
+    ) -> Result<ConversionReport, ConversionError> {
+        // 1. Encode non-ASCII characters the way the calculator reads them
+        let script = if self.options.raw_utf8 {
+            lua_script.as_bytes().to_vec()
+        } else {
+            xml::escape_unicode(lua_script)?
+        };
+
+        // 2. Wrap Lua script in XML, adding a symbol table and resources only when needed
+        #[cfg(feature = "unverified-pages")]
+        let (script_xml, has_bitmap) = self.lua_problem_xml(script, document_name)?;
+        #[cfg(not(feature = "unverified-pages"))]
+        let (script_xml, has_bitmap) = (xml::wrap_encoded_lua_script(&script, document_name)?, false);
         
-        // 5. Add TI encrypted header
-        let mut problem_data = Vec::new();
-        problem_data.extend_from_slice(xml::get_ti_encrypted_header());
-        problem_data.extend_from_slice(&padded);
+        // 3. Compress, pad and encrypt the problem
+        let problem_data = encrypt_problem_xml(&script_xml)?;
         
-        // 6. Get default Document.xml
+        // 4. Get default Document.xml
         let document_xml = xml::create_default_document_xml();
         
-        // 7. Create the .tns archive
+        // 5. Create the .tns archive, marked as containing bitmaps if it does
         create_tns_archive(
             output_path,
             document_xml,
             &problem_data,
             "Problem1.xml",
+            has_bitmap,
         )?;
         
-        Ok(())
+        Ok(ConversionReport::default())
     }
 
     /// Convert a Python script to .tns format
@@ -130,44 +235,71 @@ impl Converter {
         python_filename: &str,
         output_path: &Path,
         document_name: &str,
-    ) -> Result<(), ConversionError> {
-        // 1. Create Python XML wrapper
-        let python_xml = xml::wrap_python_script(python_filename, document_name)?;
-        
-        // 2. Compress the XML
-        let compressed = compression::compress_xml(&python_xml)?;
-        
-        // 3. Pad to 8-byte boundary for DES
-        let mut padded = pad_to_8_bytes(compressed);
-        
-        // 4. Encrypt with DES
-        des::encrypt_document(&mut padded)?;
+    ) -> Result<ConversionReport, ConversionError> {
+        let module = PythonModule::new(python_filename, python_script);
+        self.convert_python_project_to_tns(&[module], output_path, document_name)
+    }
+
+    /// Convert a Python project of one or more modules to .tns format
+    ///
+    /// The first module is the main script. Every module is stored as its
+    /// own `.py` file in the document and gets a Python Editor page, so the
+    /// modules can import each other on the calculator. `python_shell` adds
+    /// a Python Shell page that runs the main script (with the
+    /// `unverified-pages` feature).
+    ///
+    /// # Arguments
+    /// * `modules` - The main script followed by its local modules
+    /// * `output_path` - Path where the .tns file will be written
+    /// * `document_name` - Name for the document (empty for default)
+    pub fn convert_python_project_to_tns(
+        &self,
+        modules: &[PythonModule],
+        output_path: &Path,
+        document_name: &str,
+    ) -> Result<ConversionReport, ConversionError> {
+        let mut report = ConversionReport::default();
+        #[cfg(feature = "unverified-pages")]
+        self.warn_unused_resources(&mut report.warnings);
+        let modules = &self.python_filenames(modules, &mut report.warnings)?;
+
+        // 1. Create Python XML wrapper, one page per module
+        let python_xml = match modules.as_slice() {
+            [] => return Err(ConversionError::InvalidInput("No Python modules given".to_string())),
+            #[cfg(feature = "unverified-pages")]
+            [main] if self.options.variables.is_empty() && self.options.python_shell == ShellPage::None => {
+                xml::wrap_python_script(&main.filename, document_name)?
+            }
+            #[cfg(not(feature = "unverified-pages"))]
+            [main] => xml::wrap_python_script(&main.filename, document_name)?,
+            _ => self.problem_xml(&self.python_pages(modules))?,
+        };
         
-        // 5. Add TI encrypted header
-        let mut problem_data = Vec::new();
-        problem_data.extend_from_slice(xml::get_ti_encrypted_header());
-        problem_data.extend_from_slice(&padded);
+        // 2. Compress, pad and encrypt the problem
+        let problem_data = encrypt_problem_xml(&python_xml)?;
         
-        // 6. Get default Document.xml
+        // 3. Get default Document.xml
         let document_xml = xml::create_default_document_xml();
         
-        // 7. Create the .tns archive with both Problem1.xml and the Python file
+        // 4. Create the .tns archive with Problem1.xml and the Python files
         create_tns_archive_with_python(
             output_path,
             document_xml,
             &problem_data,
-            python_filename,
-            python_script.as_bytes(),
+            modules,
         )?;
         
-        Ok(())
+        Ok(report)
     }
 
     /// Convert plain text to .tns format
     ///
-    /// Since TI-Nspire doesn't have a native "plain text note" format,
-    /// this converts the text to a Lua script that displays the text on screen.
-    /// The resulting .tns file will show the text when opened on the calculator.
+    /// By default this converts the text to a Lua script that displays the
+    /// text on screen. With `notes_app` set, the text becomes a native TI
+    /// Notes page instead (see `convert_text_to_notes_tns`).
+    ///
+    /// Problems in the LaTeX (see `math_check`) come first in the warnings,
+    /// or fail the conversion with `strict` set.
     ///
     /// # Arguments
     /// * `text` - The plain text content
@@ -178,12 +310,337 @@ impl Converter {
         text: &str,
         output_path: &Path,
         document_name: &str,
-    ) -> Result<(), ConversionError> {
-        // Convert text to a Lua script that displays it
-        let lua_script = xml::text_to_lua_script(text);
-        
-        // Use the existing Lua conversion pipeline
-        self.convert_lua_to_tns(&lua_script, output_path, document_name)
+    ) -> Result<ConversionReport, ConversionError> {
+        let problems: Vec<String> =
+            math_check::check_latex(text, &self.options.math).iter().map(ToString::to_string).collect();
+        if self.options.strict && !problems.is_empty() {
+            return Err(ConversionError::Latex(problems));
+        }
+
+        let mut report = if self.options.notes_app {
+            self.convert_text_to_notes_tns(text, output_path, document_name)?
+        } else {
+            // Convert text to a Lua script that displays it
+            let lua_script = xml::text_to_lua_script(text, &self.options.math);
+
+            // Use the existing Lua conversion pipeline
+            self.convert_lua_to_tns(&lua_script, output_path, document_name)?
+        };
+        report.warnings.splice(0..0, problems);
+        Ok(report)
+    }
+
+    /// Convert plain text to a .tns file with a native TI Notes page
+    ///
+    /// Each line becomes a rich-text paragraph; `**bold**` and `*italic*`
+    /// markup is applied and `$...$` math becomes Math Boxes. Math without a
+    /// calculator form is kept as Unicode text and reported as a warning.
+    /// Unlike the Lua viewer, the text can be edited,
+    /// selected and copied on the calculator.
+    ///
+    /// # Arguments
+    /// * `text` - The plain text content
+    /// * `output_path` - Path where the .tns file will be written
+    /// * `_document_name` - Name for the document (empty for default)
+    pub fn convert_text_to_notes_tns(
+        &self,
+        text: &str,
+        output_path: &Path,
+        _document_name: &str,
+    ) -> Result<ConversionReport, ConversionError> {
+        let mut report = ConversionReport::default();
+        #[cfg(feature = "unverified-pages")]
+        self.warn_unused_resources(&mut report.warnings);
+
+        // 1. Build the Notes page
+        let paragraphs = notes::parse_notes(text, &self.options.math, &mut report.warnings);
+        let widget = Widget::Notes { tree: notes::to_r2d_tree(&paragraphs) };
+        let problem_xml = self.problem_xml(&[widget])?;
+
+        // 2. Compress, pad and encrypt the problem
+        let problem_data = encrypt_problem_xml(&problem_xml)?;
+
+        // 3. Create the .tns archive
+        create_tns_archive(
+            output_path,
+            xml::create_default_document_xml(),
+            &problem_data,
+            "Problem1.xml",
+            false,
+        )?;
+
+        Ok(report)
+    }
+
+    /// Convert CSV data to a .tns file with a Lists & Spreadsheet page
+    ///
+    /// Header cells name the columns; each column is stored as a list
+    /// variable in the document so it can be used from other pages.
+    ///
+    /// # Arguments
+    /// * `csv_text` - The CSV content, header row first
+    /// * `output_path` - Path where the .tns file will be written
+    /// * `_document_name` - Name for the document (empty for default)
+    #[cfg(feature = "unverified-pages")]
+    pub fn convert_csv_to_tns(
+        &self,
+        csv_text: &str,
+        output_path: &Path,
+        _document_name: &str,
+    ) -> Result<ConversionReport, ConversionError> {
+        let mut report = ConversionReport::default();
+        self.warn_unused_resources(&mut report.warnings);
+
+        // 1. Read the columns as list variables
+        let delimiter = self.options.csv_delimiter.unwrap_or(',');
+        let rows = csv::parse_csv(csv_text, delimiter)?;
+        let lists = csv::rows_to_lists(&rows, &mut report.warnings)?;
+
+        // 2. Build the Lists & Spreadsheet page bound to those lists
+        let columns = lists.iter().map(|l| l.name.clone()).collect();
+        let widget = Widget::ListsSpreadsheet { columns };
+        let variables = self.document_variables(lists, &mut report.warnings);
+        let problem_xml = xml::build_problem_with_variables(&variables, &[widget])?;
+
+        // 3. Compress, pad and encrypt the problem
+        let problem_data = encrypt_problem_xml(&problem_xml)?;
+
+        // 4. Create the .tns archive
+        create_tns_archive(
+            output_path,
+            xml::create_default_document_xml(),
+            &problem_data,
+            "Problem1.xml",
+            false,
+        )?;
+
+        Ok(report)
+    }
+
+    /// Convert a function spec to a .tns file with a Graphs page
+    ///
+    /// Slider values are stored as document variables so the plotted
+    /// functions can use them.
+    ///
+    /// # Arguments
+    /// * `spec_text` - The spec, as plain text or TOML (see `graph_spec`)
+    /// * `format` - Which of the two spec formats `spec_text` uses
+    /// * `output_path` - Path where the .tns file will be written
+    /// * `_document_name` - Name for the document (empty for default)
+    #[cfg(feature = "unverified-pages")]
+    pub fn convert_graph_spec_to_tns(
+        &self,
+        spec_text: &str,
+        format: SpecFormat,
+        output_path: &Path,
+        _document_name: &str,
+    ) -> Result<ConversionReport, ConversionError> {
+        let mut report = ConversionReport::default();
+        self.warn_unused_resources(&mut report.warnings);
+
+        // 1. Read the spec
+        let spec = GraphSpec::parse(spec_text, format)?;
+
+        // 2. Build the Graphs page, with sliders backed by variables
+        let variables = self.document_variables(spec.slider_variables(), &mut report.warnings);
+        let problem_xml = xml::build_problem_with_variables(&variables, &[Widget::Graphs(spec)])?;
+
+        // 3. Compress, pad and encrypt the problem
+        let problem_data = encrypt_problem_xml(&problem_xml)?;
+
+        // 4. Create the .tns archive
+        create_tns_archive(
+            output_path,
+            xml::create_default_document_xml(),
+            &problem_data,
+            "Problem1.xml",
+            false,
+        )?;
+
+        Ok(report)
+    }
+
+    /// Convert TI-Basic source to a .tns file of stored programs/functions
+    ///
+    /// Every `Define` block is stored as a document variable and gets its
+    /// own Program Editor page. `LibPub`/`LibPriv` markers are kept, so the
+    /// result can be used as a library document in MyLib.
+    ///
+    /// # Arguments
+    /// * `source` - TI-Basic source with `Define ... EndPrgm`/`EndFunc` blocks
+    /// * `output_path` - Path where the .tns file will be written
+    /// * `_document_name` - Name for the document (empty for default)
+    #[cfg(feature = "unverified-pages")]
+    pub fn convert_tibasic_to_tns(
+        &self,
+        source: &str,
+        output_path: &Path,
+        _document_name: &str,
+    ) -> Result<ConversionReport, ConversionError> {
+        let mut report = ConversionReport::default();
+        self.warn_unused_resources(&mut report.warnings);
+
+        // 1. Read the definitions
+        let definitions = tibasic::parse_tibasic(source)?;
+
+        // 2. Store each one and give it a Program Editor page
+        let stored = definitions.iter().map(|d| d.to_variable()).collect();
+        let variables = self.document_variables(stored, &mut report.warnings);
+        let widgets: Vec<_> = definitions
+            .iter()
+            .map(|d| Widget::ProgramEditor { name: d.name.clone() })
+            .collect();
+        let problem_xml = xml::build_problem_with_variables(&variables, &widgets)?;
+
+        // 3. Compress, pad and encrypt the problem
+        let problem_data = encrypt_problem_xml(&problem_xml)?;
+
+        // 4. Create the .tns archive
+        create_tns_archive(
+            output_path,
+            xml::create_default_document_xml(),
+            &problem_data,
+            "Problem1.xml",
+            false,
+        )?;
+
+        Ok(report)
+    }
+
+    /// Build a problem XML, with the `--vars` variables in its symbol table
+    fn problem_xml(&self, widgets: &[Widget]) -> Result<Vec<u8>, ConversionError> {
+        #[cfg(feature = "unverified-pages")]
+        if !self.options.variables.is_empty() {
+            return Ok(xml::build_problem_with_variables(&self.options.variables, widgets)?);
+        }
+        Ok(xml::build_problem(widgets)?)
+    }
+
+    /// Wrap an encoded Lua script with the `--vars` variables and the
+    /// `--resource` images, and tell whether the document has images
+    #[cfg(feature = "unverified-pages")]
+    fn lua_problem_xml(&self, script: Vec<u8>, document_name: &str) -> Result<(Vec<u8>, bool), ConversionError> {
+        let resources = self.script_resources()?;
+        if self.options.variables.is_empty() && resources.is_empty() {
+            return Ok((xml::wrap_encoded_lua_script(&script, document_name)?, false));
+        }
+        let widget = Widget::LuaScript { script, resources };
+        Ok((xml::build_problem_with_variables(&self.options.variables, &[widget])?, true))
+    }
+
+    /// A Python Editor page per module, and the Python Shell page for the
+    /// main module where `python_shell` asks for one
+    fn python_pages(&self, modules: &[PythonModule]) -> Vec<Widget> {
+        let editors = modules.iter().map(|m| Widget::PythonEditor { filename: m.filename.clone() });
+        #[cfg(feature = "unverified-pages")]
+        if let Some(main) = modules.first() {
+            let shell = Widget::PythonShell { filename: main.filename.clone() };
+            return match self.options.python_shell {
+                ShellPage::None => editors.collect(),
+                ShellPage::Last => editors.chain([shell]).collect(),
+                ShellPage::First => std::iter::once(shell).chain(editors).collect(),
+            };
+        }
+        editors.collect()
+    }
+
+    /// Check module file names against the calculator's rules
+    ///
+    /// With `sanitize_filenames`, invalid names are renamed (keeping them
+    /// unique) and each rename is reported in `warnings`; otherwise the
+    /// first invalid name is an error. Valid names are never taken by a
+    /// renamed file, so imports of them keep working. The same name is used
+    /// for the page and the archive entry.
+    fn python_filenames(
+        &self,
+        modules: &[PythonModule],
+        warnings: &mut Vec<String>,
+    ) -> Result<Vec<PythonModule>, ConversionError> {
+        let mut checked: Vec<PythonModule> = Vec::with_capacity(modules.len());
+        let reserved: Vec<&str> = modules
+            .iter()
+            .map(|m| m.filename.as_str())
+            .filter(|name| python::validate_filename(name).is_ok())
+            .collect();
+
+        for module in modules {
+            let mut filename = module.filename.clone();
+            if let Err(reason) = python::validate_filename(&filename) {
+                if !self.options.sanitize_filenames {
+                    return Err(ConversionError::InvalidInput(format!(
+                        "Python file name \"{}\" is not allowed on the calculator: {} (use --sanitize to rename it)",
+                        filename, reason
+                    )));
+                }
+                filename = python::sanitize_filename(&filename);
+            }
+
+            // Keep names unique, as sanitizing can map two names to one
+            let taken = |name: &str, checked: &[PythonModule]| {
+                checked.iter().any(|m| m.filename.eq_ignore_ascii_case(name))
+                    || (name != module.filename && reserved.iter().any(|r| r.eq_ignore_ascii_case(name)))
+            };
+            let stem = filename.trim_end_matches(".py").to_string();
+            let mut suffix = 2;
+            while taken(&filename, &checked) {
+                let tail = format!("_{}.py", suffix);
+                let keep = stem.len().min(python::MAX_FILENAME_LEN - tail.len());
+                filename = format!("{}{}", &stem[..keep], tail);
+                suffix += 1;
+            }
+
+            if filename != module.filename {
+                warnings.push(format!("Python file \"{}\" stored as {}", module.filename, filename));
+            }
+            checked.push(PythonModule { filename, source: module.source.clone() });
+        }
+
+        Ok(checked)
+    }
+
+    /// Decode `--resource` images into TI.Image script resources
+    #[cfg(feature = "unverified-pages")]
+    fn script_resources(&self) -> Result<Vec<ScriptResource>, ConversionError> {
+        let mut resources: Vec<ScriptResource> = Vec::with_capacity(self.options.resources.len());
+        for file in &self.options.resources {
+            if !image::is_valid_resource_name(&file.name) {
+                return Err(ConversionError::InvalidInput(format!(
+                    "Resource name '{}' must be a Lua identifier that is not a keyword", file.name
+                )));
+            }
+            if resources.iter().any(|r| r.name == file.name) {
+                return Err(ConversionError::InvalidInput(format!("Resource {} is given twice", file.name)));
+            }
+            let data = image::decode_image(&file.data)?.to_ti_image();
+            resources.push(ScriptResource { name: file.name.clone(), data });
+        }
+        Ok(resources)
+    }
+
+    /// Note that `--resource` images only go into Lua script documents
+    #[cfg(feature = "unverified-pages")]
+    fn warn_unused_resources(&self, warnings: &mut Vec<String>) {
+        if !self.options.resources.is_empty() {
+            warnings.push("images are only embedded in Lua script documents; --resource ignored".to_string());
+        }
+    }
+
+    /// Combine `--vars` variables with the ones a conversion generates
+    ///
+    /// Generated variables win, since the document's pages depend on them;
+    /// each overridden `--vars` entry is reported in `warnings`.
+    #[cfg(feature = "unverified-pages")]
+    fn document_variables(&self, generated: Vec<DocVariable>, warnings: &mut Vec<String>) -> Vec<DocVariable> {
+        let mut variables: Vec<DocVariable> = Vec::with_capacity(self.options.variables.len() + generated.len());
+        for variable in &self.options.variables {
+            if generated.iter().any(|g| g.name.eq_ignore_ascii_case(&variable.name)) {
+                warnings.push(format!("variable {} is replaced by one this document defines", variable.name));
+            } else {
+                variables.push(variable.clone());
+            }
+        }
+        variables.extend(generated);
+        variables
     }
 
     /// Extract a script from .tns format
@@ -214,6 +671,28 @@ fn pad_to_8_bytes(mut data: Vec<u8>) -> Vec<u8> {
     data
 }
 
+/// Compress, pad and encrypt a problem XML document
+///
+/// Returns the bytes stored in the archive: the TI encrypted header
+/// followed by the encrypted deflate stream.
+fn encrypt_problem_xml(problem_xml: &[u8]) -> Result<Vec<u8>, ConversionError> {
+    // 1. Compress the XML
+    let compressed = compression::compress_xml(problem_xml)?;
+
+    // 2. Pad to 8-byte boundary for DES
+    let mut padded = pad_to_8_bytes(compressed);
+
+    // 3. Encrypt with DES
+    des::encrypt_document(&mut padded)?;
+
+    // 4. Add TI encrypted header
+    let mut problem_data = Vec::new();
+    problem_data.extend_from_slice(xml::get_ti_encrypted_header());
+    problem_data.extend_from_slice(&padded);
+
+    Ok(problem_data)
+}
+
 /// Create a .tns archive with Document.xml and Problem1.xml
 ///
 /// Uses the custom TNS writer that generates proper TI-Nspire format with:
@@ -225,38 +704,42 @@ fn create_tns_archive(
     document_xml: &[u8],
     problem_xml: &[u8],
     _problem_name: &str,
+    has_bitmap: bool,
 ) -> Result<(), ConversionError> {
     let entries = vec![
         TnsFileEntry::new_ti_encrypted("Document.xml", document_xml.to_vec()),
         TnsFileEntry::new_ti_encrypted("Problem1.xml", problem_xml.to_vec()),
     ];
 
-    tns_writer::write_tns_file(output_path, entries, false)
+    tns_writer::write_tns_file(output_path, entries, has_bitmap)
         .map_err(|e| ConversionError::Zip(format!("Failed to write TNS file: {}", e)))
 }
 
-/// Create a .tns archive with Document.xml, Problem1.xml, and a Python file
+/// Create a .tns archive with Document.xml, Problem1.xml, and the Python files
 fn create_tns_archive_with_python(
     output_path: &Path,
     document_xml: &[u8],
     problem_xml: &[u8],
-    python_filename: &str,
-    python_content: &[u8],
+    modules: &[PythonModule],
 ) -> Result<(), ConversionError> {
-    // Compress Python content using deflate
-    let compressed_python = compression::compress_xml(python_content)?;
-    let python_crc = crc32fast::hash(python_content);
-
-    let entries = vec![
+    let mut entries = vec![
         TnsFileEntry::new_ti_encrypted("Document.xml", document_xml.to_vec()),
         TnsFileEntry::new_ti_encrypted("Problem1.xml", problem_xml.to_vec()),
-        TnsFileEntry::new_deflated(
-            python_filename,
+    ];
+
+    for module in modules {
+        // Compress Python content using deflate
+        let python_content = module.source.as_bytes();
+        let compressed_python = compression::compress_xml(python_content)?;
+        let python_crc = crc32fast::hash(python_content);
+
+        entries.push(TnsFileEntry::new_deflated(
+            &module.filename,
             compressed_python,
             python_content.len() as u32,
             python_crc,
-        ),
-    ];
+        ));
+    }
 
     tns_writer::write_tns_file(output_path, entries, false)
         .map_err(|e| ConversionError::Zip(format!("Failed to write TNS file: {}", e)))
@@ -267,6 +750,23 @@ mod tests {
     use super::*;
     use std::fs;
 
+    /// Convert into a temporary file and return the report and the archive
+    ///
+    /// Checks the TI header magic and removes the file again.
+    fn convert_to_bytes(
+        file_name: &str,
+        convert: impl FnOnce(&Path) -> Result<ConversionReport, ConversionError>,
+    ) -> (ConversionReport, Vec<u8>) {
+        let output_path = std::env::temp_dir().join(file_name);
+        let result = convert(&output_path);
+        let bytes = fs::read(&output_path);
+        let _ = fs::remove_file(&output_path);
+
+        let bytes = bytes.unwrap();
+        assert_eq!(&bytes[0..6], b"*TIMLP", "Should start with TI magic");
+        (result.unwrap(), bytes)
+    }
+
     #[test]
     fn test_pad_to_8_bytes() {
         let data = vec![1, 2, 3];
@@ -414,4 +914,246 @@ Superscripts: x^2 + y^2 = z^2";
         // Clean up
         let _ = fs::remove_file(output_path);
     }
+
+    #[test]
+    fn test_latex_problems() {
+        let temp_dir = std::env::temp_dir();
+        let output_path = temp_dir.join("test_latex_problems.tns");
+        let text = "Area: $\\pi r^2$\nBroken: $\\frac{1}{2$";
+
+        let report = Converter::new().convert_text_to_tns(text, &output_path, "").unwrap();
+        assert_eq!(report.warnings, vec!["line 2, column 18: unclosed group"]);
+        let _ = fs::remove_file(&output_path);
+
+        let converter = Converter::with_options(ConversionOptions { strict: true, ..Default::default() });
+        let result = converter.convert_text_to_tns(text, &output_path, "");
+        assert!(matches!(result, Err(ConversionError::Latex(problems)) if problems.len() == 1));
+        assert!(!output_path.exists());
+    }
+
+    #[test]
+    fn test_convert_text_to_notes_tns() {
+        let options = ConversionOptions { notes_app: true, ..Default::default() };
+        let converter = Converter::with_options(options);
+
+        let text = "**Notes**\nArea: $\\pi r^2$\nSum: $\\sum_i i$";
+        let (report, _) = convert_to_bytes("test_notes.tns", |path| converter.convert_text_to_tns(text, path, ""));
+        assert_eq!(report.warnings.len(), 1, "Only the \\sum box should fall back");
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_convert_csv_to_tns() {
+        let options = ConversionOptions { csv_delimiter: Some(';'), ..Default::default() };
+        let converter = Converter::with_options(options);
+
+        let csv_text = "t;Distance (m)\n0;0\n1;4.9\n2;\n";
+        let (report, _) = convert_to_bytes("test_csv.tns", |path| converter.convert_csv_to_tns(csv_text, path, ""));
+        assert_eq!(report.warnings.len(), 1, "Header should be renamed");
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_convert_csv_unterminated_quote() {
+        let converter = Converter::new();
+        let output_path = std::env::temp_dir().join("test_csv_error.tns");
+        let result = converter.convert_csv_to_tns("a\n\"1\n", &output_path, "");
+        assert!(matches!(result, Err(ConversionError::Csv(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_convert_graph_spec_to_tns() {
+        let converter = Converter::new();
+        let spec = "f1(x) = a*x^2\nslider a = 1 min=-2 max=2 step=0.1\n";
+        convert_to_bytes("test_graph.tns", |path| converter.convert_graph_spec_to_tns(spec, SpecFormat::Text, path, ""));
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_convert_tibasic_to_tns() {
+        let converter = Converter::new();
+        let source = "Define LibPub sq(x)=Func\nReturn x^2\nEndFunc\nDefine hi()=\nPrgm\nDisp \"hi\"\nEndPrgm\n";
+        convert_to_bytes("test_tibasic.tns", |path| converter.convert_tibasic_to_tns(source, path, ""));
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_document_variables() {
+        use super::super::variables::VarValue;
+
+        let options = ConversionOptions {
+            variables: vec![
+                DocVariable::new("g", VarValue::Number(9.81)),
+                DocVariable::new("a", VarValue::Number(5.0)),
+            ],
+            ..Default::default()
+        };
+        let converter = Converter::with_options(options);
+        let mut warnings = Vec::new();
+
+        let generated = vec![DocVariable::new("A", VarValue::Number(1.0))];
+        let variables = converter.document_variables(generated, &mut warnings);
+        let names: Vec<_> = variables.iter().map(|v| v.name.as_str()).collect();
+        assert_eq!(names, vec!["g", "A"]);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_convert_plain_lua_keeps_lua_header() {
+        // Without variables or resources the archive must not change
+        let script = "print(\"]]>\")";
+        let (_, bytes) = convert_to_bytes("test_lua_plain.tns", |path| {
+            Converter::new().convert_lua_to_tns(script, path, "")
+        });
+
+        let problem = encrypt_problem_xml(&xml::wrap_lua_script(script, "").unwrap()).unwrap();
+        let (_, expected) = convert_to_bytes("test_lua_header.tns", |path| {
+            create_tns_archive(path, xml::create_default_document_xml(), &problem, "Problem1.xml", false)
+                .map(|_| ConversionReport::default())
+        });
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_convert_lua_with_variables() {
+        use super::super::variables::VarValue;
+
+        let options = ConversionOptions {
+            variables: vec![DocVariable::new("g", VarValue::Number(9.81))],
+            ..Default::default()
+        };
+        let converter = Converter::with_options(options);
+
+        let (report, _) = convert_to_bytes("test_lua_vars.tns", |path| {
+            converter.convert_lua_to_tns("print(var.recall(\"g\"))", path, "")
+        });
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_convert_lua_with_resources() {
+        let options = ConversionOptions {
+            resources: vec![ResourceFile { name: "dot".to_string(), data: b"P3 1 1 255\n0 0 0\n".to_vec() }],
+            ..Default::default()
+        };
+        let converter = Converter::with_options(options);
+
+        let (_, bytes) = convert_to_bytes("test_lua_resources.tns", |path| {
+            converter.convert_lua_to_tns("local img = image.new(_R.IMG.dot)", path, "")
+        });
+        assert_eq!(&bytes[0..10], b"*TIMLP0700", "Should use the bitmap version");
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_resource_errors() {
+        let output_path = std::env::temp_dir().join("test_resource_errors.tns");
+        let resource = |name: &str, data: &[u8]| ResourceFile { name: name.to_string(), data: data.to_vec() };
+
+        let bad_name = ConversionOptions { resources: vec![resource("a-b", b"P3 1 1 1\n0 0 0")], ..Default::default() };
+        let result = Converter::with_options(bad_name).convert_lua_to_tns("", &output_path, "");
+        assert!(matches!(result, Err(ConversionError::InvalidInput(_))));
+
+        let bad_image = ConversionOptions { resources: vec![resource("a", b"nope")], ..Default::default() };
+        let result = Converter::with_options(bad_image.clone()).convert_lua_to_tns("", &output_path, "");
+        assert!(matches!(result, Err(ConversionError::Image(_))));
+
+        // Other document types ignore resources with a warning
+        let report = Converter::with_options(bad_image).convert_csv_to_tns("a\n1\n", &output_path, "").unwrap();
+        assert_eq!(report.warnings.len(), 1);
+
+        // Clean up
+        let _ = fs::remove_file(output_path);
+    }
+
+    #[test]
+    fn test_convert_python_project_to_tns() {
+        let converter = Converter::new();
+        let modules = vec![
+            PythonModule::new("main.py", "from shapes import area\nprint(area(2))"),
+            PythonModule::new("shapes.py", "def area(r):\n    return 3.14*r*r"),
+        ];
+        let (_, bytes) = convert_to_bytes("test_python_project.tns", |path| {
+            converter.convert_python_project_to_tns(&modules, path, "")
+        });
+
+        // Both modules are stored under their own names
+        let contains = |name: &[u8]| bytes.windows(name.len()).any(|w| w == name);
+        assert!(contains(b"main.py"));
+        assert!(contains(b"shapes.py"));
+
+        let output_path = std::env::temp_dir().join("test_python_empty.tns");
+        let result = converter.convert_python_project_to_tns(&[], &output_path, "");
+        assert!(matches!(result, Err(ConversionError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_python_filenames() {
+        let output_path = std::env::temp_dir().join("test_python_names.tns");
+        let modules = vec![
+            PythonModule::new("my script (2).py", "import my_script_2"),
+            PythonModule::new("my_script_2.py", ""),
+        ];
+
+        let result = Converter::new().convert_python_project_to_tns(&modules, &output_path, "");
+        assert!(matches!(result, Err(ConversionError::InvalidInput(_))));
+
+        let options = ConversionOptions { sanitize_filenames: true, ..Default::default() };
+        let converter = Converter::with_options(options);
+        let mut warnings = Vec::new();
+        let checked = converter.python_filenames(&modules, &mut warnings).unwrap();
+        let names: Vec<_> = checked.iter().map(|m| m.filename.as_str()).collect();
+        assert_eq!(names, vec!["my_script_2_2.py", "my_script_2.py"]);
+        assert_eq!(warnings, vec!["Python file \"my script (2).py\" stored as my_script_2_2.py"]);
+
+        let (_, bytes) = convert_to_bytes("test_python_names.tns", |path| {
+            converter.convert_python_project_to_tns(&modules, path, "")
+        });
+        assert!(bytes.windows(14).any(|w| w == b"my_script_2.py"));
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_convert_python_with_shell() {
+        let modules = [PythonModule::new("main.py", "print('hi')")];
+        let editor = || Widget::PythonEditor { filename: "main.py".to_string() };
+        let shell = || Widget::PythonShell { filename: "main.py".to_string() };
+
+        for (python_shell, widgets) in [
+            (ShellPage::Last, vec![editor(), shell()]),
+            (ShellPage::First, vec![shell(), editor()]),
+        ] {
+            let options = ConversionOptions { python_shell, ..Default::default() };
+            let converter = Converter::with_options(options);
+            let (report, bytes) = convert_to_bytes("test_python_shell.tns", |path| {
+                converter.convert_python_to_tns("print('hi')", "main.py", path, "")
+            });
+            assert!(report.warnings.is_empty());
+
+            // Same archive as one built from the pages in the expected order
+            let problem = encrypt_problem_xml(&xml::build_problem(&widgets).unwrap()).unwrap();
+            let (_, expected) = convert_to_bytes("test_python_shell_pages.tns", |path| {
+                create_tns_archive_with_python(path, xml::create_default_document_xml(), &problem, &modules)
+                    .map(|_| ConversionReport::default())
+            });
+            assert_eq!(bytes, expected);
+        }
+    }
+
+    #[test]
+    fn test_load_source() {
+        let options = ConversionOptions { tab_width: Some(2), ..Default::default() };
+        let converter = Converter::with_options(options);
+
+        let python = converter.load_source(b"if x:\r\n\tpass\r\n", true).unwrap();
+        assert_eq!(python.text, "if x:\n  pass\n");
+        assert_eq!(python.changes.len(), 2);
+
+        // Tabs are only expanded in Python sources
+        let lua = converter.load_source(b"\tx = 1", false).unwrap();
+        assert_eq!(lua.text, "\tx = 1");
+    }
 }
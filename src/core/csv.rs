@@ -0,0 +1,224 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! CSV input for Lists & Spreadsheet pages
+//!
+//! The first row holds column headers, which become list names. Remaining
+//! cells become list elements: numbers stay numbers, empty cells become
+//! void elements and anything else is stored as a string. Quoted cells are
+//! always strings, so `"007"` keeps its leading zeros.
+
+use thiserror::Error;
+
+use super::variables::{self, DocVariable, VarValue};
+
+/// Errors that can occur while reading CSV
+#[derive(Debug, Error)]
+pub enum CsvError {
+    #[error("Unterminated quoted field starting on line {0}")]
+    UnterminatedQuote(usize),
+    #[error("CSV input has no header row")]
+    Empty,
+}
+
+/// A field of a CSV row
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Field {
+    pub text: String,
+    /// Whether the field was written in quotes
+    pub quoted: bool,
+}
+
+impl PartialEq<&str> for Field {
+    fn eq(&self, other: &&str) -> bool {
+        self.text == *other
+    }
+}
+
+/// Split CSV text into rows of fields
+///
+/// Follows RFC 4180: fields may be quoted, quotes inside quoted fields are
+/// doubled, and quoted fields may contain the delimiter and line breaks.
+/// Blank lines are skipped.
+pub fn parse_csv(text: &str, delimiter: char) -> Result<Vec<Vec<Field>>, CsvError> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = Field::default();
+    let mut in_quotes = false;
+    let mut quote_line = 0;
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.text.push('"');
+                }
+                '"' => in_quotes = false,
+                _ => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.text.push(c);
+                }
+            }
+            continue;
+        }
+
+        match c {
+            '"' if field.text.is_empty() => {
+                in_quotes = true;
+                field.quoted = true;
+                quote_line = line;
+            }
+            '\r' => {}
+            '\n' => {
+                line += 1;
+                row.push(std::mem::take(&mut field));
+                if row.len() > 1 || row[0] != Field::default() {
+                    rows.push(std::mem::take(&mut row));
+                } else {
+                    row.clear();
+                }
+            }
+            _ if c == delimiter => row.push(std::mem::take(&mut field)),
+            _ => field.text.push(c),
+        }
+    }
+
+    if in_quotes {
+        return Err(CsvError::UnterminatedQuote(quote_line));
+    }
+    if field != Field::default() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+/// Turn parsed CSV rows into one list variable per column
+///
+/// Headers are sanitized into valid, unique variable names; every rename is
+/// described in `warnings`.
+pub fn rows_to_lists(
+    rows: &[Vec<Field>],
+    warnings: &mut Vec<String>,
+) -> Result<Vec<DocVariable>, CsvError> {
+    let (header, data) = rows.split_first().ok_or(CsvError::Empty)?;
+    let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let mut lists: Vec<DocVariable> = Vec::with_capacity(width);
+
+    for column in 0..width {
+        let title = header.get(column).map_or("", |field| field.text.as_str());
+        let mut name = if variables::is_valid_name(title) {
+            title.to_string()
+        } else {
+            variables::sanitize_name(title)
+        };
+
+        // Keep names unique, shortening the base name to make room
+        let base = name.clone();
+        let mut suffix = 2;
+        while lists.iter().any(|l| l.name.eq_ignore_ascii_case(&name)) {
+            let tail = suffix.to_string();
+            let keep = base.len().min(variables::MAX_NAME_LEN - tail.len());
+            name = format!("{}{}", &base[..keep], tail);
+            suffix += 1;
+        }
+
+        if name != title {
+            warnings.push(format!("column {} header \"{}\" stored as list {}", column + 1, title, name));
+        }
+
+        let values = data
+            .iter()
+            .map(|row| row.get(column).map_or(VarValue::Void, parse_cell))
+            .collect();
+        lists.push(DocVariable::new(&name, VarValue::List(values)));
+    }
+
+    Ok(lists)
+}
+
+fn parse_cell(cell: &Field) -> VarValue {
+    let trimmed = cell.text.trim();
+    if cell.quoted {
+        return VarValue::Text(cell.text.clone());
+    }
+    if trimmed.is_empty() {
+        return VarValue::Void;
+    }
+    match trimmed.parse::<f64>() {
+        Ok(n) if n.is_finite() => VarValue::Number(n),
+        _ => VarValue::Text(cell.text.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_simple() {
+        let rows = parse_csv("a,b\n1,2\r\n3,4\n", ',').unwrap();
+        assert_eq!(rows, vec![vec!["a", "b"], vec!["1", "2"], vec!["3", "4"]]);
+    }
+
+    #[test]
+    fn test_parse_quoted_and_empty() {
+        let rows = parse_csv("name;note\n\"Smith; J\";\"say \"\"hi\"\"\"\n;\n", ';').unwrap();
+        assert_eq!(rows[1], vec!["Smith; J", "say \"hi\""]);
+        assert_eq!(rows[2], vec!["", ""]);
+    }
+
+    #[test]
+    fn test_parse_multiline_quoted() {
+        let rows = parse_csv("a\n\"x\ny\"\n", ',').unwrap();
+        assert_eq!(rows[1], vec!["x\ny"]);
+    }
+
+    #[test]
+    fn test_parse_unterminated_quote() {
+        let result = parse_csv("a\n\"oops\n", ',');
+        assert!(matches!(result, Err(CsvError::UnterminatedQuote(2))));
+    }
+
+    #[test]
+    fn test_rows_to_lists() {
+        let rows = parse_csv("time,Temp (C),label\n0,20.5,start\n1,,\n2,22,end\n", ',').unwrap();
+        let mut warnings = Vec::new();
+        let lists = rows_to_lists(&rows, &mut warnings).unwrap();
+
+        assert_eq!(lists.len(), 3);
+        assert_eq!(lists[0].name, "time");
+        assert_eq!(lists[0].value.to_string(), "{0,1,2}");
+        assert_eq!(lists[1].name, "temp_c");
+        assert_eq!(lists[1].value.to_string(), "{20.5,_,22}");
+        assert_eq!(lists[2].value.to_string(), "{\"start\",_,\"end\"}");
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_quoted_cells_are_text() {
+        let rows = parse_csv("id,n\n\"007\",\"1e3\"\n\"\",8\n", ',').unwrap();
+        let lists = rows_to_lists(&rows, &mut Vec::new()).unwrap();
+        assert_eq!(lists[0].value.to_string(), "{\"007\",\"\"}");
+        assert_eq!(lists[1].value.to_string(), "{\"1e3\",8}");
+    }
+
+    #[test]
+    fn test_duplicate_headers() {
+        let rows = parse_csv("x,x,\n1,2,3\n", ',').unwrap();
+        let lists = rows_to_lists(&rows, &mut Vec::new()).unwrap();
+        let names: Vec<_> = lists.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["x", "x2", "v"]);
+    }
+
+    #[test]
+    fn test_empty_input() {
+        assert!(matches!(rows_to_lists(&[], &mut Vec::new()), Err(CsvError::Empty)));
+    }
+}
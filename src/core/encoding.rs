@@ -0,0 +1,286 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! Input normalization for source files
+//!
+//! Editors save files in many shapes: with a byte order mark, as UTF-16,
+//! in a legacy 8-bit code page or with Windows line endings. Everything is
+//! turned into BOM-free UTF-8 with `\n` line endings before conversion, and
+//! every change is described so the CLI can report it.
+
+use thiserror::Error;
+
+/// Errors that can occur while decoding input
+#[derive(Debug, Error)]
+pub enum EncodingError {
+    #[error("Invalid {0} text")]
+    InvalidUtf16(&'static str),
+}
+
+/// Source text after normalization
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Normalized {
+    pub text: String,
+    /// What was changed, e.g. "converted 3 CRLF line endings to LF"
+    pub changes: Vec<String>,
+}
+
+/// Decode and normalize a source file
+///
+/// With `tab_width` set, tabs in leading indentation are expanded to spaces
+/// at that tab stop, as the TI Python editor only indents with spaces.
+pub fn normalize_source(bytes: &[u8], tab_width: Option<usize>) -> Result<Normalized, EncodingError> {
+    let mut changes = Vec::new();
+    let text = decode(bytes, &mut changes)?;
+    let mut text = normalize_line_endings(&text, &mut changes);
+    if let Some(width) = tab_width {
+        text = expand_tabs(&text, width, &mut changes);
+    }
+    Ok(Normalized { text, changes })
+}
+
+/// Decode bytes to text, detecting BOMs, UTF-16 and Windows-1252
+fn decode(bytes: &[u8], changes: &mut Vec<String>) -> Result<String, EncodingError> {
+    if let Some(rest) = bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        changes.push("removed UTF-8 byte order mark".to_string());
+        return Ok(decode_utf8_or_legacy(rest, changes));
+    }
+    if let Some(rest) = bytes.strip_prefix(b"\xFF\xFE") {
+        changes.push("decoded from UTF-16LE".to_string());
+        return decode_utf16(rest, false);
+    }
+    if let Some(rest) = bytes.strip_prefix(b"\xFE\xFF") {
+        changes.push("decoded from UTF-16BE".to_string());
+        return decode_utf16(rest, true);
+    }
+
+    // UTF-16 without a BOM: ASCII text has a zero in every other byte
+    if bytes.len() >= 4 && bytes.len().is_multiple_of(2) {
+        let pairs = bytes.len() / 2;
+        let zeros_at = |offset: usize| bytes.iter().skip(offset).step_by(2).filter(|&&b| b == 0).count();
+        if zeros_at(1) * 2 > pairs && zeros_at(0) == 0 {
+            changes.push("decoded from UTF-16LE (no byte order mark)".to_string());
+            return decode_utf16(bytes, false);
+        }
+        if zeros_at(0) * 2 > pairs && zeros_at(1) == 0 {
+            changes.push("decoded from UTF-16BE (no byte order mark)".to_string());
+            return decode_utf16(bytes, true);
+        }
+    }
+
+    Ok(decode_utf8_or_legacy(bytes, changes))
+}
+
+fn decode_utf8_or_legacy(bytes: &[u8], changes: &mut Vec<String>) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => {
+            changes.push("decoded from Windows-1252 (input was not valid UTF-8)".to_string());
+            bytes.iter().map(|&b| windows_1252_char(b)).collect()
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], big_endian: bool) -> Result<String, EncodingError> {
+    let name = if big_endian { "UTF-16BE" } else { "UTF-16LE" };
+    if !bytes.len().is_multiple_of(2) {
+        return Err(EncodingError::InvalidUtf16(name));
+    }
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|p| if big_endian { u16::from_be_bytes([p[0], p[1]]) } else { u16::from_le_bytes([p[0], p[1]]) })
+        .collect();
+    let text = String::from_utf16(&units).map_err(|_| EncodingError::InvalidUtf16(name))?;
+    // A BOM inside the text (e.g. after a BOM-less detection) is dropped too
+    Ok(text.strip_prefix('\u{FEFF}').map(str::to_string).unwrap_or(text))
+}
+
+/// Map a Windows-1252 byte to its character
+///
+/// Latin-1 with typographic characters in 0x80-0x9F. The five undefined
+/// bytes map to the matching C1 control, as browsers do.
+fn windows_1252_char(byte: u8) -> char {
+    const HIGH: [u16; 32] = [
+        0x20AC, 0x0081, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021,
+        0x02C6, 0x2030, 0x0160, 0x2039, 0x0152, 0x008D, 0x017D, 0x008F,
+        0x0090, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014,
+        0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0x009D, 0x017E, 0x0178,
+    ];
+    match byte {
+        0x80..=0x9F => char::from_u32(u32::from(HIGH[usize::from(byte - 0x80)])).unwrap_or('\u{FFFD}'),
+        _ => char::from(byte),
+    }
+}
+
+/// Turn CRLF and lone CR line endings into LF
+fn normalize_line_endings(text: &str, changes: &mut Vec<String>) -> String {
+    let crlf = text.matches("\r\n").count();
+    let lone_cr = text.matches('\r').count() - crlf;
+    if crlf == 0 && lone_cr == 0 {
+        return text.to_string();
+    }
+
+    if crlf > 0 {
+        changes.push(format!("converted {} CRLF line ending(s) to LF", crlf));
+    }
+    if lone_cr > 0 {
+        changes.push(format!("converted {} CR line ending(s) to LF", lone_cr));
+    }
+    text.replace("\r\n", "\n").replace('\r', "\n")
+}
+
+/// Expand tabs in leading indentation to spaces at `width` tab stops
+///
+/// Lines that start inside a triple-quoted string are part of the string's
+/// value and keep their tabs.
+fn expand_tabs(text: &str, width: usize, changes: &mut Vec<String>) -> String {
+    let width = width.max(1);
+    let mut expanded_lines = 0;
+    let mut long_string = None;
+
+    let lines: Vec<String> = text
+        .split('\n')
+        .map(|line| {
+            let in_string = long_string.is_some();
+            long_string = open_long_string(line, long_string);
+            if in_string {
+                return line.to_string();
+            }
+
+            let indent_len = line.len() - line.trim_start_matches([' ', '\t']).len();
+            let (indent, rest) = line.split_at(indent_len);
+            if !indent.contains('\t') {
+                return line.to_string();
+            }
+
+            expanded_lines += 1;
+            let mut column = 0;
+            for c in indent.chars() {
+                column = if c == '\t' { (column / width + 1) * width } else { column + 1 };
+            }
+            format!("{}{}", " ".repeat(column), rest)
+        })
+        .collect();
+
+    if expanded_lines > 0 {
+        changes.push(format!(
+            "expanded tab indentation on {} line(s) to {}-space stops",
+            expanded_lines, width
+        ));
+    }
+    lines.join("\n")
+}
+
+/// The triple quote still open at the end of a Python line, given the one
+/// open at its start
+///
+/// Short strings and comments on the line are skipped, so quotes inside
+/// them don't open a long string.
+pub fn open_long_string(line: &str, mut open: Option<&'static str>) -> Option<&'static str> {
+    let mut rest = line;
+    loop {
+        if let Some(quote) = open {
+            let Some(end) = find_unescaped(rest, quote) else {
+                return open;
+            };
+            rest = &rest[end + quote.len()..];
+            open = None;
+            continue;
+        }
+
+        let start = rest.find(['#', '"', '\''])?;
+        rest = &rest[start..];
+        if rest.starts_with('#') {
+            return None;
+        }
+        if let Some(quote) = ["\"\"\"", "'''"].into_iter().find(|q| rest.starts_with(q)) {
+            rest = &rest[quote.len()..];
+            open = Some(quote);
+            continue;
+        }
+        // A short string ends on the same line
+        let quote = &rest[..1];
+        let end = find_unescaped(&rest[1..], quote)?;
+        rest = &rest[end + 2..];
+    }
+}
+
+/// Byte offset of the first `quote` in `text` that isn't escaped with `\`
+fn find_unescaped(text: &str, quote: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if text[i..].starts_with(quote) {
+            return Some(i);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_utf8_unchanged() {
+        let result = normalize_source("print('hé')\n".as_bytes(), None).unwrap();
+        assert_eq!(result.text, "print('hé')\n");
+        assert!(result.changes.is_empty());
+    }
+
+    #[test]
+    fn test_utf8_bom_and_crlf() {
+        let result = normalize_source(b"\xEF\xBB\xBFa\r\nb\rc", None).unwrap();
+        assert_eq!(result.text, "a\nb\nc");
+        assert_eq!(
+            result.changes,
+            vec![
+                "removed UTF-8 byte order mark",
+                "converted 1 CRLF line ending(s) to LF",
+                "converted 1 CR line ending(s) to LF",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_utf16() {
+        let le = normalize_source(b"\xFF\xFEh\x00\xE9\x00", None).unwrap();
+        assert_eq!(le.text, "hé");
+        assert_eq!(le.changes, vec!["decoded from UTF-16LE"]);
+
+        let be = normalize_source(b"\x00h\x00i", None).unwrap();
+        assert_eq!(be.text, "hi");
+        assert_eq!(be.changes, vec!["decoded from UTF-16BE (no byte order mark)"]);
+
+        assert!(normalize_source(b"\xFF\xFE\x00", None).is_err());
+    }
+
+    #[test]
+    fn test_windows_1252() {
+        let result = normalize_source(b"Gr\xF6\xDFe \x80 \x93ok\x94", None).unwrap();
+        assert_eq!(result.text, "Größe € “ok”");
+        assert_eq!(result.changes.len(), 1);
+    }
+
+    #[test]
+    fn test_expand_tabs() {
+        let source = "def f():\n\tif x:\n\t\treturn '\t'\n  \tpass\n";
+        let result = normalize_source(source.as_bytes(), Some(4)).unwrap();
+        assert_eq!(result.text, "def f():\n    if x:\n        return '\t'\n    pass\n");
+        assert_eq!(result.changes, vec!["expanded tab indentation on 3 line(s) to 4-space stops"]);
+
+        let untouched = normalize_source(source.as_bytes(), None).unwrap();
+        assert_eq!(untouched.text, source);
+    }
+
+    #[test]
+    fn test_expand_tabs_keeps_long_strings() {
+        let source = "s = \"\"\"a\n\tb \\\"\"\"\n\tc\"\"\"\n\tx = '\"\"\"'  # '''\n\ty = 1\n";
+        let result = normalize_source(source.as_bytes(), Some(2)).unwrap();
+        assert_eq!(result.text, "s = \"\"\"a\n\tb \\\"\"\"\n\tc\"\"\"\n  x = '\"\"\"'  # '''\n  y = 1\n");
+        assert_eq!(result.changes, vec!["expanded tab indentation on 2 line(s) to 2-space stops"]);
+    }
+}
@@ -0,0 +1,170 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! Glyph coverage of the calculator fonts
+//!
+//! The math renderer prefers real symbols (∑, Δ, →) but falls back to ASCII
+//! (`SUM`, `Delta`, `->`) where a device font would show a blank box. A
+//! [`GlyphProfile`] says which characters a device can show.
+//!
+//! Characters are in one of two groups:
+//! - The *extended* glyphs ([`EXTENDED_GLYPHS`]) are shown only when the
+//!   profile lists them as available.
+//! - Everything else (lowercase Greek, ≤, ², ½, ...) is assumed to be
+//!   shown unless the profile lists it as missing.
+//!
+//! The built-in profiles are:
+//! - `clickpad` / `touchpad`: none of the extended glyphs. This is the
+//!   default.
+//! - `cx`: adds uppercase Greek, arrows, ∞, ° and ′.
+//! - `cx2`: adds big operators, ∂, ∇, quantifiers, set and logic symbols.
+//!
+//! A profile file in TOML starts from a built-in profile and adjusts it:
+//!
+//! ```toml
+//! base = "cx"
+//! available = "∑∫∈"
+//! missing = "⅛⅜⅝⅞"
+//! ```
+
+use std::collections::HashSet;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Errors that can occur while loading a glyph profile
+#[derive(Debug, Error)]
+pub enum GlyphProfileError {
+    #[error("TOML error: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("Unknown device '{0}' (expected clickpad, touchpad, cx or cx2)")]
+    UnknownDevice(String),
+}
+
+/// Symbols with an ASCII fallback that only some fonts have
+///
+/// The last row holds the pieces of tall brackets and the lines of 2D
+/// layouts, which no built-in profile lists as available.
+pub const EXTENDED_GLYPHS: &str = concat!(
+    "ΓΔΘΛΞΠΣΥΦΨΩ←→↑↓↔⇐⇒⇔↦∑∏∐∫∮∬∭⋃⋂⨁⨂∞∂∇∀∃∄∅¬∧∨∩∪∖∠△□⋄ℵℏℓ′°∈∉∋⊂⊃⊆⊇⊥∥",
+    "⎛⎜⎝⎞⎟⎠⎡⎢⎣⎤⎥⎦⎧⎨⎩⎪⎫⎬⎭│─",
+);
+
+// The CX and CX II lists are a best guess from what scripts are commonly
+// seen to display, not from a published font table. Glyph profile files
+// (see `GlyphProfile::from_toml`) override them where a device differs.
+
+/// Extended glyphs in the TI-Nspire CX font
+const CX_GLYPHS: &str = "ΓΔΘΛΞΠΣΥΦΨΩ←→↑↓↔⇐⇒⇔∞°′";
+
+/// Extended glyphs added by the CX II (OS 5) font
+const CX2_GLYPHS: &str = "∑∏∫∂∇∀∃∈∉⊂⊃⊆⊇∩∪∅¬∧∨∠";
+
+/// Which characters a device font can show
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GlyphProfile {
+    /// Extended glyphs the font has
+    available: HashSet<char>,
+    /// Other characters the font lacks
+    missing: HashSet<char>,
+}
+
+/// TOML layout of a profile file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlProfile {
+    base: Option<String>,
+    #[serde(default)]
+    available: String,
+    #[serde(default)]
+    missing: String,
+}
+
+impl GlyphProfile {
+    /// The built-in profile of a calculator model
+    pub fn device(name: &str) -> Result<Self, GlyphProfileError> {
+        let available = match name.to_lowercase().as_str() {
+            "clickpad" | "touchpad" => String::new(),
+            "cx" => CX_GLYPHS.to_string(),
+            "cx2" | "cx-ii" | "cxii" => format!("{}{}", CX_GLYPHS, CX2_GLYPHS),
+            _ => return Err(GlyphProfileError::UnknownDevice(name.to_string())),
+        };
+        Ok(Self { available: available.chars().collect(), missing: HashSet::new() })
+    }
+
+    /// Parse a profile file
+    pub fn from_toml(text: &str) -> Result<Self, GlyphProfileError> {
+        let toml_profile: TomlProfile = toml::from_str(text)?;
+        let mut profile = match &toml_profile.base {
+            Some(base) => Self::device(base)?,
+            None => Self::default(),
+        };
+        for c in toml_profile.available.chars().filter(|c| !c.is_whitespace()) {
+            profile.available.insert(c);
+            profile.missing.remove(&c);
+        }
+        for c in toml_profile.missing.chars().filter(|c| !c.is_whitespace()) {
+            profile.available.remove(&c);
+            profile.missing.insert(c);
+        }
+        Ok(profile)
+    }
+
+    /// Whether the font can show a character
+    pub fn shows(&self, c: char) -> bool {
+        if c.is_ascii() {
+            true
+        } else if EXTENDED_GLYPHS.contains(c) {
+            self.available.contains(&c)
+        } else {
+            !self.missing.contains(&c)
+        }
+    }
+
+    /// Whether the font can show every character of the text
+    pub fn shows_all(&self, text: &str) -> bool {
+        text.chars().all(|c| self.shows(c))
+    }
+
+    /// The glyph if the font has it, otherwise the fallback
+    pub fn pick(&self, glyph: char, fallback: char) -> char {
+        if self.shows(glyph) { glyph } else { fallback }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_profiles() {
+        let touchpad = GlyphProfile::device("touchpad").unwrap();
+        assert_eq!(touchpad, GlyphProfile::default());
+        assert!(touchpad.shows('α'));
+        assert!(touchpad.shows('²'));
+        assert!(!touchpad.shows('Δ'));
+
+        let cx = GlyphProfile::device("CX").unwrap();
+        assert!(cx.shows_all("Δ→∞"));
+        assert!(!cx.shows('∑'));
+
+        let cx2 = GlyphProfile::device("cx2").unwrap();
+        assert!(cx2.shows_all("Δ∑∫∈∀"));
+        assert!(matches!(GlyphProfile::device("ti84"), Err(GlyphProfileError::UnknownDevice(_))));
+    }
+
+    #[test]
+    fn test_profile_file() {
+        let profile = GlyphProfile::from_toml("base = \"cx\"\navailable = \"∑ ∫\"\nmissing = \"⅛ Δ\"\n").unwrap();
+        assert!(profile.shows_all("∑∫→"));
+        assert!(!profile.shows('⅛'));
+        assert!(!profile.shows('Δ'));
+        // Bracket pieces are only drawn where a profile opts in
+        assert_eq!(profile.pick('⎛', '/'), '/');
+
+        let profile = GlyphProfile::from_toml("available = \"⎛\"\nmissing = \"≤\"").unwrap();
+        assert_eq!(profile.pick('⎛', '/'), '⎛');
+        assert_eq!(profile.pick('≤', '<'), '<');
+        assert!(GlyphProfile::from_toml("colour = true").is_err());
+    }
+}
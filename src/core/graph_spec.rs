@@ -0,0 +1,398 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! Function specifications for Graphs pages
+//!
+//! A spec lists the functions to plot, the viewing window and optional
+//! sliders. It can be written as plain text:
+//!
+//! ```text
+//! # Damped oscillation
+//! f1(x) = a*sin(x)
+//! f2(x) = x^2/10
+//! xmin = -10
+//! xmax = 10
+//! ymin = -5
+//! ymax = 5
+//! slider a = 1 min=0 max=5 step=0.5
+//! ```
+//!
+//! or as TOML, with the same function lines:
+//!
+//! ```toml
+//! functions = ["f1(x) = a*sin(x)", "f2(x) = x^2/10"]
+//!
+//! [window]
+//! xmin = -10
+//! xmax = 10
+//!
+//! [[sliders]]
+//! name = "a"
+//! value = 1
+//! max = 5
+//! ```
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::variables::{self, DocVariable, VarValue};
+
+/// Errors that can occur while reading a graph spec
+#[derive(Debug, Error)]
+pub enum GraphSpecError {
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("TOML error: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+/// Format of a graph spec file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecFormat {
+    Text,
+    Toml,
+}
+
+/// A function to plot, e.g. `f1(x) = x^2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphFunction {
+    pub name: String,
+    pub variable: String,
+    /// Right-hand side in calculator syntax
+    pub expr: String,
+}
+
+/// Graph window; defaults match the calculator's standard window
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GraphWindow {
+    pub xmin: f64,
+    pub xmax: f64,
+    pub ymin: f64,
+    pub ymax: f64,
+}
+
+impl Default for GraphWindow {
+    fn default() -> Self {
+        Self { xmin: -10.0, xmax: 10.0, ymin: -6.67, ymax: 6.67 }
+    }
+}
+
+/// A slider bound to a document variable
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Slider {
+    pub name: String,
+    pub value: f64,
+    #[serde(default)]
+    pub min: f64,
+    #[serde(default = "default_slider_max")]
+    pub max: f64,
+    #[serde(default = "default_slider_step")]
+    pub step: f64,
+}
+
+fn default_slider_max() -> f64 {
+    10.0
+}
+
+fn default_slider_step() -> f64 {
+    1.0
+}
+
+/// Contents of a Graphs page
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GraphSpec {
+    pub functions: Vec<GraphFunction>,
+    pub window: GraphWindow,
+    pub sliders: Vec<Slider>,
+}
+
+/// TOML layout of a spec file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlSpec {
+    #[serde(default)]
+    functions: Vec<String>,
+    #[serde(default)]
+    window: GraphWindow,
+    #[serde(default)]
+    sliders: Vec<Slider>,
+}
+
+impl GraphSpec {
+    /// Parse and validate a spec
+    pub fn parse(text: &str, format: SpecFormat) -> Result<Self, GraphSpecError> {
+        let spec = match format {
+            SpecFormat::Text => parse_text(text)?,
+            SpecFormat::Toml => parse_toml(text)?,
+        };
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    /// Slider values as document variables, so functions can refer to them
+    pub fn slider_variables(&self) -> Vec<DocVariable> {
+        self.sliders
+            .iter()
+            .map(|s| DocVariable::new(&s.name, VarValue::Number(s.value)))
+            .collect()
+    }
+
+    fn validate(&self) -> Result<(), GraphSpecError> {
+        if self.functions.is_empty() {
+            return Err(GraphSpecError::Invalid("Graph spec defines no functions".to_string()));
+        }
+
+        let w = &self.window;
+        if w.xmin >= w.xmax || w.ymin >= w.ymax {
+            return Err(GraphSpecError::Invalid(format!(
+                "Empty window: x {}..{}, y {}..{}",
+                w.xmin, w.xmax, w.ymin, w.ymax
+            )));
+        }
+
+        for slider in &self.sliders {
+            if !variables::is_valid_name(&slider.name) {
+                return Err(GraphSpecError::Invalid(format!("Invalid slider name '{}'", slider.name)));
+            }
+            if slider.min > slider.max || slider.value < slider.min || slider.value > slider.max {
+                return Err(GraphSpecError::Invalid(format!(
+                    "Slider {} value {} outside {}..{}",
+                    slider.name, slider.value, slider.min, slider.max
+                )));
+            }
+            if slider.step <= 0.0 {
+                return Err(GraphSpecError::Invalid(format!("Slider {} needs a positive step", slider.name)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_text(text: &str) -> Result<GraphSpec, GraphSpecError> {
+    let mut spec = GraphSpec::default();
+    let mut names = Vec::new();
+
+    for (index, raw) in text.lines().enumerate() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let syntax = |message: String| GraphSpecError::Syntax { line: index + 1, message };
+
+        if let Some(rest) = line.strip_prefix("slider ") {
+            let slider = parse_slider(rest).map_err(syntax)?;
+            add_name(&mut names, &slider.name).map_err(syntax)?;
+            spec.sliders.push(slider);
+            continue;
+        }
+
+        let (key, value) = line.split_once('=').ok_or_else(|| syntax("expected '='".to_string()))?;
+        let key = key.trim();
+        match key {
+            "xmin" | "xmax" | "ymin" | "ymax" => {
+                let value = parse_number(value).map_err(syntax)?;
+                match key {
+                    "xmin" => spec.window.xmin = value,
+                    "xmax" => spec.window.xmax = value,
+                    "ymin" => spec.window.ymin = value,
+                    _ => spec.window.ymax = value,
+                }
+            }
+            _ => {
+                let function = parse_function(line).map_err(syntax)?;
+                add_name(&mut names, &function.name).map_err(syntax)?;
+                spec.functions.push(function);
+            }
+        }
+    }
+
+    Ok(spec)
+}
+
+fn parse_toml(text: &str) -> Result<GraphSpec, GraphSpecError> {
+    let toml_spec: TomlSpec = toml::from_str(text)?;
+    let mut names = Vec::new();
+
+    let functions = toml_spec
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(i, definition)| {
+            parse_function(definition)
+                .and_then(|function| add_name(&mut names, &function.name).map(|_| function))
+                .map_err(|message| GraphSpecError::Invalid(format!("functions[{}]: {}", i, message)))
+        })
+        .collect::<Result<_, _>>()?;
+    for (i, slider) in toml_spec.sliders.iter().enumerate() {
+        add_name(&mut names, &slider.name)
+            .map_err(|message| GraphSpecError::Invalid(format!("sliders[{}]: {}", i, message)))?;
+    }
+
+    Ok(GraphSpec {
+        functions,
+        window: toml_spec.window,
+        sliders: toml_spec.sliders,
+    })
+}
+
+/// Record a function or slider name, which must not be taken yet
+///
+/// The calculator doesn't distinguish case, so `F1` and `f1` are the same.
+fn add_name(names: &mut Vec<String>, name: &str) -> Result<(), String> {
+    if names.iter().any(|n| n.eq_ignore_ascii_case(name)) {
+        return Err(format!("{} is already defined", name));
+    }
+    names.push(name.to_string());
+    Ok(())
+}
+
+/// Parse `name(var) = expr`
+fn parse_function(definition: &str) -> Result<GraphFunction, String> {
+    let (lhs, expr) = definition.split_once('=').ok_or("expected 'f1(x) = ...'")?;
+    let lhs = lhs.trim();
+    let expr = expr.trim();
+
+    let (name, variable) = lhs
+        .strip_suffix(')')
+        .and_then(|l| l.split_once('('))
+        .ok_or_else(|| format!("expected a function like f1(x), got '{}'", lhs))?;
+    let (name, variable) = (name.trim(), variable.trim());
+
+    if !variables::is_valid_name(name) {
+        return Err(format!("invalid function name '{}'", name));
+    }
+    if variable != "x" {
+        return Err(format!("{} must be a function of x, not '{}'", name, variable));
+    }
+    if expr.is_empty() {
+        return Err(format!("{} has no definition", name));
+    }
+
+    Ok(GraphFunction {
+        name: name.to_string(),
+        variable: variable.to_string(),
+        expr: expr.to_string(),
+    })
+}
+
+/// Parse `a = 1 min=0 max=5 step=0.5`
+fn parse_slider(text: &str) -> Result<Slider, String> {
+    let (name, rest) = text.split_once('=').ok_or("expected 'slider name = value'")?;
+    let mut parts = rest.split_whitespace();
+    let value = parse_number(parts.next().ok_or("slider needs a value")?)?;
+
+    let mut slider = Slider {
+        name: name.trim().to_string(),
+        value,
+        min: 0.0,
+        max: default_slider_max(),
+        step: default_slider_step(),
+    };
+
+    for part in parts {
+        let (key, value) = part.split_once('=').ok_or_else(|| format!("expected key=value, got '{}'", part))?;
+        let value = parse_number(value)?;
+        match key {
+            "min" => slider.min = value,
+            "max" => slider.max = value,
+            "step" => slider.step = value,
+            _ => return Err(format!("unknown slider setting '{}'", key)),
+        }
+    }
+
+    Ok(slider)
+}
+
+fn parse_number(text: &str) -> Result<f64, String> {
+    let text = text.trim();
+    text.parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite())
+        .ok_or_else(|| format!("'{}' is not a number", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT_SPEC: &str = "# comment\n\
+f1(x) = a*sin(x)\n\
+f2(x)=x^2\n\
+xmin = -5\n\
+xmax = 5\n\
+slider a = 1 min=0 max=5 step=0.5\n";
+
+    #[test]
+    fn test_parse_text() {
+        let spec = GraphSpec::parse(TEXT_SPEC, SpecFormat::Text).unwrap();
+        assert_eq!(spec.functions.len(), 2);
+        assert_eq!(spec.functions[0].name, "f1");
+        assert_eq!(spec.functions[0].expr, "a*sin(x)");
+        assert_eq!(spec.window.xmin, -5.0);
+        assert_eq!(spec.window.ymax, 6.67);
+        assert_eq!(spec.sliders[0], Slider { name: "a".to_string(), value: 1.0, min: 0.0, max: 5.0, step: 0.5 });
+        assert_eq!(spec.slider_variables(), vec![DocVariable::new("a", VarValue::Number(1.0))]);
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let toml = r#"
+functions = ["f1(x) = a*x"]
+
+[window]
+ymin = -1
+ymax = 1
+
+[[sliders]]
+name = "a"
+value = 2
+"#;
+        let spec = GraphSpec::parse(toml, SpecFormat::Toml).unwrap();
+        assert_eq!(spec.functions[0].expr, "a*x");
+        assert_eq!(spec.window.xmin, -10.0);
+        assert_eq!(spec.window.ymin, -1.0);
+        assert_eq!(spec.sliders[0].max, 10.0);
+    }
+
+    #[test]
+    fn test_syntax_error_has_line() {
+        let err = GraphSpec::parse("f1(x)=x\nnonsense\n", SpecFormat::Text).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: expected '='");
+
+        let err = GraphSpec::parse("f1(t)=t\n", SpecFormat::Text).unwrap_err();
+        assert!(err.to_string().contains("function of x"));
+    }
+
+    #[test]
+    fn test_duplicate_names() {
+        let err = GraphSpec::parse("f1(x)=x\nf2(x)=2x\nF1(x)=3x\n", SpecFormat::Text).unwrap_err();
+        assert_eq!(err.to_string(), "line 3: F1 is already defined");
+
+        let err = GraphSpec::parse("f1(x)=a*x\nslider a = 1\n\nslider a = 2\n", SpecFormat::Text).unwrap_err();
+        assert_eq!(err.to_string(), "line 4: a is already defined");
+
+        let err = GraphSpec::parse("slider f1 = 1\nf1(x)=x\n", SpecFormat::Text).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: f1 is already defined");
+
+        let toml = "functions = [\"f1(x)=x\", \"f1(x)=2x\"]";
+        let err = GraphSpec::parse(toml, SpecFormat::Toml).unwrap_err();
+        assert_eq!(err.to_string(), "functions[1]: f1 is already defined");
+
+        let toml = "functions = [\"f1(x)=a*x\"]\n[[sliders]]\nname = \"a\"\nvalue = 1\n[[sliders]]\nname = \"A\"\nvalue = 2\n";
+        let err = GraphSpec::parse(toml, SpecFormat::Toml).unwrap_err();
+        assert_eq!(err.to_string(), "sliders[1]: A is already defined");
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(GraphSpec::parse("xmin=1\n", SpecFormat::Text).is_err());
+        assert!(GraphSpec::parse("f1(x)=x\nxmin=5\nxmax=1\n", SpecFormat::Text).is_err());
+        assert!(GraphSpec::parse("f1(x)=x\nslider a = 20\n", SpecFormat::Text).is_err());
+        assert!(GraphSpec::parse("window = 3", SpecFormat::Toml).is_err());
+    }
+}
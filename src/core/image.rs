@@ -0,0 +1,330 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! Image resources for Lua script documents
+//!
+//! Decodes PNG, BMP and PPM files and encodes them in TI.Image format,
+//! which Lua loads with `image.new`. Script apps carry these images as
+//! named resources, read from Lua as `_R.IMG.name`.
+//!
+//! TI.Image layout (all fields little-endian):
+//! - 20-byte header: width (u32), height (u32), alignment (u8, 0),
+//!   flags (u8, 0), padding (u16, 0), bytes per row (u32, width * 2),
+//!   bits per pixel (u16, 16), planes per bit (u16, 1)
+//! - Pixels row by row, one u16 each: RGB555 with bit 15 set for opaque
+
+use thiserror::Error;
+
+/// Errors that can occur while reading an image
+#[derive(Debug, Error)]
+pub enum ImageError {
+    #[error("PNG error: {0}")]
+    Png(String),
+    #[error("BMP error: {0}")]
+    Bmp(String),
+    #[error("PPM error: {0}")]
+    Ppm(String),
+    #[error("Unsupported image format (expected PNG, BMP or PPM)")]
+    UnknownFormat,
+    #[error("Image {width}x{height} is empty or too large")]
+    BadSize { width: u32, height: u32 },
+}
+
+/// Largest width or height accepted; the handheld screen is 318x212
+const MAX_DIMENSION: u32 = 2048;
+
+/// An image file to embed, named as it is referred to from Lua
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Decoded image with 8-bit RGBA pixels, row by row from the top
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl RgbaImage {
+    fn new(width: u32, height: u32, pixels: Vec<[u8; 4]>) -> Result<Self, ImageError> {
+        if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+            return Err(ImageError::BadSize { width, height });
+        }
+        Ok(Self { width, height, pixels })
+    }
+
+    /// Encode in TI.Image format
+    pub fn to_ti_image(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(20 + self.pixels.len() * 2);
+        data.extend_from_slice(&self.width.to_le_bytes());
+        data.extend_from_slice(&self.height.to_le_bytes());
+        data.push(0); // alignment
+        data.push(0); // flags
+        data.extend_from_slice(&0u16.to_le_bytes());
+        data.extend_from_slice(&(self.width * 2).to_le_bytes());
+        data.extend_from_slice(&16u16.to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+
+        for &[r, g, b, a] in &self.pixels {
+            let opaque = if a >= 128 { 0x8000 } else { 0 };
+            let pixel = opaque | (u16::from(r >> 3) << 10) | (u16::from(g >> 3) << 5) | u16::from(b >> 3);
+            data.extend_from_slice(&pixel.to_le_bytes());
+        }
+        data
+    }
+}
+
+/// Lua's reserved words, which can't follow the `.` in `_R.IMG.name`
+const LUA_KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if", "in",
+    "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+/// Check that a resource name can be used as `_R.IMG.name` from Lua
+pub fn is_valid_resource_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !LUA_KEYWORDS.contains(&name)
+}
+
+/// Decode a PNG, BMP or PPM file, detected from its first bytes
+pub fn decode_image(data: &[u8]) -> Result<RgbaImage, ImageError> {
+    if data.starts_with(b"\x89PNG") {
+        decode_png(data)
+    } else if data.starts_with(b"BM") {
+        decode_bmp(data)
+    } else if data.starts_with(b"P3") || data.starts_with(b"P6") {
+        decode_ppm(data)
+    } else {
+        Err(ImageError::UnknownFormat)
+    }
+}
+
+/// Encode bytes as a Lua string body with `\ddd` escapes
+///
+/// This is the form TI's tools use for image data in Lua source, and keeps
+/// binary data safe inside XML.
+pub fn lua_escape(data: &[u8]) -> String {
+    let mut escaped = String::with_capacity(data.len() * 4);
+    for byte in data {
+        escaped.push_str(&format!("\\{:03}", byte));
+    }
+    escaped
+}
+
+fn decode_png(data: &[u8]) -> Result<RgbaImage, ImageError> {
+    let png_error = |e: png::DecodingError| ImageError::Png(e.to_string());
+
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().map_err(png_error)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(png_error)?;
+    let buffer = &buffer[..info.buffer_size()];
+
+    let pixels = match info.color_type {
+        png::ColorType::Rgba => buffer.chunks_exact(4).map(|p| [p[0], p[1], p[2], p[3]]).collect(),
+        png::ColorType::Rgb => buffer.chunks_exact(3).map(|p| [p[0], p[1], p[2], 255]).collect(),
+        png::ColorType::GrayscaleAlpha => buffer.chunks_exact(2).map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+        png::ColorType::Grayscale => buffer.iter().map(|&v| [v, v, v, 255]).collect(),
+        png::ColorType::Indexed => return Err(ImageError::Png("palette was not expanded".to_string())),
+    };
+
+    RgbaImage::new(info.width, info.height, pixels)
+}
+
+/// Decode an uncompressed 24- or 32-bit BMP
+fn decode_bmp(data: &[u8]) -> Result<RgbaImage, ImageError> {
+    let bmp_error = |message: &str| ImageError::Bmp(message.to_string());
+    let u16_at = |offset: usize| data.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at =
+        |offset: usize| data.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+    let truncated = || bmp_error("file is truncated");
+    let pixel_offset = u32_at(10).ok_or_else(truncated)? as usize;
+    let width = u32_at(18).ok_or_else(truncated)? as i32;
+    let height = u32_at(22).ok_or_else(truncated)? as i32;
+    let bits = u16_at(28).ok_or_else(truncated)?;
+    let compression = u32_at(30).ok_or_else(truncated)?;
+
+    // BI_RGB, or BI_BITFIELDS with the usual BGRA masks for 32-bit files
+    if (bits != 24 && bits != 32) || !(compression == 0 || (compression == 3 && bits == 32)) {
+        return Err(ImageError::Bmp(format!(
+            "only uncompressed 24- and 32-bit images are supported, got {} bits", bits
+        )));
+    }
+
+    // Positive heights are stored bottom-up
+    let bottom_up = height > 0;
+    let (width, height) = (width.unsigned_abs(), height.unsigned_abs());
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(ImageError::BadSize { width, height });
+    }
+    let bytes_per_pixel = usize::from(bits / 8);
+    let stride = (width as usize * bytes_per_pixel).div_ceil(4) * 4;
+
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for row in 0..height as usize {
+        let source_row = if bottom_up { height as usize - 1 - row } else { row };
+        let start = pixel_offset + source_row * stride;
+        let line = data
+            .get(start..start + width as usize * bytes_per_pixel)
+            .ok_or_else(truncated)?;
+        // 32-bit BI_RGB files usually leave alpha at zero, so only bitfields carry alpha
+        pixels.extend(line.chunks_exact(bytes_per_pixel).map(|p| {
+            let alpha = if bytes_per_pixel == 4 && compression == 3 { p[3] } else { 255 };
+            [p[2], p[1], p[0], alpha]
+        }));
+    }
+
+    RgbaImage::new(width, height, pixels)
+}
+
+/// Decode a binary (P6) or plain (P3) PPM with up to 8 bits per channel
+fn decode_ppm(data: &[u8]) -> Result<RgbaImage, ImageError> {
+    let ppm_error = |message: String| ImageError::Ppm(message);
+    let binary = data.starts_with(b"P6");
+
+    // Header: magic, width, height, maxval, separated by whitespace and comments
+    let mut pos = 2;
+    let mut header = [0u32; 3];
+    for value in header.iter_mut() {
+        loop {
+            match data.get(pos) {
+                Some(b'#') => {
+                    while data.get(pos).is_some_and(|&b| b != b'\n') {
+                        pos += 1;
+                    }
+                }
+                Some(b) if b.is_ascii_whitespace() => pos += 1,
+                _ => break,
+            }
+        }
+        let start = pos;
+        while data.get(pos).is_some_and(u8::is_ascii_digit) {
+            pos += 1;
+        }
+        *value = std::str::from_utf8(&data[start..pos])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| ppm_error("invalid header".to_string()))?;
+    }
+    let [width, height, max] = header;
+    if max == 0 || max > 255 {
+        return Err(ppm_error(format!("maximum value {} is not supported", max)));
+    }
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(ImageError::BadSize { width, height });
+    }
+
+    let count = (width * height * 3) as usize;
+    let samples: Vec<u32> = if binary {
+        // Exactly one whitespace byte separates the header from the pixels
+        let body = data.get(pos + 1..pos + 1 + count).ok_or_else(|| ppm_error("file is truncated".to_string()))?;
+        body.iter().map(|&b| u32::from(b)).collect()
+    } else {
+        let text = std::str::from_utf8(&data[pos..]).map_err(|_| ppm_error("invalid text".to_string()))?;
+        let samples = text
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or(""))
+            .flat_map(str::split_whitespace)
+            .take(count)
+            .map(|s| s.parse().map_err(|_| ppm_error(format!("invalid sample '{}'", s))))
+            .collect::<Result<Vec<u32>, _>>()?;
+        if samples.len() < count {
+            return Err(ppm_error("file is truncated".to_string()));
+        }
+        samples
+    };
+
+    let scale = |v: u32| (v.min(max) * 255 / max) as u8;
+    let pixels = samples.chunks_exact(3).map(|p| [scale(p[0]), scale(p[1]), scale(p[2]), 255]).collect();
+    RgbaImage::new(width, height, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ti_image_encoding() {
+        let image = RgbaImage::new(2, 1, vec![[255, 0, 0, 255], [0, 0, 255, 0]]).unwrap();
+        let data = image.to_ti_image();
+
+        assert_eq!(data.len(), 24);
+        assert_eq!(&data[0..4], &2u32.to_le_bytes());
+        assert_eq!(&data[4..8], &1u32.to_le_bytes());
+        assert_eq!(&data[12..16], &4u32.to_le_bytes());
+        assert_eq!(&data[16..20], &[16, 0, 1, 0]);
+        assert_eq!(&data[20..22], &0xFC00u16.to_le_bytes());
+        assert_eq!(&data[22..24], &0x001Fu16.to_le_bytes());
+    }
+
+    #[test]
+    fn test_decode_ppm() {
+        let plain = b"P3\n# red and white\n2 1\n255\n255 0 0  255 255 255\n";
+        let image = decode_image(plain).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.pixels, vec![[255, 0, 0, 255], [255, 255, 255, 255]]);
+
+        let binary = b"P6 1 1 15\n\x0f\x00\x0f";
+        assert_eq!(decode_image(binary).unwrap().pixels, vec![[255, 0, 255, 255]]);
+    }
+
+    #[test]
+    fn test_decode_bmp() {
+        // 2x2, 24-bit, bottom-up; rows padded to 8 bytes
+        let mut bmp = b"BM".to_vec();
+        bmp.extend_from_slice(&[0; 8]);
+        bmp.extend_from_slice(&54u32.to_le_bytes());
+        bmp.extend_from_slice(&40u32.to_le_bytes());
+        bmp.extend_from_slice(&2i32.to_le_bytes());
+        bmp.extend_from_slice(&2i32.to_le_bytes());
+        bmp.extend_from_slice(&1u16.to_le_bytes());
+        bmp.extend_from_slice(&24u16.to_le_bytes());
+        bmp.extend_from_slice(&[0; 24]);
+        bmp.extend_from_slice(&[255, 0, 0, 0, 255, 0, 0, 0]); // bottom: blue, green
+        bmp.extend_from_slice(&[0, 0, 255, 0, 0, 0, 0, 0]); // top: red, black
+
+        let image = decode_image(&bmp).unwrap();
+        assert_eq!(
+            image.pixels,
+            vec![[255, 0, 0, 255], [0, 0, 0, 255], [0, 0, 255, 255], [0, 255, 0, 255]]
+        );
+    }
+
+    #[test]
+    fn test_decode_png() {
+        let mut data = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut data, 1, 1);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[10, 20, 30, 40]).unwrap();
+        }
+        assert_eq!(decode_image(&data).unwrap().pixels, vec![[10, 20, 30, 40]]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(decode_image(b"GIF89a"), Err(ImageError::UnknownFormat)));
+        assert!(matches!(decode_image(b"P3 0 1 255\n"), Err(ImageError::BadSize { .. })));
+        assert!(matches!(decode_image(b"P6 2 2 255\n\x00"), Err(ImageError::Ppm(_))));
+    }
+
+    #[test]
+    fn test_resource_names_and_escape() {
+        assert!(is_valid_resource_name("sprite_1"));
+        assert!(!is_valid_resource_name("1sprite"));
+        assert!(!is_valid_resource_name("my-sprite"));
+        assert!(!is_valid_resource_name("end"));
+        assert!(!is_valid_resource_name("function"));
+        assert!(is_valid_resource_name("End"));
+        assert_eq!(lua_escape(&[0, 10, 255]), "\\000\\010\\255");
+    }
+}
@@ -0,0 +1,949 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! LaTeX math tokenizer and parser
+//!
+//! Turns LaTeX source into a small syntax tree that the math back ends
+//! (`math_render` for Unicode text) walk to produce output. The parser is
+//! forgiving: stray or missing braces and unknown commands never fail, they
+//! are kept in the tree so a back end can reproduce the source.
+//!
+//! Unlike TeX, spaces are kept as characters (also after control words),
+//! since the input is mostly prose with some math in it.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A lexical unit of LaTeX source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// `\name`, or `\` followed by a single non-letter (`\,`, `\{`, `\\`)
+    Command(String),
+    Char(char),
+    BeginGroup,
+    EndGroup,
+    Superscript,
+    Subscript,
+    /// `&`, the column separator in environments
+    AlignTab,
+}
+
+/// Split LaTeX source into tokens
+pub fn tokenize(input: &str) -> Vec<Token> {
+    tokenize_with_offsets(input).into_iter().map(|(_, token)| token).collect()
+}
+
+/// Split LaTeX source into tokens, each with the byte offset it starts at
+pub fn tokenize_with_offsets(input: &str) -> Vec<(usize, Token)> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        let token = match c {
+            '\\' => match chars.peek().map(|&(_, next)| next) {
+                Some(next) if next.is_ascii_alphabetic() => {
+                    let mut name = String::new();
+                    while let Some(&(_, letter)) = chars.peek().filter(|(_, c)| c.is_ascii_alphabetic()) {
+                        name.push(letter);
+                        chars.next();
+                    }
+                    Token::Command(name)
+                }
+                Some(next) => {
+                    chars.next();
+                    Token::Command(next.to_string())
+                }
+                None => Token::Char('\\'),
+            },
+            '{' => Token::BeginGroup,
+            '}' => Token::EndGroup,
+            '^' => Token::Superscript,
+            '_' => Token::Subscript,
+            '&' => Token::AlignTab,
+            _ => Token::Char(c),
+        };
+        tokens.push((offset, token));
+    }
+
+    tokens
+}
+
+/// A node of the math syntax tree
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// A literal character, including spaces and line breaks
+    Char(char),
+    /// `\name[optional]{arg}...`; arguments are groups or single atoms
+    Command {
+        name: String,
+        optional: Option<Vec<Node>>,
+        args: Vec<Node>,
+    },
+    /// `{...}`; `closed` is false when the input ended before the `}`
+    Group { children: Vec<Node>, closed: bool },
+    /// A base with a subscript and/or superscript
+    Script {
+        base: Option<Box<Node>>,
+        sub: Option<Box<Node>>,
+        sup: Option<Box<Node>>,
+    },
+    /// `\begin{name}...\end{name}`, split into rows of `&`-separated cells
+    Environment {
+        name: String,
+        rows: Vec<Vec<Vec<Node>>>,
+        closed: bool,
+    },
+}
+
+/// Number of arguments a command takes: (has optional `[...]`, required)
+///
+/// Commands not listed take no arguments, so a following `{...}` is parsed
+/// as an ordinary group.
+pub fn command_arity(name: &str) -> (bool, usize) {
+    match name {
+        "frac" | "dfrac" | "tfrac" | "cfrac" | "binom" | "dbinom" | "tbinom" => (false, 2),
+        "sqrt" => (true, 1),
+        "text" | "textrm" | "textit" | "textbf" | "textsf" | "texttt" | "mbox" | "mathrm" | "mathit"
+        | "mathbf" | "mathbb" | "mathcal" | "mathscr" | "mathfrak" | "mathsf" | "mathtt" | "boldsymbol" | "bm"
+        | "operatorname" => (false, 1),
+        "left" | "right" | "bigl" | "bigr" | "Bigl" | "Bigr" | "big" | "Big" => (false, 1),
+        "overline" | "underline" | "hat" | "bar" | "vec" | "dot" | "ddot" | "tilde" => (false, 1),
+        _ => (false, 0),
+    }
+}
+
+/// Parse LaTeX source into a list of nodes
+pub fn parse(input: &str) -> Vec<Node> {
+    parse_with_macros(input, &mut Macros::default())
+}
+
+/// Parse LaTeX source, defining and expanding macros
+///
+/// Definitions are left out of the tree, and so is the line break after a
+/// definition on a line of its own. `macros` keeps the definitions, so they
+/// carry over to later calls (the next math region of a text).
+pub fn parse_with_macros(input: &str, macros: &mut Macros) -> Vec<Node> {
+    let mut parser = Parser { tokens: macros.expand(tokenize(input)), pos: 0 };
+    let mut nodes = Vec::new();
+    // Stray `}` and `]` at the top level are kept as characters
+    loop {
+        nodes.extend(parser.parse_sequence(Context::TopLevel));
+        match parser.next() {
+            Some(Token::EndGroup) => nodes.push(Node::Char('}')),
+            Some(_) => unreachable!("top-level sequences only stop at '}}' or the end"),
+            None => break,
+        }
+    }
+    nodes
+}
+
+/// Expand the macros in LaTeX source, recording its definitions
+///
+/// For back ends that read the source rather than the tree, like the
+/// calculator expressions of `ti_expr`.
+pub fn expand_macros(input: &str, macros: &mut Macros) -> String {
+    tokens_to_source(&macros.expand(tokenize(input)))
+}
+
+/// Write tokens back as LaTeX source
+fn tokens_to_source(tokens: &[Token]) -> String {
+    let mut source = String::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Command(name) => {
+                source.push('\\');
+                source.push_str(name);
+                // An expanded `\alpha` before `x` must not become `\alphax`
+                if name.starts_with(|c: char| c.is_ascii_alphabetic())
+                    && matches!(tokens.get(i + 1), Some(Token::Char(c)) if c.is_ascii_alphabetic())
+                {
+                    source.push(' ');
+                }
+            }
+            Token::Char(c) => source.push(*c),
+            Token::BeginGroup => source.push('{'),
+            Token::EndGroup => source.push('}'),
+            Token::Superscript => source.push('^'),
+            Token::Subscript => source.push('_'),
+            Token::AlignTab => source.push('&'),
+        }
+    }
+    source
+}
+
+/// Macros defined with `\newcommand`, `\renewcommand`, `\providecommand`
+/// or `\def`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Macros {
+    definitions: HashMap<String, Macro>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Macro {
+    /// Number of parameters, `#1` to `#9`
+    params: usize,
+    /// Default of an optional first parameter: `\newcommand{\f}[2][x]{...}`
+    default: Option<Vec<Token>>,
+    body: Vec<Token>,
+}
+
+/// Expansions allowed per parse, so a recursive macro can't hang
+const MAX_EXPANSIONS: usize = 10_000;
+
+impl Macros {
+    /// Whether a command is a defined macro
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.definitions.contains_key(name)
+    }
+
+    /// Record the definitions in a token list and expand the macros used
+    fn expand(&mut self, tokens: Vec<Token>) -> Vec<Token> {
+        // The remaining input, next token last
+        let mut stack: Vec<Token> = tokens.into_iter().rev().collect();
+        let mut out = Vec::new();
+        let mut expansions = 0;
+
+        while let Some(token) = stack.pop() {
+            let Token::Command(name) = &token else {
+                out.push(token);
+                continue;
+            };
+
+            if matches!(name.as_str(), "newcommand" | "renewcommand" | "providecommand" | "def") {
+                let mut rest = stack.clone();
+                if let Some((macro_name, definition)) = read_definition(name, &mut rest) {
+                    if name != "providecommand" || !self.definitions.contains_key(&macro_name) {
+                        self.definitions.insert(macro_name, definition);
+                    }
+                    stack = rest;
+                    // A definition on a line of its own leaves no empty line
+                    if matches!(out.last(), None | Some(Token::Char('\n')))
+                        && stack.last() == Some(&Token::Char('\n'))
+                    {
+                        stack.pop();
+                    }
+                    continue;
+                }
+            } else if let Some(definition) = self.definitions.get(name)
+                && expansions < MAX_EXPANSIONS
+            {
+                expansions += 1;
+                let body = definition.apply(&mut stack);
+                stack.extend(body.into_iter().rev());
+                continue;
+            }
+            out.push(token);
+        }
+
+        out
+    }
+}
+
+impl Macro {
+    /// Take the arguments off the input and return the substituted body
+    fn apply(&self, stack: &mut Vec<Token>) -> Vec<Token> {
+        let mut args = Vec::with_capacity(self.params);
+        for i in 0..self.params {
+            if i == 0
+                && let Some(default) = &self.default
+            {
+                args.push(read_bracket(stack).unwrap_or_else(|| default.clone()));
+                continue;
+            }
+            skip_spaces(stack);
+            let arg = match read_group(stack) {
+                Some(group) => group,
+                None => stack.pop().into_iter().collect(),
+            };
+            args.push(arg);
+        }
+
+        let mut body = Vec::with_capacity(self.body.len());
+        let mut tokens = self.body.iter().peekable();
+        while let Some(token) = tokens.next() {
+            if *token == Token::Char('#') {
+                match tokens.peek() {
+                    Some(Token::Char(digit))
+                        if let Some(n) = digit.to_digit(10)
+                            && (1..=self.params).contains(&(n as usize)) =>
+                    {
+                        body.extend(args[n as usize - 1].iter().cloned());
+                        tokens.next();
+                        continue;
+                    }
+                    Some(Token::Char('#')) => {
+                        tokens.next();
+                    }
+                    _ => {}
+                }
+            }
+            body.push(token.clone());
+        }
+        body
+    }
+}
+
+/// Read a definition after its `\newcommand` or `\def`
+///
+/// `\newcommand{\name}[params][default]{body}`, `\newcommand\name{body}` and
+/// `\def\name#1#2{body}` are understood.
+fn read_definition(kind: &str, stack: &mut Vec<Token>) -> Option<(String, Macro)> {
+    skip_spaces(stack);
+
+    if kind == "def" {
+        let Some(Token::Command(name)) = stack.pop() else {
+            return None;
+        };
+        let mut params = 0;
+        while stack.last() == Some(&Token::Char('#')) {
+            stack.pop();
+            match stack.pop() {
+                Some(Token::Char(digit)) if digit.to_digit(10) == Some(params + 1) => params += 1,
+                _ => return None,
+            }
+        }
+        let body = read_group(stack)?;
+        return Some((name, Macro { params: params as usize, default: None, body }));
+    }
+
+    let name = match stack.pop()? {
+        Token::Command(name) => name,
+        Token::BeginGroup => {
+            skip_spaces(stack);
+            let Some(Token::Command(name)) = stack.pop() else {
+                return None;
+            };
+            skip_spaces(stack);
+            if stack.pop() != Some(Token::EndGroup) {
+                return None;
+            }
+            name
+        }
+        _ => return None,
+    };
+
+    skip_spaces(stack);
+    let params = match read_bracket(stack) {
+        Some(count) => {
+            let count: String = count
+                .iter()
+                .map(|token| match token {
+                    Token::Char(c) => Some(*c),
+                    _ => None,
+                })
+                .collect::<Option<_>>()?;
+            count.trim().parse::<usize>().ok().filter(|n| (1..=9).contains(n))?
+        }
+        None => 0,
+    };
+    skip_spaces(stack);
+    let default = if params > 0 { read_bracket(stack) } else { None };
+    skip_spaces(stack);
+    let body = read_group(stack)?;
+    Some((name, Macro { params, default, body }))
+}
+
+fn skip_spaces(stack: &mut Vec<Token>) {
+    while matches!(stack.last(), Some(Token::Char(' ' | '\t'))) {
+        stack.pop();
+    }
+}
+
+/// Take a `{...}` group off the input and return its contents
+///
+/// Nothing is taken when the next token isn't `{` or the group is unclosed.
+fn read_group(stack: &mut Vec<Token>) -> Option<Vec<Token>> {
+    if stack.last() != Some(&Token::BeginGroup) {
+        return None;
+    }
+    let mut depth = 0;
+    let start = (0..stack.len()).rev().find(|&i| {
+        match stack[i] {
+            Token::BeginGroup => depth += 1,
+            Token::EndGroup => depth -= 1,
+            _ => {}
+        }
+        depth == 0
+    })?;
+    Some(take_between(stack, start))
+}
+
+/// Take a `[...]` argument off the input and return its contents
+///
+/// Nothing is taken when the next token isn't `[` or the `]` is missing.
+fn read_bracket(stack: &mut Vec<Token>) -> Option<Vec<Token>> {
+    if stack.last() != Some(&Token::Char('[')) {
+        return None;
+    }
+    let mut depth = 0;
+    let start = (0..stack.len() - 1).rev().find(|&i| {
+        match stack[i] {
+            Token::BeginGroup => depth += 1,
+            Token::EndGroup => depth -= 1,
+            Token::Char(']') if depth == 0 => return true,
+            _ => {}
+        }
+        false
+    })?;
+    Some(take_between(stack, start))
+}
+
+/// Remove the tokens from `start` to the top of the stack and return them
+/// in input order, without the opening and closing delimiters
+fn take_between(stack: &mut Vec<Token>, start: usize) -> Vec<Token> {
+    let mut tokens: Vec<Token> = stack.drain(start..).rev().collect();
+    tokens.pop();
+    tokens.remove(0);
+    tokens
+}
+
+/// Where a sequence is being parsed, which decides what ends it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Context {
+    TopLevel,
+    Group,
+    Optional,
+    Cell,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(&Token::Char(' ')) {
+            self.pos += 1;
+        }
+    }
+
+    /// Parse nodes until the end of input or a token that ends `context`
+    ///
+    /// The ending token is left unconsumed.
+    fn parse_sequence(&mut self, context: Context) -> Vec<Node> {
+        let mut nodes: Vec<Node> = Vec::new();
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::EndGroup => break,
+                Token::Char(']') if context == Context::Optional => break,
+                Token::AlignTab if context == Context::Cell => break,
+                Token::Command(name) if context == Context::Cell && (name == "\\" || name == "end") => break,
+                Token::AlignTab => {
+                    self.pos += 1;
+                    nodes.push(Node::Char('&'));
+                }
+                Token::Superscript | Token::Subscript => {
+                    let is_sup = *token == Token::Superscript;
+                    self.pos += 1;
+                    self.parse_script(&mut nodes, is_sup);
+                }
+                _ => {
+                    if let Some(node) = self.parse_atom() {
+                        nodes.push(node);
+                    }
+                }
+            }
+        }
+
+        nodes
+    }
+
+    /// Attach a `^` or `_` argument to the previous node
+    fn parse_script(&mut self, nodes: &mut Vec<Node>, is_sup: bool) {
+        self.skip_spaces();
+        let Some(arg) = self.parse_argument() else {
+            nodes.push(Node::Char(if is_sup { '^' } else { '_' }));
+            return;
+        };
+
+        // `x_1^2` fills both slots of one script
+        if let Some(Node::Script { sub, sup, .. }) = nodes.last_mut() {
+            let slot = if is_sup { sup } else { sub };
+            if slot.is_none() {
+                *slot = Some(Box::new(arg));
+                return;
+            }
+        }
+
+        let base = nodes.pop().map(Box::new);
+        let (sub, sup) = if is_sup { (None, Some(Box::new(arg))) } else { (Some(Box::new(arg)), None) };
+        nodes.push(Node::Script { base, sub, sup });
+    }
+
+    /// A command argument or script: a group or a single atom
+    fn parse_argument(&mut self) -> Option<Node> {
+        match self.peek()? {
+            Token::EndGroup | Token::Superscript | Token::Subscript | Token::AlignTab => None,
+            _ => self.parse_atom(),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Option<Node> {
+        match self.next()? {
+            Token::Char(c) => Some(Node::Char(c)),
+            Token::BeginGroup => Some(self.parse_group()),
+            Token::Command(name) if name == "begin" => Some(self.parse_environment()),
+            Token::Command(name) => Some(self.parse_command(name)),
+            Token::EndGroup => Some(Node::Char('}')),
+            Token::Superscript => Some(Node::Char('^')),
+            Token::Subscript => Some(Node::Char('_')),
+            Token::AlignTab => Some(Node::Char('&')),
+        }
+    }
+
+    /// Parse the rest of a group after its `{`
+    fn parse_group(&mut self) -> Node {
+        let children = self.parse_sequence(Context::Group);
+        let closed = self.peek() == Some(&Token::EndGroup);
+        if closed {
+            self.pos += 1;
+        }
+        Node::Group { children, closed }
+    }
+
+    fn parse_command(&mut self, name: String) -> Node {
+        let (has_optional, count) = command_arity(&name);
+
+        let mut optional = None;
+        if has_optional && self.peek() == Some(&Token::Char('[')) {
+            self.pos += 1;
+            optional = Some(self.parse_sequence(Context::Optional));
+            if self.peek() == Some(&Token::Char(']')) {
+                self.pos += 1;
+            }
+        }
+
+        let mut args = Vec::with_capacity(count);
+        for _ in 0..count {
+            let start = self.pos;
+            self.skip_spaces();
+            match self.parse_argument() {
+                Some(arg) => args.push(arg),
+                None => {
+                    // Missing argument: keep the spaces for the following text
+                    self.pos = start;
+                    break;
+                }
+            }
+        }
+
+        Node::Command { name, optional, args }
+    }
+
+    /// Parse `{name}` and the body after `\begin`
+    fn parse_environment(&mut self) -> Node {
+        let name = match self.peek() {
+            Some(Token::BeginGroup) => {
+                self.pos += 1;
+                match self.parse_group() {
+                    Node::Group { children, closed: true } => plain_text(&children),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        let Some(name) = name else {
+            // Not a well-formed \begin{name}; keep it as a plain command
+            return Node::Command { name: "begin".to_string(), optional: None, args: Vec::new() };
+        };
+
+        // \begin{array}{cc}: the column spec is not content
+        if matches!(name.as_str(), "array" | "tabular") {
+            self.skip_spaces();
+            if self.peek() == Some(&Token::BeginGroup) {
+                self.pos += 1;
+                self.parse_group();
+            }
+        }
+
+        let mut rows = Vec::new();
+        let mut row = Vec::new();
+        let mut closed = false;
+
+        loop {
+            let mut cell = self.parse_sequence(Context::Cell);
+            // A `}` with no matching `{` is text, and the cell goes on after it
+            while self.peek() == Some(&Token::EndGroup) {
+                self.pos += 1;
+                cell.push(Node::Char('}'));
+                cell.extend(self.parse_sequence(Context::Cell));
+            }
+            row.push(cell);
+
+            match self.next() {
+                Some(Token::AlignTab) => {}
+                Some(Token::Command(c)) if c == "\\" => rows.push(std::mem::take(&mut row)),
+                Some(Token::Command(c)) if c == "end" => {
+                    // Skip the {name} after \end
+                    if self.peek() == Some(&Token::BeginGroup) {
+                        self.pos += 1;
+                        self.parse_group();
+                    }
+                    closed = true;
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        // A trailing `\\` before `\end` leaves an empty last row
+        let trailing_empty = row.len() == 1 && row[0].iter().all(|n| matches!(n, Node::Char(c) if c.is_whitespace()));
+        if !trailing_empty || rows.is_empty() {
+            rows.push(row);
+        }
+
+        Node::Environment { name, rows, closed }
+    }
+}
+
+/// The text of a node list made only of characters
+pub fn plain_text(nodes: &[Node]) -> Option<String> {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Char(c) => Some(*c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// A run of text that is either prose or math
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    /// Prose, with `\$` unescaped to `$`
+    Text(String),
+    /// LaTeX source between math delimiters, without the delimiters
+    Math(String),
+}
+
+/// Split text into prose and math delimited by `$...$`, `$$...$$`,
+/// `\(...\)` or `\[...\]`
+///
+/// Inline `$` follows pandoc's rules, so prices aren't taken for math: the
+/// opening `$` is followed by a non-space, and the closing `$` follows a
+/// non-space and isn't followed by a digit. `$...$` and `\(...\)` end at
+/// the line; `$$...$$` and `\[...\]` may span lines. `\$` is a literal
+/// dollar sign, and a delimiter without a partner stays text.
+pub fn split_math(text: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut prose_start = 0;
+
+    for (region, math) in math_regions(text) {
+        if region.start > prose_start {
+            segments.push(Segment::Text(unescape_dollars(&text[prose_start..region.start])));
+        }
+        segments.push(Segment::Math(text[math].to_string()));
+        prose_start = region.end;
+    }
+
+    if prose_start < text.len() {
+        segments.push(Segment::Text(unescape_dollars(&text[prose_start..])));
+    }
+    segments
+}
+
+/// Byte ranges of the math regions in a text, see [`split_math`]
+///
+/// Each region is given with its delimiters and as just the math inside.
+pub fn math_regions(text: &str) -> Vec<(Range<usize>, Range<usize>)> {
+    let mut regions = Vec::new();
+    let mut pos = 0;
+
+    while let Some(c) = text[pos..].chars().next() {
+        let rest = &text[pos..];
+        if rest.starts_with("\\$") || rest.starts_with("\\\\") {
+            // `\$` is a dollar sign and `\\[` a line break, not math
+            pos += 2;
+        } else if let Some((delimiter, length)) = math_region(rest) {
+            let start = pos + delimiter;
+            let end = start + length + delimiter;
+            regions.push((pos..end, start..start + length));
+            pos = end;
+        } else {
+            pos += c.len_utf8();
+        }
+    }
+    regions
+}
+
+/// Prose with `\$` read as a dollar sign
+fn unescape_dollars(text: &str) -> String {
+    let mut prose = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("\\$") {
+            prose.push('$');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("\\\\") {
+            prose.push_str("\\\\");
+            rest = after;
+        } else {
+            prose.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    prose
+}
+
+/// The math region at the start of the text, as the length of its
+/// delimiters and of the math between them
+fn math_region(text: &str) -> Option<(usize, usize)> {
+    if let Some(body) = text.strip_prefix("$$") {
+        return Some((2, body.find("$$")?));
+    }
+    if let Some(body) = text.strip_prefix("\\[") {
+        return Some((2, body.find("\\]")?));
+    }
+    if let Some(body) = text.strip_prefix("\\(") {
+        let line = body.split('\n').next().unwrap_or_default();
+        return Some((2, line.find("\\)")?));
+    }
+
+    let body = text.strip_prefix('$')?;
+    if body.starts_with(char::is_whitespace) {
+        return None;
+    }
+    let line = body.split('\n').next().unwrap_or_default();
+    let mut previous = None;
+    for (i, c) in line.char_indices() {
+        if c == '$'
+            && i > 0
+            && !matches!(previous, Some('\\') | Some(' ') | Some('\t'))
+            && !line[i + 1..].starts_with(|c: char| c.is_ascii_digit())
+        {
+            return Some((1, i));
+        }
+        previous = Some(c);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<Node> {
+        text.chars().map(Node::Char).collect()
+    }
+
+    fn group(text: &str) -> Node {
+        Node::Group { children: chars(text), closed: true }
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("\\alpha\\,x^{2}\\\\"),
+            vec![
+                Token::Command("alpha".to_string()),
+                Token::Command(",".to_string()),
+                Token::Char('x'),
+                Token::Superscript,
+                Token::BeginGroup,
+                Token::Char('2'),
+                Token::EndGroup,
+                Token::Command("\\".to_string()),
+            ]
+        );
+        assert_eq!(tokenize("a\\"), vec![Token::Char('a'), Token::Char('\\')]);
+    }
+
+    #[test]
+    fn test_nested_scripts() {
+        let nodes = parse("x^{a_{1}}");
+        let inner = Node::Script {
+            base: Some(Box::new(Node::Char('a'))),
+            sub: Some(Box::new(group("1"))),
+            sup: None,
+        };
+        assert_eq!(
+            nodes,
+            vec![Node::Script {
+                base: Some(Box::new(Node::Char('x'))),
+                sub: None,
+                sup: Some(Box::new(Node::Group { children: vec![inner], closed: true })),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_sub_and_sup_share_a_script() {
+        let nodes = parse("x_1^2");
+        assert_eq!(
+            nodes,
+            vec![Node::Script {
+                base: Some(Box::new(Node::Char('x'))),
+                sub: Some(Box::new(Node::Char('1'))),
+                sup: Some(Box::new(Node::Char('2'))),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_command_arguments() {
+        let nodes = parse("\\frac{\\sqrt[3]{x}}{2}");
+        let sqrt = Node::Command {
+            name: "sqrt".to_string(),
+            optional: Some(chars("3")),
+            args: vec![group("x")],
+        };
+        assert_eq!(
+            nodes,
+            vec![Node::Command {
+                name: "frac".to_string(),
+                optional: None,
+                args: vec![Node::Group { children: vec![sqrt], closed: true }, group("2")],
+            }]
+        );
+
+        // Single-token arguments, and spaces before arguments
+        let nodes = parse("\\frac 12");
+        assert_eq!(
+            nodes,
+            vec![Node::Command { name: "frac".to_string(), optional: None, args: chars("12") }]
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_braces() {
+        assert_eq!(parse("a}b"), chars("a}b"));
+        assert_eq!(parse("{ab"), vec![Node::Group { children: chars("ab"), closed: false }]);
+        assert_eq!(parse("x^"), chars("x^"));
+
+        // A missing argument leaves the command without it
+        assert_eq!(
+            parse("\\frac{1} "),
+            vec![
+                Node::Command { name: "frac".to_string(), optional: None, args: vec![group("1")] },
+                Node::Char(' '),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_environment() {
+        let nodes = parse("\\begin{pmatrix}a & b \\\\ c & d \\\\\n\\end{pmatrix}!");
+        assert_eq!(
+            nodes,
+            vec![
+                Node::Environment {
+                    name: "pmatrix".to_string(),
+                    rows: vec![
+                        vec![chars("a "), chars(" b ")],
+                        vec![chars(" c "), chars(" d ")],
+                    ],
+                    closed: true,
+                },
+                Node::Char('!'),
+            ]
+        );
+
+        // The column spec of an array is dropped
+        let nodes = parse("\\begin{array}{cc}a&b\\end{array}");
+        assert_eq!(
+            nodes,
+            vec![Node::Environment { name: "array".to_string(), rows: vec![vec![chars("a"), chars("b")]], closed: true }]
+        );
+
+        // A stray `}` stays in its cell
+        let nodes = parse("\\begin{pmatrix} a } b & c \\\\ d & e \\end{pmatrix}");
+        assert_eq!(
+            nodes,
+            vec![Node::Environment {
+                name: "pmatrix".to_string(),
+                rows: vec![vec![chars(" a } b "), chars(" c ")], vec![chars(" d "), chars(" e ")]],
+                closed: true,
+            }]
+        );
+
+        // Ampersands outside environments are text
+        assert_eq!(parse("a&b"), chars("a&b"));
+    }
+
+    #[test]
+    fn test_unclosed_environment() {
+        let nodes = parse("\\begin{cases}x");
+        assert_eq!(
+            nodes,
+            vec![Node::Environment { name: "cases".to_string(), rows: vec![vec![chars("x")]], closed: false }]
+        );
+        assert!(matches!(&parse("\\begin x")[0], Node::Command { name, .. } if name == "begin"));
+    }
+
+    #[test]
+    fn test_split_math() {
+        let text = |s: &str| Segment::Text(s.to_string());
+        let math = |s: &str| Segment::Math(s.to_string());
+
+        assert_eq!(split_math("Area: $\\pi r^2$ units"), vec![text("Area: "), math("\\pi r^2"), text(" units")]);
+        assert_eq!(split_math("\\(a_1\\) and \\[b^2\\]"), vec![math("a_1"), text(" and "), math("b^2")]);
+        assert_eq!(split_math("$$\\sum\nx$$"), vec![math("\\sum\nx")]);
+        assert_eq!(split_math("my_var_2 and 2^10 bytes"), vec![text("my_var_2 and 2^10 bytes")]);
+    }
+
+    #[test]
+    fn test_split_math_dollars() {
+        let text = |s: &str| Segment::Text(s.to_string());
+        let math = |s: &str| Segment::Math(s.to_string());
+
+        // Prices and unpaired dollars stay text
+        assert_eq!(split_math("costs $5 or $10"), vec![text("costs $5 or $10")]);
+        assert_eq!(split_math("$ x$ and $x $"), vec![text("$ x$ and $x $")]);
+        assert_eq!(split_math("$x\ny$"), vec![text("$x\ny$")]);
+        // Escaped dollars, also inside math
+        assert_eq!(split_math("\\$5 for $x$"), vec![text("$5 for "), math("x")]);
+        assert_eq!(split_math("$a\\$b$"), vec![math("a\\$b")]);
+        // A line break is not display math
+        assert_eq!(split_math("a \\\\[2pt] b"), vec![text("a \\\\[2pt] b")]);
+    }
+
+    #[test]
+    fn test_macros() {
+        assert_eq!(parse("\\newcommand{\\R}{\\mathbb{R}}\nx \\in \\R"), parse("x \\in \\mathbb{R}"));
+        assert_eq!(parse("\\def\\half#1{\\frac{#1}{2}}\\half{a+b}"), parse("\\frac{a+b}{2}"));
+        assert_eq!(parse("\\newcommand\\pair[2]{(#1, #2)}\\pair a {b}"), parse("(a, b)"));
+        // Optional first parameter with a default
+        assert_eq!(parse("\\newcommand{\\norm}[2][2]{|#2|_#1}\\norm{x} \\norm[1]{y}"), parse("|x|_2 |y|_1"));
+        // \providecommand doesn't replace an existing macro, \renewcommand does
+        assert_eq!(parse("\\def\\a{1}\\providecommand{\\a}{2}\\a\\renewcommand{\\a}{3}\\a"), chars("13"));
+    }
+
+    #[test]
+    fn test_macros_across_calls() {
+        let mut macros = Macros::default();
+        assert_eq!(parse_with_macros("\\newcommand{\\E}{\\mathbb{E}}", &mut macros), Vec::new());
+        assert_eq!(parse_with_macros("\\E[X]", &mut macros), parse("\\mathbb{E}[X]"));
+        assert!(matches!(&parse("\\E")[0], Node::Command { name, .. } if name == "E"));
+    }
+
+    #[test]
+    fn test_expand_macros() {
+        let mut macros = Macros::default();
+        assert_eq!(expand_macros("\\def\\a{\\alpha}\\def\\h#1{\\frac{#1}{2}}", &mut macros), "");
+        assert_eq!(expand_macros("\\a x + \\h{y}^2", &mut macros), "\\alpha x + \\frac{y}{2}^2");
+        assert_eq!(expand_macros("\\a\\,x & \\{", &mut macros), "\\alpha\\,x & \\{");
+    }
+
+    #[test]
+    fn test_malformed_macros() {
+        // Broken definitions stay in the source
+        assert!(matches!(&parse("\\newcommand{\\R}")[0], Node::Command { name, .. } if name == "newcommand"));
+        assert!(matches!(&parse("\\def\\f#2{x}")[0], Node::Command { name, .. } if name == "def"));
+        // A recursive macro stops expanding
+        assert!(parse("\\def\\a{x\\a}\\a").len() > 100);
+        // A missing argument is empty
+        assert_eq!(parse("\\def\\f#1{(#1)}\\f"), chars("()"));
+    }
+}
@@ -0,0 +1,241 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! Diagnostics for LaTeX math
+//!
+//! The renderers never fail: an unknown command is copied as `\name`, an
+//! unclosed group is kept as source and a superscript without Unicode
+//! characters is written in parentheses. [`check_latex`] reports these spots
+//! with their line and column, so authors can fix them before a document
+//! reaches the calculator. With `--strict` they are errors.
+
+use std::fmt;
+use std::ops::Range;
+
+use super::latex::{self, Macros, Token};
+use super::math_render::{self, MathLayout, MathScope, RenderOptions};
+
+/// A problem in LaTeX source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// 1-based line in the text
+    pub line: usize,
+    /// 1-based column, in characters
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// A group or environment that is not closed yet, with its byte offset
+enum Open {
+    Group(usize),
+    Environment(usize, String),
+}
+
+/// Check the LaTeX of a text the way `render_latex` reads it
+///
+/// With [`MathScope::Delimited`] only the math regions are checked, and a
+/// macro defined in one region is known in the ones after it. Fallbacks
+/// that depend on the layout (scripts in parentheses) are only reported for
+/// the layout in `options`.
+pub fn check_latex(text: &str, options: &RenderOptions) -> Vec<Diagnostic> {
+    let regions: Vec<Range<usize>> = match options.scope {
+        MathScope::Document => std::iter::once(0..text.len()).collect(),
+        MathScope::Delimited => latex::math_regions(text).into_iter().map(|(_, math)| math).collect(),
+    };
+
+    let mut macros = Macros::default();
+    let mut diagnostics = Vec::new();
+    for region in regions {
+        let source = &text[region.clone()];
+        latex::parse_with_macros(source, &mut macros);
+
+        for (offset, message) in check_math(source, &macros, options) {
+            let (line, column) = position(text, region.start + offset);
+            diagnostics.push(Diagnostic { line, column, message });
+        }
+    }
+    diagnostics
+}
+
+/// Problems in one piece of math, with the byte offsets they start at
+fn check_math(source: &str, macros: &Macros, options: &RenderOptions) -> Vec<(usize, String)> {
+    let tokens = latex::tokenize_with_offsets(source);
+    let mut problems = Vec::new();
+    let mut open = Vec::new();
+    let mut i = 0;
+
+    while let Some((offset, token)) = tokens.get(i) {
+        let offset = *offset;
+        i += 1;
+
+        match token {
+            Token::BeginGroup => open.push(Open::Group(offset)),
+            Token::EndGroup => match open.last() {
+                Some(Open::Group(_)) => {
+                    open.pop();
+                }
+                _ => problems.push((offset, "unmatched '}'".to_string())),
+            },
+            Token::Command(name) if name == "begin" || name == "end" => {
+                let Some((environment, length)) = group_text(&tokens[i..]) else {
+                    continue;
+                };
+                i += length;
+
+                if name == "begin" {
+                    open.push(Open::Environment(offset, environment));
+                    continue;
+                }
+                match open.last() {
+                    Some(Open::Environment(_, begun)) if *begun == environment => {
+                        open.pop();
+                    }
+                    Some(Open::Environment(_, begun)) => {
+                        problems.push((offset, format!("\\end{{{}}} doesn't match \\begin{{{}}}", environment, begun)));
+                        open.pop();
+                    }
+                    _ => problems.push((offset, format!("\\end{{{}}} without \\begin", environment))),
+                }
+            }
+            Token::Command(name) => {
+                if !macros.is_defined(name) && !math_render::is_known_command(name, options) {
+                    problems.push((offset, format!("unknown command \\{}", name)));
+                }
+                // `\left\{`: the delimiter isn't a command of its own
+                if math_render::is_delimiter_command(name) && matches!(tokens.get(i), Some((_, Token::Command(_)))) {
+                    i += 1;
+                }
+            }
+            Token::Superscript | Token::Subscript if options.layout == MathLayout::Linear => {
+                let superscript = *token == Token::Superscript;
+                if let Some(text) = script_text(&tokens[i..], source, macros, options)
+                    && math_render::script_chars(&text, superscript, options).is_none()
+                {
+                    let kind = if superscript { "superscript" } else { "subscript" };
+                    problems.push((offset, format!("{} '{}' fell back to parentheses", kind, text)));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for item in open {
+        problems.push(match item {
+            Open::Group(offset) => (offset, "unclosed group".to_string()),
+            Open::Environment(offset, name) => (offset, format!("\\begin{{{}}} is never closed", name)),
+        });
+    }
+    problems.sort_by_key(|(offset, _)| *offset);
+    problems
+}
+
+/// The name in a `{name}` group at the start of the tokens, and the number
+/// of tokens it spans
+fn group_text(tokens: &[(usize, Token)]) -> Option<(String, usize)> {
+    let [(_, Token::BeginGroup), rest @ ..] = tokens else {
+        return None;
+    };
+    let mut name = String::new();
+    for (i, (_, token)) in rest.iter().enumerate() {
+        match token {
+            Token::Char(c) => name.push(*c),
+            Token::EndGroup => return Some((name, i + 2)),
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// The rendered text of the argument of `^` or `_`, if it can be checked
+///
+/// An unclosed argument is reported as an unclosed group instead.
+fn script_text(tokens: &[(usize, Token)], source: &str, macros: &Macros, options: &RenderOptions) -> Option<String> {
+    let mut rest = tokens.iter().skip_while(|(_, token)| matches!(token, Token::Char(' ' | '\t')));
+    let argument = match rest.next()? {
+        (_, Token::Char(c)) => return Some(c.to_string()),
+        (_, Token::Command(name)) => return math_render::command_symbol(name, options).map(str::to_string),
+        (start, Token::BeginGroup) => {
+            let mut depth = 0;
+            let (end, _) = rest.find(|(_, token)| {
+                match token {
+                    Token::BeginGroup => depth += 1,
+                    Token::EndGroup if depth == 0 => return true,
+                    Token::EndGroup => depth -= 1,
+                    _ => {}
+                }
+                false
+            })?;
+            &source[start + 1..*end]
+        }
+        _ => return None,
+    };
+
+    let nodes = latex::parse_with_macros(argument, &mut macros.clone());
+    Some(math_render::nodes_to_unicode(&nodes, options))
+}
+
+/// Line and column of a byte offset, both 1-based
+fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count() + 1, before[line_start..].chars().count() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(text: &str, options: &RenderOptions) -> Vec<String> {
+        check_latex(text, options).iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        let options = RenderOptions::default();
+        assert_eq!(messages("ok\nso $x + \\foo$", &options), vec!["line 2, column 9: unknown command \\foo"]);
+        assert!(messages("$\\alpha \\leq \\frac{1}{2} \\left\\{ x \\right.$ and \\foo", &options).is_empty());
+        assert!(messages("$\\sin x \\cdot \\lim f(n) \\max(a, b) \\det A$", &options).is_empty());
+        assert!(messages("$\\binom{n}{k} \\in \\{0, 1\\}$ in 50\\% of cases", &options).is_empty());
+
+        let document = RenderOptions { scope: MathScope::Document, ..Default::default() };
+        assert_eq!(messages("so \\foo", &document), vec!["line 1, column 4: unknown command \\foo"]);
+    }
+
+    #[test]
+    fn test_groups_and_environments() {
+        let options = RenderOptions::default();
+        assert_eq!(messages("$x^{2$", &options), vec!["line 1, column 4: unclosed group"]);
+        assert_eq!(messages("\\(a}\\)", &options), vec!["line 1, column 4: unmatched '}'"]);
+        assert_eq!(
+            messages("\\[\\begin{pmatrix} a \\end{bmatrix}\\]", &options),
+            vec!["line 1, column 21: \\end{bmatrix} doesn't match \\begin{pmatrix}"]
+        );
+        assert_eq!(
+            messages("$$\\begin{cases} a\nb$$", &options),
+            vec!["line 1, column 3: \\begin{cases} is never closed"]
+        );
+    }
+
+    #[test]
+    fn test_script_fallbacks() {
+        let options = RenderOptions::default();
+        assert_eq!(messages("$x^{2n} + e^{-q}$", &options), vec!["line 1, column 12: superscript '-q' fell back to parentheses"]);
+        assert_eq!(messages("$a_{xy}$", &options), vec!["line 1, column 3: subscript 'xy' fell back to parentheses"]);
+
+        // The 2D layout raises scripts instead
+        let two_d = RenderOptions { layout: MathLayout::TwoD, ..Default::default() };
+        assert!(messages("$e^{-q}$", &two_d).is_empty());
+    }
+
+    #[test]
+    fn test_macros_are_known() {
+        let options = RenderOptions::default();
+        assert!(messages("$\\newcommand{\\R}{\\mathbb{R}}$\n$x \\in \\R^2$", &options).is_empty());
+    }
+}
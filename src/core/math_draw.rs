@@ -0,0 +1,446 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! Native math drawing for the Lua text viewer
+//!
+//! The TI font is missing many math glyphs (∑, ∫, tall brackets), which is
+//! why the Unicode back end falls back to `SUM` and `INT`. In native mode
+//! the viewer draws formulas itself instead: each formula's [`Layout`] is
+//! written into the script as a Lua table, and [`LUA_MATH_RUNTIME`] measures
+//! it with the device's fonts and draws it with `gc:drawString` at smaller
+//! sizes for scripts and `gc:drawLine` for fraction bars, radicals,
+//! brackets and big operators.
+
+use super::latex::Node;
+use super::math_layout::{layout, Layout};
+use super::math_render::{RenderOptions, RenderedLine};
+
+/// Render one line of LaTeX text for native drawing
+///
+/// Lines without any math structure stay ordinary text. `render_latex`
+/// calls this for each math region of a line too, so only the formula is
+/// drawn and the prose around it is wrapped as text.
+pub fn render_line(nodes: &[Node], options: &RenderOptions) -> RenderedLine {
+    match layout(nodes, options) {
+        Layout::Text(text) => RenderedLine::Text(text),
+        Layout::Row(items) if items.is_empty() => RenderedLine::Text(String::new()),
+        formula => RenderedLine::Formula(formula),
+    }
+}
+
+/// A Lua string literal
+fn lua_string(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len() + 2);
+    literal.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            c if c.is_ascii_control() => literal.push_str(&format!("\\{:03}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Write a layout as a Lua table constructor for the runtime
+pub fn layout_to_lua(layout: &Layout) -> String {
+    let field = |name: &str, child: &Option<Box<Layout>>| match child {
+        Some(child) => format!(", {}={}", name, layout_to_lua(child)),
+        None => String::new(),
+    };
+    let bracket = |name: &str, c: &Option<char>| match c {
+        Some(c) => format!(", {}={}", name, lua_string(&c.to_string())),
+        None => String::new(),
+    };
+
+    match layout {
+        Layout::Text(text) => format!("{{t=\"text\", s={}}}", lua_string(text)),
+        Layout::Row(items) => {
+            let items: Vec<String> = items.iter().map(layout_to_lua).collect();
+            format!("{{t=\"row\", {}}}", items.join(", "))
+        }
+        Layout::Fraction { num, den } => {
+            format!("{{t=\"frac\", num={}, den={}}}", layout_to_lua(num), layout_to_lua(den))
+        }
+        Layout::Scripts { base, sub, sup } => {
+            format!("{{t=\"scr\", base={}{}{}}}", layout_to_lua(base), field("sub", sub), field("sup", sup))
+        }
+        Layout::BigOp { name, symbol, over, under } => format!(
+            "{{t=\"op\", k={}, s={}{}{}}}",
+            lua_string(name),
+            lua_string(symbol),
+            field("over", over),
+            field("under", under)
+        ),
+        Layout::Root { index, body } => {
+            let index = index.as_ref().map(|i| format!(", index={}", lua_string(i))).unwrap_or_default();
+            format!("{{t=\"root\", body={}{}}}", layout_to_lua(body), index)
+        }
+        Layout::Fenced { open, close, body } => format!(
+            "{{t=\"fence\", body={}{}{}}}",
+            layout_to_lua(body),
+            bracket("open", open),
+            bracket("close", close)
+        ),
+        Layout::Matrix { rows, spacing } => {
+            let rows: Vec<String> = rows
+                .iter()
+                .map(|row| format!("{{{}}}", row.iter().map(layout_to_lua).collect::<Vec<_>>().join(", ")))
+                .collect();
+            format!("{{t=\"mat\", sp={}, rows={{{}}}}}", spacing, rows.join(", "))
+        }
+    }
+}
+
+/// Lua functions that measure and draw the tables from [`layout_to_lua`]
+///
+/// `math_measure(gc, box, level)` stores the width, ascent and descent in
+/// the table; `math_draw(gc, box, x, baseline, level)` draws it. Level 1 is
+/// the text size, scripts and limits go one level smaller. The ignored
+/// tests run it with `lua` against a stub `gc` and compile the whole viewer
+/// with `luac -p`.
+pub const LUA_MATH_RUNTIME: &str = r#"
+-- Native math drawing (generated by Luna-RS)
+local MATH_SIZES = {11, 9, 7}
+local OP_SHAPES = {sum = true, prod = true, int = true}
+
+local function math_size(level)
+    return MATH_SIZES[math.min(level, #MATH_SIZES)]
+end
+
+-- Height of the fraction bar above the baseline
+local function math_axis(level)
+    return math.floor(math_size(level) * 0.3)
+end
+
+local function measure_text(gc, s, level)
+    gc:setFont("sansserif", "r", math_size(level))
+    local h = gc:getStringHeight("X")
+    local asc = math.floor(h * 0.75)
+    return gc:getStringWidth(s), asc, h - asc
+end
+
+function math_measure(gc, box, level)
+    local t = box.t
+    local w, asc, desc = 0, 0, 0
+    if t == "text" then
+        w, asc, desc = measure_text(gc, box.s, level)
+    elseif t == "row" then
+        for _, child in ipairs(box) do
+            math_measure(gc, child, level)
+            w = w + child.w
+            asc = math.max(asc, child.asc)
+            desc = math.max(desc, child.desc)
+        end
+    elseif t == "frac" then
+        math_measure(gc, box.num, level)
+        math_measure(gc, box.den, level)
+        local axis = math_axis(level)
+        w = math.max(box.num.w, box.den.w) + 4
+        asc = axis + 2 + box.num.asc + box.num.desc
+        desc = box.den.asc + box.den.desc + 2 - axis
+    elseif t == "scr" then
+        math_measure(gc, box.base, level)
+        w, asc, desc = box.base.w, box.base.asc, box.base.desc
+        local extra = 0
+        if box.sup then
+            math_measure(gc, box.sup, level + 1)
+            box.up = math.floor(box.base.asc * 0.5) + box.sup.desc
+            asc = math.max(asc, box.up + box.sup.asc)
+            extra = box.sup.w
+        end
+        if box.sub then
+            math_measure(gc, box.sub, level + 1)
+            box.down = box.base.desc + math.floor(box.sub.asc * 0.5)
+            desc = math.max(desc, box.down + box.sub.desc)
+            extra = math.max(extra, box.sub.w)
+        end
+        w = w + extra + 1
+    elseif t == "op" then
+        if OP_SHAPES[box.k] then
+            local size = math_size(level)
+            box.sw = box.k == "int" and math.floor(size * 0.6) or size
+            box.sh = math.floor(size * 1.4)
+            box.sa = math.floor(box.sh / 2) + math_axis(level)
+        else
+            local sa, sd
+            box.sw, sa, sd = measure_text(gc, box.s, level)
+            box.sh, box.sa = sa + sd, sa
+        end
+        w, asc, desc = box.sw, box.sa, box.sh - box.sa
+        if box.over then
+            math_measure(gc, box.over, level + 1)
+            w = math.max(w, box.over.w)
+            asc = asc + box.over.asc + box.over.desc + 1
+        end
+        if box.under then
+            math_measure(gc, box.under, level + 1)
+            w = math.max(w, box.under.w)
+            desc = desc + box.under.asc + box.under.desc + 1
+        end
+        w = w + 2
+    elseif t == "root" then
+        math_measure(gc, box.body, level)
+        box.iw = box.index and measure_text(gc, box.index, level + 2) or 0
+        w = box.iw + 8 + box.body.w
+        asc = box.body.asc + 3
+        desc = box.body.desc
+    elseif t == "fence" then
+        math_measure(gc, box.body, level)
+        box.bw = math.max(3, math.floor(math_size(level) / 3))
+        w = box.body.w
+        if box.open then w = w + box.bw + 2 end
+        if box.close then w = w + box.bw + 2 end
+        asc = box.body.asc + 1
+        desc = box.body.desc + 1
+    elseif t == "mat" then
+        box.widths, box.heights = {}, {}
+        local total = 0
+        for r, row in ipairs(box.rows) do
+            local ra, rd = 0, 0
+            for c, cell in ipairs(row) do
+                math_measure(gc, cell, level)
+                box.widths[c] = math.max(box.widths[c] or 0, cell.w)
+                ra = math.max(ra, cell.asc)
+                rd = math.max(rd, cell.desc)
+            end
+            box.heights[r] = {ra, rd}
+            total = total + ra + rd + 2
+        end
+        for _, cw in ipairs(box.widths) do
+            w = w + cw
+        end
+        w = w + math.max(0, #box.widths - 1) * box.sp * 4
+        asc = math.floor(total / 2) + math_axis(level)
+        desc = total - asc
+    end
+    box.w, box.asc, box.desc = w, asc, desc
+end
+
+-- Bracket outlines in a unit box; closing brackets are mirrored
+local BRACKETS = {
+    ["("] = {{1, 0}, {0, 0.2}, {0, 0.8}, {1, 1}},
+    ["["] = {{1, 0}, {0, 0}, {0, 1}, {1, 1}},
+    ["{"] = {{1, 0}, {0.5, 0.05}, {0.5, 0.45}, {0, 0.5}, {0.5, 0.55}, {0.5, 0.95}, {1, 1}},
+    ["⟨"] = {{1, 0}, {0, 0.5}, {1, 1}},
+    ["⌊"] = {{0, 0}, {0, 1}, {1, 1}},
+    ["⌈"] = {{1, 0}, {0, 0}, {0, 1}},
+    ["|"] = {{0.5, 0}, {0.5, 1}},
+}
+local MIRRORED = {[")"] = "(", ["]"] = "[", ["}"] = "{", ["⟩"] = "⟨", ["⌋"] = "⌊", ["⌉"] = "⌈"}
+
+local function draw_bracket(gc, c, x, top, bottom, bw)
+    if c == "‖" then
+        draw_bracket(gc, "|", x - 1, top, bottom, bw)
+        draw_bracket(gc, "|", x + 1, top, bottom, bw)
+        return
+    end
+    local shape, flip = BRACKETS[c], false
+    if not shape and MIRRORED[c] then
+        shape, flip = BRACKETS[MIRRORED[c]], true
+    end
+    if not shape then
+        gc:drawString(c, x, bottom, "bottom")
+        return
+    end
+    local function point(p)
+        local fx = flip and 1 - p[1] or p[1]
+        return x + math.floor(fx * bw + 0.5), top + math.floor(p[2] * (bottom - top) + 0.5)
+    end
+    for i = 2, #shape do
+        local x1, y1 = point(shape[i - 1])
+        local x2, y2 = point(shape[i])
+        gc:drawLine(x1, y1, x2, y2)
+    end
+end
+
+local function draw_operator(gc, box, x, top, baseline, level)
+    local w, h = box.sw, box.sh
+    local bottom = top + h
+    if box.k == "sum" then
+        local mx, my = x + math.floor(w / 2), top + math.floor(h / 2)
+        gc:drawLine(x + w, top, x, top)
+        gc:drawLine(x, top, mx, my)
+        gc:drawLine(mx, my, x, bottom)
+        gc:drawLine(x, bottom, x + w, bottom)
+    elseif box.k == "prod" then
+        gc:drawLine(x, top, x + w, top)
+        gc:drawLine(x + 2, top, x + 2, bottom)
+        gc:drawLine(x + w - 2, top, x + w - 2, bottom)
+    elseif box.k == "int" then
+        local cx = x + math.floor(w / 2)
+        gc:drawLine(x + w, top + 1, cx + 1, top)
+        gc:drawLine(cx + 1, top, cx - 1, bottom)
+        gc:drawLine(cx - 1, bottom, x, bottom - 1)
+    else
+        gc:setFont("sansserif", "r", math_size(level))
+        gc:drawString(box.s, x, baseline, "baseline")
+    end
+end
+
+function math_draw(gc, box, x, y, level)
+    local t = box.t
+    if t == "text" then
+        gc:setFont("sansserif", "r", math_size(level))
+        gc:drawString(box.s, x, y, "baseline")
+    elseif t == "row" then
+        for _, child in ipairs(box) do
+            math_draw(gc, child, x, y, level)
+            x = x + child.w
+        end
+    elseif t == "frac" then
+        local bar = y - math_axis(level)
+        gc:drawLine(x + 1, bar, x + box.w - 2, bar)
+        math_draw(gc, box.num, x + math.floor((box.w - box.num.w) / 2), bar - 2 - box.num.desc, level)
+        math_draw(gc, box.den, x + math.floor((box.w - box.den.w) / 2), bar + 2 + box.den.asc, level)
+    elseif t == "scr" then
+        math_draw(gc, box.base, x, y, level)
+        local sx = x + box.base.w + 1
+        if box.sup then
+            math_draw(gc, box.sup, sx, y - box.up, level + 1)
+        end
+        if box.sub then
+            math_draw(gc, box.sub, sx, y + box.down, level + 1)
+        end
+    elseif t == "op" then
+        local cx = x + math.floor(box.w / 2)
+        local top = y - box.sa
+        draw_operator(gc, box, cx - math.floor(box.sw / 2), top, y, level)
+        if box.over then
+            math_draw(gc, box.over, cx - math.floor(box.over.w / 2), top - 1 - box.over.desc, level + 1)
+        end
+        if box.under then
+            math_draw(gc, box.under, cx - math.floor(box.under.w / 2), top + box.sh + 1 + box.under.asc, level + 1)
+        end
+    elseif t == "root" then
+        local top, bottom = y - box.asc, y + box.desc
+        local x0 = x + box.iw
+        local mid = bottom - math.floor((bottom - top) / 3)
+        gc:drawLine(x0, mid, x0 + 2, bottom)
+        gc:drawLine(x0 + 2, bottom, x0 + 6, top)
+        gc:drawLine(x0 + 6, top, x0 + 8 + box.body.w, top)
+        if box.index then
+            gc:setFont("sansserif", "r", math_size(level + 2))
+            gc:drawString(box.index, x, mid - 1, "baseline")
+        end
+        math_draw(gc, box.body, x0 + 8, y, level)
+    elseif t == "fence" then
+        local top, bottom = y - box.asc, y + box.desc
+        local bx = x
+        if box.open then
+            draw_bracket(gc, box.open, x, top, bottom, box.bw)
+            bx = x + box.bw + 2
+        end
+        math_draw(gc, box.body, bx, y, level)
+        if box.close then
+            draw_bracket(gc, box.close, bx + box.body.w + 1, top, bottom, box.bw)
+        end
+    elseif t == "mat" then
+        local row_top = y - box.asc
+        for r, row in ipairs(box.rows) do
+            local ra, rd = box.heights[r][1], box.heights[r][2]
+            local cx = x
+            for c, cell in ipairs(row) do
+                math_draw(gc, cell, cx, row_top + ra, level)
+                cx = cx + box.widths[c] + box.sp * 4
+            end
+            row_top = row_top + ra + rd + 2
+        end
+    end
+end
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::latex;
+
+    fn formula(input: &str) -> String {
+        match render_line(&latex::parse(input), &RenderOptions::default()) {
+            RenderedLine::Formula(layout) => layout_to_lua(&layout),
+            other => panic!("expected a formula: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_plain_lines_stay_text() {
+        assert_eq!(render_line(&latex::parse("x² \\alpha"), &RenderOptions::default()), RenderedLine::Text("x² α".to_string()));
+        assert_eq!(render_line(&[], &RenderOptions::default()), RenderedLine::Text(String::new()));
+    }
+
+    #[test]
+    fn test_layout_to_lua() {
+        assert_eq!(
+            formula("a = \\frac{1}{x^2}"),
+            "{t=\"row\", {t=\"text\", s=\"a = \"}, {t=\"frac\", num={t=\"text\", s=\"1\"}, \
+den={t=\"scr\", base={t=\"text\", s=\"x\"}, sup={t=\"text\", s=\"2\"}}}}"
+        );
+        assert_eq!(
+            formula("\\sum_{i=0}^{n}"),
+            "{t=\"op\", k=\"sum\", s=\"SUM\", over={t=\"text\", s=\"n\"}, under={t=\"text\", s=\"i=0\"}}"
+        );
+        assert_eq!(
+            formula("\\sqrt[3]{\\left( x \\right.}"),
+            "{t=\"root\", body={t=\"fence\", body={t=\"text\", s=\" x \"}, open=\"(\"}, index=\"3\"}"
+        );
+        assert_eq!(
+            formula("\\begin{bmatrix}1&2\\end{bmatrix}"),
+            "{t=\"fence\", body={t=\"mat\", sp=2, rows={{{t=\"text\", s=\"1\"}, {t=\"text\", s=\"2\"}}}}, \
+open=\"[\", close=\"]\"}"
+        );
+        assert_eq!(
+            formula("\\left(\\frac{a}{b}\\right)^2"),
+            "{t=\"scr\", base={t=\"fence\", body={t=\"frac\", num={t=\"text\", s=\"a\"}, den={t=\"text\", s=\"b\"}}, \
+open=\"(\", close=\")\"}, sup={t=\"text\", s=\"2\"}}"
+        );
+    }
+
+    /// Lua that stands in for the device: fixed-size fonts, and each
+    /// drawing call printed as a line
+    const STUB_GC: &str = r#"
+local size = 0
+local gc = {}
+function gc:setFont(family, style, s) size = s end
+function gc:getStringWidth(s) return #s * math.floor(size / 2) end
+function gc:getStringHeight(s) return size + 3 end
+function gc:drawString(s, x, y, align) print("string " .. s .. " " .. size) end
+function gc:drawLine(x1, y1, x2, y2) print("line") end
+"#;
+
+    #[test]
+    #[ignore = "needs lua on the PATH"]
+    fn test_runtime_draws_with_stub_gc() {
+        let script = format!(
+            "{}{}local formula = {}\n\
+math_measure(gc, formula, 1)\n\
+print(\"measured \" .. tostring(formula.w > 0 and formula.asc > 0 and formula.desc > 0))\n\
+math_draw(gc, formula, 0, formula.asc, 1)\n",
+            LUA_MATH_RUNTIME,
+            STUB_GC,
+            formula("\\left(\\frac{a}{b^2}\\right) + \\sum_{i=0}^{n} \\sqrt[3]{x}")
+        );
+        let path = std::env::temp_dir().join("test_math_runtime.lua");
+        std::fs::write(&path, script).unwrap();
+
+        let output = std::process::Command::new("lua").arg(&path).output().expect("lua is not installed");
+        let _ = std::fs::remove_file(&path);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        assert!(stdout.starts_with("measured true\n"), "{}", stdout);
+        // Scripts and limits are a size smaller, root indices two
+        for call in ["string a 11", "string b 11", "string 2 9", "string n 9", "string i=0 9", "string 3 7", "string x 11"] {
+            assert!(stdout.lines().any(|line| line == call), "no `{}` in:\n{}", call, stdout);
+        }
+        // Brackets, the fraction bar, the sum sign and the radical are lines
+        assert!(stdout.lines().filter(|line| *line == "line").count() >= 8, "{}", stdout);
+    }
+
+    #[test]
+    fn test_lua_string() {
+        assert_eq!(lua_string("say \"hi\" \\ ok\n"), "\"say \\\"hi\\\" \\\\ ok\\010\"");
+        assert_eq!(lua_string("π"), "\"π\"");
+    }
+}
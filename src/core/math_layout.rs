@@ -0,0 +1,557 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! Two-dimensional math layout
+//!
+//! An alternative back end to the linear text of `math_render`. Formulas are
+//! turned into a box model, [`Layout`], and drawn on a character grid the way
+//! CAS tools pretty-print: fractions are stacked over a bar, scripts that
+//! have no Unicode super/subscript form are raised or lowered, big operators
+//! get their limits above and below, and brackets grow with their contents.
+//!
+//! ```text
+//!  n
+//! SUM i²
+//! i=0
+//! ```
+//!
+//! Each grid cell holds one character, so the text viewer can line the
+//! columns up in its proportional font.
+
+use super::latex::Node;
+use super::math_render::{
+    argument_nodes, command_symbol, delimiter_char, environment_style, is_binomial, is_fraction, nodes_to_unicode,
+    script_chars, tall_bracket, RenderOptions, RenderedLine, TextBlock,
+};
+
+/// A formula as nested boxes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layout {
+    /// Text on one line
+    Text(String),
+    /// Boxes side by side on a common baseline
+    Row(Vec<Layout>),
+    Fraction { num: Box<Layout>, den: Box<Layout> },
+    Scripts {
+        base: Box<Layout>,
+        sub: Option<Box<Layout>>,
+        sup: Option<Box<Layout>>,
+    },
+    /// `\sum`, `\int`, `\lim`, ... with limits above and below
+    BigOp {
+        /// The command name, e.g. `sum`
+        name: String,
+        symbol: String,
+        over: Option<Box<Layout>>,
+        under: Option<Box<Layout>>,
+    },
+    Root { index: Option<String>, body: Box<Layout> },
+    /// Contents between brackets as tall as the contents
+    Fenced {
+        open: Option<char>,
+        close: Option<char>,
+        body: Box<Layout>,
+    },
+    /// Rows of cells, `spacing` apart
+    Matrix { rows: Vec<Vec<Layout>>, spacing: usize },
+}
+
+/// Commands whose scripts become limits above and below
+pub fn is_big_operator(name: &str) -> bool {
+    matches!(
+        name,
+        "sum" | "prod" | "coprod" | "int" | "oint" | "iint" | "iiint" | "bigcup" | "bigcap" | "bigoplus"
+            | "bigotimes" | "lim" | "limsup" | "liminf" | "max" | "min" | "sup" | "inf"
+    )
+}
+
+/// Lay out a list of nodes
+pub fn layout(nodes: &[Node], options: &RenderOptions) -> Layout {
+    let mut items = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+
+    while i < nodes.len() {
+        // \left( ... \right) around the nodes in between
+        if let Node::Command { name, args, .. } = &nodes[i]
+            && name == "left"
+            && let Some(end) = matching_right(nodes, i)
+        {
+            let close = match right_command(&nodes[end]) {
+                Some(Node::Command { args, .. }) => args.first().and_then(delimiter_char),
+                _ => None,
+            };
+            if !text.is_empty() {
+                items.push(Layout::Text(std::mem::take(&mut text)));
+            }
+            let fenced = Layout::Fenced {
+                open: args.first().and_then(delimiter_char),
+                close,
+                body: Box::new(layout(&nodes[i + 1..end], options)),
+            };
+            items.push(attach_right_scripts(&nodes[end], fenced, options));
+            i = end + 1;
+            continue;
+        }
+
+        match layout_node(&nodes[i], options) {
+            Some(item) => {
+                if !text.is_empty() {
+                    items.push(Layout::Text(std::mem::take(&mut text)));
+                }
+                items.push(item);
+            }
+            None => text.push_str(&nodes_to_unicode(std::slice::from_ref(&nodes[i]), options)),
+        }
+        i += 1;
+    }
+
+    if !text.is_empty() {
+        items.push(Layout::Text(text));
+    }
+    if items.len() == 1 {
+        items.pop().expect("one item")
+    } else {
+        Layout::Row(items)
+    }
+}
+
+/// Index of the `\right` that closes the `\left` at `start`
+fn matching_right(nodes: &[Node], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, node) in nodes.iter().enumerate().skip(start) {
+        if matches!(node, Node::Command { name, .. } if name == "left") {
+            depth += 1;
+        } else if right_command(node).is_some() {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// The `\right` of a node, which is the base of any scripts after it
+fn right_command(node: &Node) -> Option<&Node> {
+    match node {
+        Node::Command { name, .. } if name == "right" => Some(node),
+        Node::Script { base: Some(base), .. } => right_command(base),
+        _ => None,
+    }
+}
+
+/// Move scripts written after `\right` onto the whole fenced box
+fn attach_right_scripts(right: &Node, fenced: Layout, options: &RenderOptions) -> Layout {
+    match right {
+        Node::Script { base: Some(base), sub, sup } => {
+            let arg = |arg: &Option<Box<Node>>| arg.as_deref().map(|arg| Box::new(layout_argument(arg, options)));
+            Layout::Scripts { base: Box::new(attach_right_scripts(base, fenced, options)), sub: arg(sub), sup: arg(sup) }
+        }
+        _ => fenced,
+    }
+}
+
+/// The layout of a structural node, or None for plain text
+fn layout_node(node: &Node, options: &RenderOptions) -> Option<Layout> {
+    match node {
+        Node::Group { children, closed: true } => Some(layout(children, options)),
+        Node::Command { name, args, .. }
+            if is_fraction(name)
+                && let [num, den] = args.as_slice()
+                && let (Some(num), Some(den)) = (argument_nodes(num), argument_nodes(den)) =>
+        {
+            Some(Layout::Fraction { num: Box::new(layout(num, options)), den: Box::new(layout(den, options)) })
+        }
+        Node::Command { name, args, .. }
+            if is_binomial(name)
+                && let [n, k] = args.as_slice()
+                && let (Some(n), Some(k)) = (argument_nodes(n), argument_nodes(k)) =>
+        {
+            let matrix = Layout::Matrix { rows: vec![vec![layout(n, options)], vec![layout(k, options)]], spacing: 0 };
+            Some(Layout::Fenced { open: Some('('), close: Some(')'), body: Box::new(matrix) })
+        }
+        Node::Command { name, optional, args }
+            if name == "sqrt"
+                && let [body] = args.as_slice()
+                && let Some(body) = argument_nodes(body) =>
+        {
+            let index = optional.as_deref().map(|index| nodes_to_unicode(index, options).trim().to_string());
+            Some(Layout::Root {
+                index: index.filter(|index| !index.is_empty() && index != "2"),
+                body: Box::new(layout(body, options)),
+            })
+        }
+        Node::Script { base, sub, sup } => {
+            let arg = |arg: &Option<Box<Node>>| arg.as_deref().map(|arg| Box::new(layout_argument(arg, options)));
+            match base.as_deref() {
+                Some(Node::Command { name, args, .. }) if args.is_empty() && is_big_operator(name) => {
+                    Some(Layout::BigOp {
+                        name: name.clone(),
+                        symbol: command_symbol(name, options).unwrap_or(name).to_string(),
+                        over: arg(sup),
+                        under: arg(sub),
+                    })
+                }
+                _ => Some(Layout::Scripts {
+                    base: Box::new(base.as_deref().map_or(Layout::Text(String::new()), |base| layout_argument(base, options))),
+                    sub: arg(sub),
+                    sup: arg(sup),
+                }),
+            }
+        }
+        Node::Command { name, args, .. } if args.is_empty() && is_big_operator(name) => Some(Layout::BigOp {
+            name: name.clone(),
+            symbol: command_symbol(name, options).unwrap_or(name).to_string(),
+            over: None,
+            under: None,
+        }),
+        Node::Environment { name, rows, .. } => {
+            let (open, close, spacing) = environment_style(name);
+            let matrix = Layout::Matrix {
+                rows: rows.iter().map(|row| row.iter().map(|cell| layout(trim_spaces(cell), options)).collect()).collect(),
+                spacing: spacing.len(),
+            };
+            Some(Layout::Fenced { open, close, body: Box::new(matrix) })
+        }
+        _ => None,
+    }
+}
+
+/// A script or argument: the contents of a group, or a single node
+fn layout_argument(node: &Node, options: &RenderOptions) -> Layout {
+    match node {
+        Node::Group { children, closed: true } => layout(children, options),
+        _ => layout(std::slice::from_ref(node), options),
+    }
+}
+
+/// A cell without its surrounding whitespace
+fn trim_spaces(nodes: &[Node]) -> &[Node] {
+    let is_space = |node: &Node| matches!(node, Node::Char(c) if c.is_whitespace());
+    let start = nodes.iter().position(|node| !is_space(node)).unwrap_or(nodes.len());
+    let end = nodes.iter().rposition(|node| !is_space(node)).map_or(start, |i| i + 1);
+    &nodes[start..end]
+}
+
+/// A rectangle of characters with a baseline row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid {
+    pub rows: Vec<Vec<char>>,
+    pub baseline: usize,
+}
+
+impl Grid {
+    fn blank(width: usize, height: usize) -> Self {
+        Self { rows: vec![vec![' '; width]; height], baseline: 0 }
+    }
+
+    fn text(text: &str) -> Self {
+        Self { rows: vec![text.chars().collect()], baseline: 0 }
+    }
+
+    pub fn width(&self) -> usize {
+        self.rows.first().map_or(0, Vec::len)
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Copy `other` into this grid with its top-left corner at (x, y)
+    fn blit(&mut self, other: &Grid, x: usize, y: usize) {
+        for (dy, row) in other.rows.iter().enumerate() {
+            self.rows[y + dy][x..x + row.len()].copy_from_slice(row);
+        }
+    }
+
+    /// Place grids side by side with their baselines lined up
+    fn beside(grids: &[Grid]) -> Self {
+        let above = grids.iter().map(|g| g.baseline).max().unwrap_or(0);
+        let below = grids.iter().map(|g| g.height() - g.baseline).max().unwrap_or(1);
+        let mut result = Grid::blank(grids.iter().map(Grid::width).sum(), above + below);
+        result.baseline = above;
+
+        let mut x = 0;
+        for grid in grids {
+            result.blit(grid, x, above - grid.baseline);
+            x += grid.width();
+        }
+        result
+    }
+
+    /// Stack grids centered on top of each other; `baseline` is a row of
+    /// the part at index `baseline_part`
+    fn stack(parts: &[Grid], baseline_part: usize) -> Self {
+        let width = parts.iter().map(Grid::width).max().unwrap_or(0);
+        let mut result = Grid::blank(width, parts.iter().map(Grid::height).sum());
+
+        let mut y = 0;
+        for (i, part) in parts.iter().enumerate() {
+            if i == baseline_part {
+                result.baseline = y + part.baseline;
+            }
+            result.blit(part, (width - part.width()) / 2, y);
+            y += part.height();
+        }
+        result
+    }
+
+    /// The single row as text, if the grid is one row high
+    fn single_line(&self) -> Option<String> {
+        match self.rows.as_slice() {
+            [row] => Some(row.iter().collect()),
+            _ => None,
+        }
+    }
+
+    /// Each character in its own cell, so columns line up in the viewer
+    pub fn to_block(&self) -> TextBlock {
+        TextBlock { rows: self.rows.iter().map(|row| row.iter().map(char::to_string).collect()).collect() }
+    }
+}
+
+/// Draw a layout on a character grid
+pub fn render(layout: &Layout, options: &RenderOptions) -> Grid {
+    match layout {
+        Layout::Text(text) => Grid::text(text),
+        Layout::Row(items) => Grid::beside(&items.iter().map(|item| render(item, options)).collect::<Vec<_>>()),
+        Layout::Fraction { num, den } => {
+            let (num, den) = (render(num, options), render(den, options));
+            let bar = Grid::text(&options.glyphs.pick('─', '-').to_string().repeat(num.width().max(den.width()) + 2));
+            Grid::stack(&[num, bar, den], 1)
+        }
+        Layout::Scripts { base, sub, sup } => render_scripts(render(base, options), sub.as_deref(), sup.as_deref(), options),
+        Layout::BigOp { symbol, over, under, .. } => {
+            let mut parts = Vec::new();
+            parts.extend(over.as_deref().map(|over| render(over, options)));
+            let symbol_part = parts.len();
+            parts.push(Grid::text(symbol));
+            parts.extend(under.as_deref().map(|under| render(under, options)));
+            Grid::stack(&parts, symbol_part)
+        }
+        Layout::Root { index, body } => render_root(index.as_deref(), render(body, options), options),
+        Layout::Fenced { open, close, body } => {
+            let body = render(body, options);
+            let bracket = |c: char| Grid {
+                rows: tall_bracket(c, body.height(), options).iter().map(|piece| piece.chars().collect()).collect(),
+                baseline: body.baseline,
+            };
+            let (open, close) = (open.map(bracket), close.map(bracket));
+            let mut parts: Vec<Grid> = open.into_iter().collect();
+            parts.push(body);
+            parts.extend(close);
+            Grid::beside(&parts)
+        }
+        Layout::Matrix { rows, spacing } => render_matrix(rows, *spacing, options),
+    }
+}
+
+/// Scripts as Unicode characters when possible, otherwise raised/lowered
+fn render_scripts(base: Grid, sub: Option<&Layout>, sup: Option<&Layout>, options: &RenderOptions) -> Grid {
+    let sub = sub.map(|sub| render(sub, options));
+    let sup = sup.map(|sup| render(sup, options));
+
+    // x², aₙ: keep it on one line
+    if let Some(base_text) = base.single_line() {
+        let inline = |script: &Option<Grid>, superscript: bool| match script {
+            None => Some(String::new()),
+            Some(grid) => grid.single_line().and_then(|text| script_chars(&text, superscript, options)),
+        };
+        if let (Some(sub_text), Some(sup_text)) = (inline(&sub, false), inline(&sup, true)) {
+            return Grid::text(&format!("{}{}{}", base_text, sub_text, sup_text));
+        }
+    }
+
+    let sup_height = sup.as_ref().map_or(0, Grid::height);
+    let sub_height = sub.as_ref().map_or(0, Grid::height);
+    let script_width = sup.iter().chain(&sub).map(Grid::width).max().unwrap_or(0);
+
+    let mut result = Grid::blank(base.width() + script_width, sup_height + base.height() + sub_height);
+    result.blit(&base, 0, sup_height);
+    if let Some(sup) = &sup {
+        result.blit(sup, base.width(), 0);
+    }
+    if let Some(sub) = &sub {
+        result.blit(sub, base.width(), sup_height + base.height());
+    }
+    result.baseline = sup_height + base.baseline;
+    result
+}
+
+/// A radical with a bar over the radicand
+///
+/// ```text
+///  ______
+/// √b²-4ac
+/// ```
+fn render_root(index: Option<&str>, body: Grid, options: &RenderOptions) -> Grid {
+    let sign = options.glyphs.pick('√', 'V');
+    let (sign, index) = match index {
+        Some("3") if options.glyphs.shows('∛') => ('∛', ""),
+        Some("4") if options.glyphs.shows('∜') => ('∜', ""),
+        Some(index) => (sign, index),
+        None => (sign, ""),
+    };
+    let index_width = index.chars().count();
+
+    let mut result = Grid::blank(index_width + 1 + body.width(), body.height() + 1);
+    result.blit(&Grid::text(index), 0, 0);
+    result.blit(&Grid::text(&"_".repeat(body.width())), index_width + 1, 0);
+    for y in 1..result.height() {
+        result.rows[y][index_width] = if y == result.height() - 1 { sign } else { options.glyphs.pick('│', '|') };
+    }
+    result.blit(&body, index_width + 1, 1);
+    result.baseline = body.baseline + 1;
+    result
+}
+
+/// Cells in columns as wide as their widest cell, rows on their baselines
+fn render_matrix(rows: &[Vec<Layout>], spacing: usize, options: &RenderOptions) -> Grid {
+    let cells: Vec<Vec<Grid>> = rows.iter().map(|row| row.iter().map(|cell| render(cell, options)).collect()).collect();
+    let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|col| cells.iter().filter_map(|row| row.get(col)).map(Grid::width).max().unwrap_or(0))
+        .collect();
+
+    let lines: Vec<Grid> = cells
+        .iter()
+        .map(|row| {
+            let mut parts = Vec::new();
+            for (col, &width) in widths.iter().enumerate() {
+                if col > 0 {
+                    parts.push(Grid::blank(spacing, 1));
+                }
+                let mut cell = row.get(col).cloned().unwrap_or_else(|| Grid::blank(0, 1));
+                for line in &mut cell.rows {
+                    line.resize(width, ' ');
+                }
+                parts.push(cell);
+            }
+            Grid::beside(&parts)
+        })
+        .collect();
+
+    let width = lines.iter().map(Grid::width).max().unwrap_or(0);
+    let mut result = Grid::blank(width, lines.iter().map(Grid::height).sum::<usize>().max(1));
+    let mut y = 0;
+    for line in &lines {
+        result.blit(line, 0, y);
+        y += line.height();
+    }
+    result.baseline = (result.height() - 1) / 2;
+    result
+}
+
+/// Render one line of LaTeX text in two dimensions
+///
+/// A line that fits on one row stays ordinary text; anything taller becomes
+/// a block. `render_latex` calls this for each math region of a line too,
+/// so a block holds just its formula and the prose stays wrappable.
+pub fn render_line(nodes: &[Node], options: &RenderOptions) -> RenderedLine {
+    let grid = render(&layout(nodes, options), options);
+    match grid.single_line() {
+        Some(text) => RenderedLine::Text(text),
+        None if grid.height() == 0 => RenderedLine::Text(String::new()),
+        None => RenderedLine::Block(grid.to_block()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::glyphs::GlyphProfile;
+    use crate::core::latex;
+
+    /// Lay out LaTeX text in two dimensions as plain lines
+    fn lines(input: &str) -> Vec<String> {
+        lines_for(input, &RenderOptions::default())
+    }
+
+    /// Like `lines`, with a profile that has the bracket pieces and lines
+    fn drawn_lines(input: &str) -> Vec<String> {
+        let glyphs = GlyphProfile::from_toml("available = \"⎛⎜⎝⎞⎟⎠⎡⎢⎣⎤⎥⎦⎧⎨⎩⎪⎫⎬⎭│─\"").unwrap();
+        lines_for(input, &RenderOptions { glyphs, ..Default::default() })
+    }
+
+    fn lines_for(input: &str, options: &RenderOptions) -> Vec<String> {
+        latex::parse(input)
+            .split(|node| *node == Node::Char('\n'))
+            .flat_map(|line| {
+                let grid = render(&layout(line, options), options);
+                grid.rows.iter().map(|row| row.iter().collect::<String>().trim_end().to_string()).collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_layout_tree() {
+        let nodes = latex::parse("\\frac{1}{x^{a}}");
+        assert_eq!(
+            layout(&nodes, &RenderOptions::default()),
+            Layout::Fraction {
+                num: Box::new(Layout::Text("1".to_string())),
+                den: Box::new(Layout::Scripts {
+                    base: Box::new(Layout::Text("x".to_string())),
+                    sub: None,
+                    sup: Some(Box::new(Layout::Text("a".to_string()))),
+                }),
+            }
+        );
+    }
+
+    #[test]
+    fn test_stacked_fraction() {
+        assert_eq!(lines("x = \\frac{a+b}{2}"), vec!["     a+b", "x = -----", "      2"]);
+        assert_eq!(lines("\\frac{\\frac{1}{2}}{3}"), vec!["  1", " ---", "  2", "-----", "  3"]);
+        assert_eq!(lines("\\binom{n}{k}"), vec!["/n\\", "\\k/"]);
+        assert_eq!(drawn_lines("x = \\frac{a+b}{2}"), vec!["     a+b", "x = ─────", "      2"]);
+        assert_eq!(drawn_lines("\\binom{n}{k}"), vec!["⎛n⎞", "⎝k⎠"]);
+    }
+
+    #[test]
+    fn test_scripts() {
+        // Unicode scripts stay inline
+        assert_eq!(lines("x^2 + a_n"), vec!["x² + aₙ"]);
+        // Others are raised and lowered
+        assert_eq!(lines("e^{ab} + x_{b}"), vec![" ab", "e   + x", "       b"]);
+    }
+
+    #[test]
+    fn test_big_operator_limits() {
+        assert_eq!(lines("\\sum_{i=0}^{n} i^2"), vec![" n", "SUM i²", "i=0"]);
+        assert_eq!(lines("\\int_a^b f"), vec![" b", "INT f", " a"]);
+        assert_eq!(lines("\\lim_{x \\to 0} f"), vec![" lim   f", "x -> 0"]);
+    }
+
+    #[test]
+    fn test_roots() {
+        assert_eq!(lines("\\sqrt{b^2-4ac}"), vec![" ______", "√b²-4ac"]);
+        assert_eq!(lines("\\sqrt[3]{x}"), vec![" _", "∛x"]);
+        assert_eq!(lines("\\sqrt{\\frac{1}{2}}"), vec![" ___", "| 1", "|---", "√ 2"]);
+        assert_eq!(drawn_lines("\\sqrt{\\frac{1}{2}}"), vec![" ___", "│ 1", "│───", "√ 2"]);
+    }
+
+    #[test]
+    fn test_growing_brackets() {
+        assert_eq!(lines("\\left( \\frac{a}{b} \\right)"), vec!["/  a  \\", "| --- |", "\\  b  /"]);
+        assert_eq!(drawn_lines("\\left( \\frac{a}{b} \\right)"), vec!["⎛  a  ⎞", "⎜ ─── ⎟", "⎝  b  ⎠"]);
+        assert_eq!(lines("\\left( x \\right)"), vec!["( x )"]);
+        assert_eq!(
+            drawn_lines("\\begin{pmatrix} 1 & \\frac{1}{2} \\\\ 0 & 1 \\end{pmatrix}"),
+            vec!["⎛    1 ⎞", "⎜1  ───⎟", "⎜    2 ⎟", "⎝0  1  ⎠"]
+        );
+        // Scripts after \right belong to the whole fenced box
+        assert_eq!(lines("\\left(\\frac{a}{b}\\right)^2"), vec!["     2", "/ a \\", "|---|", "\\ b /"]);
+        assert_eq!(lines("\\left.\\frac{x^2}{2}\\right|_a^b"), vec!["     b", " x² |", "----|", " 2  |", "     a"]);
+    }
+
+    #[test]
+    fn test_render_line() {
+        assert_eq!(render_line(&latex::parse("plain text"), &RenderOptions::default()), RenderedLine::Text("plain text".to_string()));
+        let RenderedLine::Block(block) = render_line(&latex::parse("\\frac{1}{n}"), &RenderOptions::default()) else {
+            panic!("expected a block");
+        };
+        assert_eq!(block.rows[1], vec!["-", "-", "-"]);
+    }
+}
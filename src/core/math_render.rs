@@ -9,6 +9,12 @@
 use std::collections::HashMap;
 use std::sync::LazyLock;
 
+use super::glyphs::GlyphProfile;
+use super::latex::{self, Macros, Node, Segment};
+use super::symbols::SymbolTable;
+use super::math_draw;
+use super::math_layout::{self, Layout};
+
 /// LaTeX command to Unicode mapping
 static LATEX_MAP: LazyLock<HashMap<&'static str, &'static str>> = LazyLock::new(|| {
     let mut m = HashMap::new();
@@ -156,7 +162,40 @@ static LATEX_MAP: LazyLock<HashMap<&'static str, &'static str>> = LazyLock::new(
     m.insert("\\ell", "l");
     m.insert("\\prime", "'");
     m.insert("\\degree", "deg");
+
+    // Operator names, set upright as in \operatorname
+    m.insert("\\sin", "sin");
+    m.insert("\\cos", "cos");
+    m.insert("\\tan", "tan");
+    m.insert("\\cot", "cot");
+    m.insert("\\sec", "sec");
+    m.insert("\\csc", "csc");
+    m.insert("\\arcsin", "arcsin");
+    m.insert("\\arccos", "arccos");
+    m.insert("\\arctan", "arctan");
+    m.insert("\\sinh", "sinh");
+    m.insert("\\cosh", "cosh");
+    m.insert("\\tanh", "tanh");
+    m.insert("\\coth", "coth");
+    m.insert("\\log", "log");
+    m.insert("\\ln", "ln");
+    m.insert("\\lg", "lg");
+    m.insert("\\exp", "exp");
+    m.insert("\\lim", "lim");
+    m.insert("\\limsup", "limsup");
+    m.insert("\\liminf", "liminf");
+    m.insert("\\max", "max");
+    m.insert("\\min", "min");
+    m.insert("\\sup", "sup");
+    m.insert("\\inf", "inf");
+    m.insert("\\det", "det");
+    m.insert("\\dim", "dim");
+    m.insert("\\ker", "ker");
+    m.insert("\\hom", "hom");
+    m.insert("\\arg", "arg");
     m.insert("\\deg", "deg");
+    m.insert("\\gcd", "gcd");
+    m.insert("\\Pr", "Pr");
 
     // Roots and fractions (simple representations)
     m.insert("\\sqrt", "√");
@@ -184,6 +223,13 @@ static LATEX_MAP: LazyLock<HashMap<&'static str, &'static str>> = LazyLock::new(
     m.insert("\\!", "");       // negative thin space (remove)
     m.insert("\\quad", "  ");  // quad space
     m.insert("\\qquad", "    "); // double quad
+    m.insert("\\$", "$");
+    m.insert("\\%", "%");
+    m.insert("\\&", "&");
+    m.insert("\\#", "#");
+    m.insert("\\_", "_");
+    m.insert("\\{", "{");
+    m.insert("\\}", "}");
     m.insert("\\ldots", "…");
     m.insert("\\cdots", "⋯");
     m.insert("\\vdots", "⋮");
@@ -268,6 +314,72 @@ static SUBSCRIPTS: LazyLock<HashMap<char, char>> = LazyLock::new(|| {
     m
 });
 
+/// Unicode symbol to LaTeX, the reverse of [`LATEX_MAP`]
+///
+/// Extended symbols are used in place of their ASCII fallbacks, and where
+/// several commands give the same symbol the shortest wins (`≤` → `\le`).
+/// Other ASCII results (`->`, `SUM`, `in`) are left out, since they are
+/// ordinary text as well.
+static UNICODE_MAP: LazyLock<HashMap<char, String>> = LazyLock::new(|| {
+    let mut m: HashMap<char, String> = HashMap::new();
+
+    for (command, symbol) in LATEX_MAP.iter() {
+        let name = &command[1..];
+        let symbol = extended_symbol(name).unwrap_or(symbol);
+        let mut chars = symbol.chars();
+        let (Some(c), None) = (chars.next(), chars.next()) else {
+            continue;
+        };
+        // Roots take an argument, see `write_latex`
+        if c.is_ascii() || matches!(name, "sqrt" | "cbrt") {
+            continue;
+        }
+
+        let latex = match name.strip_prefix("frac").map(|digits| digits.chars().collect::<Vec<_>>()) {
+            Some(digits) => format!("\\frac{{{}}}{{{}}}", digits[0], digits[1]),
+            None => command.to_string(),
+        };
+        let shorter = |existing: &String| (latex.len(), &latex) < (existing.len(), existing);
+        if m.get(&c).is_none_or(shorter) {
+            m.insert(c, latex);
+        }
+    }
+
+    for letter in 'A'..='Z' {
+        let bold = blackboard_bold(letter, &RenderOptions::default());
+        if bold != letter {
+            m.insert(bold, format!("\\mathbb{{{}}}", letter));
+        }
+    }
+    m
+});
+
+/// ASCII fallbacks of extended symbols and the command they stand for,
+/// longest first (`Delta` → `\Delta`, `->` → `\to`)
+///
+/// Only whole words of two or more letters and arrows are listed. Single
+/// letters (`d` for `\partial`), `in` and fallbacks such as `<=` or `{}`
+/// read just as well as ordinary text.
+static FALLBACK_MAP: LazyLock<Vec<(&'static str, String)>> = LazyLock::new(|| {
+    const ARROWS: [&str; 6] = ["->", "<-", "<->", "=>", "<=>", "|->"];
+
+    let mut fallbacks: Vec<(&'static str, String)> = LATEX_MAP
+        .iter()
+        .filter(|(_, fallback)| {
+            let word = fallback.len() >= 2 && fallback.chars().all(|c| c.is_ascii_alphabetic());
+            (word && !matches!(**fallback, "in" | "ni")) || ARROWS.contains(fallback)
+        })
+        .filter_map(|(command, fallback)| {
+            let symbol = extended_symbol(&command[1..])?.chars().next()?;
+            Some((*fallback, UNICODE_MAP.get(&symbol)?.clone()))
+        })
+        .collect();
+    fallbacks.sort();
+    fallbacks.dedup_by(|a, b| a.0 == b.0);
+    fallbacks.sort_by_key(|(fallback, _)| std::cmp::Reverse(fallback.len()));
+    fallbacks
+});
+
 /// Convert LaTeX-style math notation to Unicode
 ///
 /// Supports:
@@ -276,139 +388,958 @@ static SUBSCRIPTS: LazyLock<HashMap<char, char>> = LazyLock::new(|| {
 /// - Symbols: \infty, \sum, \int, \partial, etc.
 /// - Superscripts: x^2 → x², x^{10} → x¹⁰
 /// - Subscripts: x_1 → x₁, x_{10} → x₁₀
-/// - Simple fractions: \frac12 → ½
+/// - Fractions: \frac12 → ½, \frac{a+b}{c} → (a+b)/c
+/// - Roots: \sqrt{b^2-4ac} → √(b²-4ac), \sqrt[3]{x} → ∛x
+/// - Text and fonts: \text{if }, \operatorname{Var}, \mathbb{R} → ℝ
+///
+/// The input is parsed with [`latex::parse`]; anything this back end has no
+/// rendering for is written back as its LaTeX source. Symbols are chosen for
+/// the default glyph profile, see [`latex_to_unicode_for`].
 pub fn latex_to_unicode(input: &str) -> String {
-    let mut result = String::with_capacity(input.len());
-    let chars: Vec<char> = input.chars().collect();
+    latex_to_unicode_for(input, &GlyphProfile::default())
+}
+
+/// Convert LaTeX math to Unicode, using the best glyphs a device font has
+///
+/// Symbols the font lacks fall back to ASCII (`Delta`, `SUM`, `->`), and
+/// fraction glyphs, script characters and bracket pieces it lacks are
+/// written out (`1/8`, `x^(2)` as `x(2)`, `/ | \`).
+pub fn latex_to_unicode_for(input: &str, glyphs: &GlyphProfile) -> String {
+    let options = RenderOptions { scope: MathScope::Document, glyphs: glyphs.clone(), ..Default::default() };
+    let lines: Vec<String> = render_latex(input, &options)
+        .into_iter()
+        .map(|line| match line {
+            RenderedLine::Text(text) => text,
+            RenderedLine::Block(block) => block.to_padded_lines().join("\n"),
+            RenderedLine::Formula(layout) => math_layout::render(&layout, &options).to_block().to_padded_lines().join("\n"),
+        })
+        .collect();
+    lines.join("\n")
+}
+
+/// Convert Unicode math, as written by [`latex_to_unicode`], back to LaTeX
+///
+/// Symbols become commands (`α` → `\alpha`, `ℝ` → `\mathbb{R}`), runs of
+/// script characters become scripts (`x¹⁰` → `x^{10}`, `H₂O` → `H_2O`),
+/// fraction glyphs become `\frac{1}{2}` and roots become `\sqrt`. ASCII
+/// fallbacks in [`FALLBACK_MAP`] become commands too when they stand as a
+/// whole word (`SUM`, `Delta`, `inf`) or are an arrow (`->`). Scripts that
+/// fell back to parentheses, `x(abc)`, can't be told apart from `f(x)` and
+/// are kept, like everything else.
+///
+/// The result is LaTeX for [`MathScope::Document`]: no delimiters are
+/// added. A space is put between a command and a letter after it, so `αx`
+/// comes back as `α x`.
+pub fn unicode_to_latex(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut latex = String::new();
+    write_latex(&chars, &mut latex);
+    latex
+}
+
+fn write_latex(chars: &[char], out: &mut String) {
     let mut i = 0;
 
-    while i < chars.len() {
-        if chars[i] == '\\' {
-            // Try to match a LaTeX command
-            if let Some((replacement, consumed)) = try_match_command(&chars, i) {
-                result.push_str(replacement);
-                i += consumed;
-                continue;
-            }
-        } else if chars[i] == '^' {
-            // Superscript
-            if let Some((superscript, consumed)) = convert_script(&chars, i + 1, &SUPERSCRIPTS) {
-                result.push_str(&superscript);
-                i += 1 + consumed;
-                continue;
+    while let Some(&c) = chars.get(i) {
+        let root = match c {
+            '√' => Some(""),
+            '∛' => Some("[3]"),
+            '∜' => Some("[4]"),
+            _ => None,
+        };
+        if let Some(index) = root
+            && i + 1 < chars.len()
+        {
+            // √(b²-4ac): the parentheses only group the radicand
+            let end = radicand_end(chars, i + 1);
+            let radicand = match &chars[i + 1..end] {
+                ['(', inner @ .., ')'] => inner,
+                radicand => radicand,
+            };
+            out.push_str("\\sqrt");
+            out.push_str(index);
+            out.push('{');
+            write_latex(radicand, out);
+            out.push('}');
+            i = end;
+            continue;
+        }
+
+        let superscript = script_run(&chars[i..], &SUPERSCRIPTS);
+        let subscript = script_run(&chars[i..], &SUBSCRIPTS);
+        let (marker, script) = if superscript.is_empty() { ('_', subscript) } else { ('^', superscript) };
+        if !script.is_empty() {
+            i += script.chars().count();
+            out.push(marker);
+            if script.chars().count() == 1 {
+                out.push_str(&script);
+            } else {
+                out.push_str(&format!("{{{}}}", script));
             }
-        } else if chars[i] == '_' {
-            // Subscript
-            if let Some((subscript, consumed)) = convert_script(&chars, i + 1, &SUBSCRIPTS) {
-                result.push_str(&subscript);
-                i += 1 + consumed;
-                continue;
+            continue;
+        }
+
+        if let Some((fallback, command)) = fallback_at(chars, i) {
+            i += fallback.len();
+            out.push_str(command);
+            if chars.get(i).is_some_and(|c| c.is_ascii_alphabetic()) {
+                out.push(' ');
             }
+            continue;
         }
 
-        result.push(chars[i]);
+        match UNICODE_MAP.get(&c) {
+            Some(command) => {
+                out.push_str(command);
+                // `\alpha x`, not the undefined `\alphax`
+                if command.ends_with(|c: char| c.is_ascii_alphabetic())
+                    && chars.get(i + 1).is_some_and(|c| c.is_ascii_alphabetic())
+                {
+                    out.push(' ');
+                }
+            }
+            None => out.push(c),
+        }
         i += 1;
     }
+}
+
+/// The ASCII fallback starting at `i` and its command, if it stands alone
+fn fallback_at(chars: &[char], i: usize) -> Option<&'static (&'static str, String)> {
+    let is_word_char = |c: &char| c.is_ascii_alphanumeric();
+    if chars[i].is_ascii_alphabetic() {
+        if i > 0 && is_word_char(&chars[i - 1]) {
+            return None;
+        }
+        let word: String = chars[i..].iter().take_while(|c| is_word_char(c)).collect();
+        return FALLBACK_MAP.iter().find(|(fallback, _)| *fallback == word);
+    }
+    FALLBACK_MAP.iter().find(|(fallback, _)| {
+        fallback.chars().count() <= chars.len() - i && fallback.chars().zip(&chars[i..]).all(|(a, b)| a == *b)
+    })
+}
+
+/// The characters a run of super- or subscript characters stands for
+fn script_run(chars: &[char], map: &HashMap<char, char>) -> String {
+    chars
+        .iter()
+        .map_while(|&c| map.iter().find(|&(_, &script)| script == c).map(|(&base, _)| base))
+        .collect()
+}
+
+/// Where the radicand after a root sign ends: a `(...)` group, a number or
+/// one character, with the scripts after it (the reverse of [`is_atom`])
+fn radicand_end(chars: &[char], start: usize) -> usize {
+    let mut end = start;
+    if chars[start] == '(' {
+        let mut depth = 0;
+        for (i, &c) in chars.iter().enumerate().skip(start) {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            end = i + 1;
+            if depth == 0 {
+                break;
+            }
+        }
+    } else if chars[start].is_ascii_digit() {
+        end += chars[start..].iter().take_while(|c| c.is_ascii_digit() || **c == '.').count();
+    } else {
+        end += 1;
+    }
 
-    result
+    let is_script = |c: &char| SUPERSCRIPTS.values().chain(SUBSCRIPTS.values()).any(|s| s == c);
+    end + chars[end..].iter().take_while(|c| is_script(c)).count()
 }
 
-/// Try to match a LaTeX command starting at position `start`
-fn try_match_command(chars: &[char], start: usize) -> Option<(&'static str, usize)> {
-    // Build the command string character by character
-    let mut cmd = String::with_capacity(16);
-    let mut i = start;
+/// A line of rendered text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RenderedLine {
+    /// Ordinary text that may be word-wrapped
+    Text(String),
+    /// Rows of aligned cells that must be kept together and never wrapped
+    Block(TextBlock),
+    /// A formula for the viewer to draw itself
+    Formula(Layout),
+}
 
-    // Include the backslash
-    cmd.push(chars[i]);
-    i += 1;
+/// A multi-line block of text laid out in columns
+///
+/// Every row has the same number of cells. Cells in a column are aligned
+/// by the viewer (or padded with spaces in plain text), so matrices and
+/// aligned equations keep their shape in a proportional font.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextBlock {
+    pub rows: Vec<Vec<String>>,
+}
 
-    // Collect command name (letters only for standard commands)
-    while i < chars.len() && chars[i].is_ascii_alphabetic() {
-        cmd.push(chars[i]);
-        i += 1;
+impl TextBlock {
+    fn text(text: String) -> Self {
+        Self { rows: vec![vec![text]] }
     }
 
-    // Check for special commands like \frac12
-    if cmd == "\\frac" && i + 1 < chars.len() {
-        let frac_cmd = format!("\\frac{}{}", chars[i], chars[i + 1]);
-        if let Some(&replacement) = LATEX_MAP.get(frac_cmd.as_str()) {
-            return Some((replacement, i + 2 - start));
+    /// A single column, one cell per row
+    fn column(cells: Vec<String>) -> Self {
+        Self { rows: cells.into_iter().map(|cell| vec![cell]).collect() }
+    }
+
+    fn height(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn width(&self) -> usize {
+        self.rows.first().map_or(0, Vec::len)
+    }
+
+    /// Place blocks side by side, centered vertically
+    fn beside(blocks: Vec<TextBlock>) -> Self {
+        let height = blocks.iter().map(TextBlock::height).max().unwrap_or(0);
+        let mut rows = vec![Vec::new(); height];
+        for block in blocks {
+            let top = (height - block.height()) / 2;
+            let width = block.width();
+            for (i, row) in rows.iter_mut().enumerate() {
+                match i.checked_sub(top).and_then(|j| block.rows.get(j)) {
+                    Some(cells) => row.extend(cells.iter().cloned()),
+                    None => row.extend(std::iter::repeat_n(String::new(), width)),
+                }
+            }
         }
+        Self { rows }
     }
 
-    // Try to find in map
-    if let Some(&replacement) = LATEX_MAP.get(cmd.as_str()) {
-        return Some((replacement, i - start));
+    /// The rows as plain text, with each column padded to the same width
+    pub fn to_padded_lines(&self) -> Vec<String> {
+        let widths: Vec<usize> = (0..self.width())
+            .map(|col| self.rows.iter().map(|row| row[col].chars().count()).max().unwrap_or(0))
+            .collect();
+        self.rows
+            .iter()
+            .map(|row| {
+                let line: String = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, &width)| format!("{:<width$}", cell, width = width))
+                    .collect();
+                line.trim_end().to_string()
+            })
+            .collect()
     }
+}
 
-    // Check for single-char special commands like \, \; \: \!
-    if start + 1 < chars.len() {
-        let special_cmd: String = chars[start..=start + 1].iter().collect();
-        if let Some(&replacement) = LATEX_MAP.get(special_cmd.as_str()) {
-            return Some((replacement, 2));
+/// How formulas are laid out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MathLayout {
+    /// One line of Unicode text: `(a+b)/c`, `x²`
+    #[default]
+    Linear,
+    /// Stacked fractions, raised scripts and limits, see `math_layout`
+    TwoD,
+    /// Formulas drawn with lines and font sizes by the Lua viewer, see `math_draw`
+    Native,
+}
+
+/// Which parts of a text are read as LaTeX
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MathScope {
+    /// Only math between `$...$`, `$$...$$`, `\(...\)` and `\[...\]`
+    #[default]
+    Delimited,
+    /// The whole text, so `file_name` becomes `fileₙame`
+    Document,
+}
+
+/// Options for rendering LaTeX text
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderOptions {
+    pub layout: MathLayout,
+    pub scope: MathScope,
+    /// Characters the device font can show
+    pub glyphs: GlyphProfile,
+    /// User symbols that extend or override the built-in tables
+    pub symbols: SymbolTable,
+}
+
+/// Render LaTeX text into lines, with environments as aligned blocks
+///
+/// A line holding `\begin{pmatrix}...\end{pmatrix}` or another environment
+/// becomes a [`TextBlock`] with the text around it on the middle row. With
+/// [`MathLayout::TwoD`], every multi-line formula is a block; with
+/// [`MathLayout::Native`], every formula is a [`RenderedLine::Formula`].
+/// Such a formula gets a line of its own, and the prose before and after it
+/// stays ordinary text that can be word-wrapped. With
+/// [`MathScope::Delimited`] only the math regions are read as LaTeX; the
+/// prose around them is kept as it is.
+pub fn render_latex(input: &str, options: &RenderOptions) -> Vec<RenderedLine> {
+    let nodes = match options.scope {
+        MathScope::Document => latex::parse(input).into_iter().map(|node| (node, true)).collect(),
+        MathScope::Delimited => parse_delimited(input),
+    };
+    nodes
+        .split(|(node, _)| *node == Node::Char('\n'))
+        .flat_map(|line| match options.layout {
+            MathLayout::Linear => vec![render_line(&untagged(line), options)],
+            MathLayout::TwoD => split_formulas(line, options, math_layout::render_line),
+            MathLayout::Native => split_formulas(line, options, math_draw::render_line),
+        })
+        .collect()
+}
+
+/// Parse the math regions of a text, with macros carried from one region
+/// to the next
+///
+/// Prose becomes plain characters, which every back end copies as they are.
+/// Each node is paired with whether it comes from a math region. A region
+/// holding only macro definitions on a line of its own leaves no empty line
+/// behind.
+fn parse_delimited(input: &str) -> Vec<(Node, bool)> {
+    let mut macros = Macros::default();
+    let mut nodes = Vec::new();
+    let mut skip_line_break = false;
+
+    for segment in latex::split_math(input) {
+        match segment {
+            Segment::Text(text) => {
+                let text = if skip_line_break { text.strip_prefix('\n').unwrap_or(&text) } else { &text };
+                nodes.extend(text.chars().map(|c| (Node::Char(c), false)));
+                skip_line_break = false;
+            }
+            Segment::Math(math) => {
+                let math = latex::parse_with_macros(&math, &mut macros);
+                skip_line_break = math.is_empty() && matches!(nodes.last(), None | Some((Node::Char('\n'), _)));
+                nodes.extend(math.into_iter().map(|node| (node, true)));
+            }
         }
     }
+    nodes
+}
 
-    None
+fn untagged(nodes: &[(Node, bool)]) -> Vec<Node> {
+    nodes.iter().map(|(node, _)| node.clone()).collect()
 }
 
-/// Convert characters after ^ or _ to super/subscript
-fn convert_script(chars: &[char], start: usize, map: &HashMap<char, char>) -> Option<(String, usize)> {
-    if start >= chars.len() {
-        return None;
+/// Render a line with a back end that draws formulas, moving each formula
+/// that isn't plain text onto a line of its own
+///
+/// Math regions that do render as text stay in the prose around them.
+fn split_formulas(
+    line: &[(Node, bool)],
+    options: &RenderOptions,
+    render: fn(&[Node], &RenderOptions) -> RenderedLine,
+) -> Vec<RenderedLine> {
+    let whole = render(&untagged(line), options);
+    if matches!(whole, RenderedLine::Text(_)) {
+        return vec![whole];
     }
 
-    let mut result = String::new();
-    let mut all_converted = true;
-    let mut original_text = String::new();
+    fn flush(prose: &mut String, lines: &mut Vec<RenderedLine>) {
+        let text = std::mem::take(prose);
+        if !text.trim().is_empty() {
+            lines.push(RenderedLine::Text(text.trim().to_string()));
+        }
+    }
 
-    let consumed = if chars[start] == '{' {
-        // Braced group: ^{123} or _{abc}
-        let mut i = start + 1;
-        while i < chars.len() && chars[i] != '}' {
-            original_text.push(chars[i]);
-            if let Some(&converted) = map.get(&chars[i]) {
-                result.push(converted);
-            } else {
-                // Can't convert this character - mark that not all converted
-                all_converted = false;
-                result.push(chars[i]);
+    let mut lines = Vec::new();
+    let mut prose = String::new();
+    for region in line.chunk_by(|(_, a), (_, b)| a == b) {
+        match render(&untagged(region), options) {
+            RenderedLine::Text(text) => prose.push_str(&text),
+            formula => {
+                flush(&mut prose, &mut lines);
+                lines.push(formula);
             }
-            i += 1;
         }
-        if i < chars.len() && chars[i] == '}' {
-            i - start + 1
-        } else {
-            return None; // Unclosed brace
+    }
+    flush(&mut prose, &mut lines);
+    lines
+}
+
+fn render_line(nodes: &[Node], options: &RenderOptions) -> RenderedLine {
+    let mut pieces: Vec<TextBlock> = Vec::new();
+    let mut text = String::new();
+
+    for (i, node) in nodes.iter().enumerate() {
+        match node {
+            Node::Environment { name, rows, .. } => {
+                if !text.is_empty() {
+                    pieces.push(TextBlock::text(std::mem::take(&mut text)));
+                }
+                pieces.push(render_environment(name, rows, options));
+            }
+            _ => render_node_after(node, i.checked_sub(1).map(|p| &nodes[p]), options, &mut text),
         }
+    }
+
+    if pieces.is_empty() {
+        return RenderedLine::Text(text);
+    }
+    if !text.is_empty() {
+        pieces.push(TextBlock::text(text));
+    }
+    RenderedLine::Block(TextBlock::beside(pieces))
+}
+
+/// Render nodes as one line of Unicode text
+pub fn nodes_to_unicode(nodes: &[Node], options: &RenderOptions) -> String {
+    let mut text = String::new();
+    render_nodes(nodes, options, &mut text);
+    text
+}
+
+fn render_nodes(nodes: &[Node], options: &RenderOptions, out: &mut String) {
+    for (i, node) in nodes.iter().enumerate() {
+        render_node_after(node, i.checked_sub(1).map(|p| &nodes[p]), options, out);
+    }
+}
+
+/// Render a node that follows `previous`
+///
+/// A group after an unknown command is that command's argument, so it is
+/// copied with its braces like the command (`\foo{x}`).
+fn render_node_after(node: &Node, previous: Option<&Node>, options: &RenderOptions, out: &mut String) {
+    if let Node::Group { children, closed: true } = node
+        && let Some(Node::Command { name, .. }) = previous
+        && !is_known_command(name, options)
+    {
+        out.push('{');
+        render_nodes(children, options, out);
+        out.push('}');
+        return;
+    }
+    render_node(node, options, out);
+}
+
+fn render_node(node: &Node, options: &RenderOptions, out: &mut String) {
+    match node {
+        Node::Char(c) => out.push(*c),
+        Node::Group { children, closed } => {
+            // Braces only group; an unclosed group keeps its brace as source
+            if !*closed {
+                out.push('{');
+            }
+            render_nodes(children, options, out);
+        }
+        Node::Command { name, optional, args } => render_command(name, optional.as_deref(), args, options, out),
+        Node::Script { base, sub, sup } => {
+            if let Some(base) = base {
+                let mut text = String::new();
+                render_node(base, options, &mut text);
+                // (a/b)² and (√x)² rather than a/b² and √x²
+                let wrap = match &**base {
+                    Node::Command { name, .. } if is_fraction(name) => !is_atom(&text, options),
+                    Node::Command { name, args, .. } if name == "sqrt" => !args.is_empty(),
+                    _ => false,
+                };
+                if wrap {
+                    text = format!("({})", text);
+                }
+                out.push_str(&text);
+            }
+            if let Some(sub) = sub {
+                render_script(sub, false, options, out);
+            }
+            if let Some(sup) = sup {
+                render_script(sup, true, options, out);
+            }
+        }
+        // Environments nested in a group or argument are written on one line
+        Node::Environment { name, rows, .. } => {
+            let (open, close, _) = environment_style(name);
+            let rows: Vec<String> = environment_cells(rows, options).iter().map(|row| row.join(", ")).collect();
+            out.extend(open);
+            out.push_str(&rows.join("; "));
+            out.extend(close);
+        }
+    }
+}
+
+fn render_command(name: &str, optional: Option<&[Node]>, args: &[Node], options: &RenderOptions, out: &mut String) {
+    if is_fraction(name)
+        && let [num, den] = args
+        && let (Some(num), Some(den)) = (argument_nodes(num), argument_nodes(den))
+    {
+        out.push_str(&render_fraction(num, den, options));
+        return;
+    }
+
+    if name == "sqrt"
+        && let [radicand] = args
+        && let Some(radicand) = argument_nodes(radicand)
+    {
+        out.push_str(&render_root(optional.unwrap_or_default(), radicand, options));
+        return;
+    }
+
+    if is_binomial(name)
+        && let [n, k] = args
+        && let (Some(n), Some(k)) = (argument_nodes(n), argument_nodes(k))
+    {
+        out.push_str(&format!("C({},{})", nodes_to_unicode(n, options), nodes_to_unicode(k, options)));
+        return;
+    }
+
+    if is_delimiter_command(name)
+        && let [delimiter] = args
+    {
+        out.extend(delimiter_char(delimiter));
+        return;
+    }
+
+    if is_style_command(name)
+        && let [content] = args
+        && let Some(content) = argument_nodes(content)
+    {
+        let mut text = String::new();
+        render_nodes(content, options, &mut text);
+        if name == "mathbb" {
+            text = text.chars().map(|c| blackboard_bold(c, options)).collect();
+        }
+        out.push_str(&text);
+        return;
+    }
+
+    match command_symbol(name, options) {
+        Some(replacement) => out.push_str(replacement),
+        None => {
+            out.push('\\');
+            out.push_str(name);
+        }
+    }
+    if let Some(optional) = optional {
+        out.push('[');
+        render_nodes(optional, options, out);
+        out.push(']');
+    }
+    // Arguments of a command kept as source keep their braces too
+    for arg in args {
+        match arg {
+            Node::Group { children, closed: true } => {
+                out.push('{');
+                render_nodes(children, options, out);
+                out.push('}');
+            }
+            _ => render_node(arg, options, out),
+        }
+    }
+}
+
+/// Brackets and column spacing of an environment
+pub fn environment_style(name: &str) -> (Option<char>, Option<char>, &'static str) {
+    match name {
+        "pmatrix" => (Some('('), Some(')'), "  "),
+        "bmatrix" => (Some('['), Some(']'), "  "),
+        "Bmatrix" => (Some('{'), Some('}'), "  "),
+        "vmatrix" => (Some('|'), Some('|'), "  "),
+        "Vmatrix" => (Some('‖'), Some('‖'), "  "),
+        "matrix" | "smallmatrix" | "array" => (None, None, "  "),
+        "cases" | "dcases" => (Some('{'), None, "  "),
+        "rcases" => (None, Some('}'), "  "),
+        // align, aligned, gather, eqnarray, split, ...
+        _ => (None, None, " "),
+    }
+}
+
+/// The rendered cells of an environment, one line of text each
+fn environment_cells(rows: &[Vec<Vec<Node>>], options: &RenderOptions) -> Vec<Vec<String>> {
+    rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|cell| {
+                    let mut text = String::new();
+                    render_nodes(cell, options, &mut text);
+                    text.replace(['\n', '\t'], " ").trim().to_string()
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Render an environment as a block with its brackets
+fn render_environment(name: &str, rows: &[Vec<Vec<Node>>], options: &RenderOptions) -> TextBlock {
+    let (open, close, spacing) = environment_style(name);
+    let mut cells = environment_cells(rows, options);
+    let columns = cells.iter().map(Vec::len).max().unwrap_or(0);
+
+    let mut grid = Vec::with_capacity(cells.len());
+    for row in &mut cells {
+        row.resize(columns, String::new());
+        let mut line = Vec::with_capacity(columns * 2);
+        for (i, cell) in row.drain(..).enumerate() {
+            if i > 0 {
+                line.push(spacing.to_string());
+            }
+            line.push(cell);
+        }
+        grid.push(line);
+    }
+    let body = TextBlock { rows: grid };
+
+    let height = body.height();
+    let mut parts = Vec::new();
+    parts.extend(open.map(|c| TextBlock::column(tall_bracket(c, height, options))));
+    parts.push(body);
+    parts.extend(close.map(|c| TextBlock::column(tall_bracket(c, height, options))));
+    TextBlock::beside(parts)
+}
+
+/// A bracket `height` rows tall, built from Unicode bracket pieces
+///
+/// Fonts without the pieces get ASCII ones: `/ | \` for parentheses.
+pub fn tall_bracket(bracket: char, height: usize, options: &RenderOptions) -> Vec<String> {
+    if height <= 1 {
+        return vec![bracket.to_string()];
+    }
+    // (top, middle, bottom, extension)
+    let pieces = match bracket {
+        '(' => ('⎛', '⎜', '⎝', '⎜'),
+        ')' => ('⎞', '⎟', '⎠', '⎟'),
+        '[' => ('⎡', '⎢', '⎣', '⎢'),
+        ']' => ('⎤', '⎥', '⎦', '⎥'),
+        '{' => ('⎧', '⎨', '⎩', '⎪'),
+        '}' => ('⎫', '⎬', '⎭', '⎪'),
+        c => (c, c, c, c),
+    };
+    let (top, middle, bottom, extension) = if [pieces.0, pieces.1, pieces.2, pieces.3].iter().all(|&c| options.glyphs.shows(c))
+    {
+        pieces
     } else {
-        // Single character: ^2 or _1
-        original_text.push(chars[start]);
-        if let Some(&converted) = map.get(&chars[start]) {
-            result.push(converted);
-            1
-        } else {
-            // Can't convert this single character
-            // Use parentheses fallback to avoid blank rectangles on TI-Nspire
-            all_converted = false;
-            result.push(chars[start]);
-            1
+        match bracket {
+            '(' | '{' => ('/', '|', '\\', '|'),
+            ')' | '}' => ('\\', '|', '/', '|'),
+            '[' | ']' => (bracket, '|', bracket, '|'),
+            c => (c, c, c, c),
         }
     };
+    (0..height)
+        .map(|i| {
+            let piece = if i == 0 {
+                top
+            } else if i == height - 1 {
+                bottom
+            } else if i == (height - 1) / 2 {
+                middle
+            } else {
+                extension
+            };
+            piece.to_string()
+        })
+        .collect()
+}
+
+/// Text-mode and font commands whose contents are shown as they are
+///
+/// The device font has no bold, calligraphic or fraktur letters, so those
+/// styles are dropped rather than shown as blank boxes. Text-mode contents
+/// keep their spacing (`\text{ if }`).
+fn is_style_command(name: &str) -> bool {
+    matches!(
+        name,
+        "text" | "textrm" | "textit" | "textbf" | "textsf" | "texttt" | "mbox" | "mathrm" | "mathit" | "mathbf"
+            | "mathbb" | "mathcal" | "mathscr" | "mathfrak" | "mathsf" | "mathtt" | "boldsymbol" | "bm"
+            | "operatorname"
+    )
+}
+
+/// Blackboard-bold form of a letter, for the number sets the font has
+fn blackboard_bold(c: char, options: &RenderOptions) -> char {
+    let bold = match c {
+        'R' => 'ℝ',
+        'N' => 'ℕ',
+        'Z' => 'ℤ',
+        'Q' => 'ℚ',
+        'C' => 'ℂ',
+        _ => c,
+    };
+    options.glyphs.pick(bold, c)
+}
+
+/// The Unicode symbol for a command whose glyph not every font has
+///
+/// [`LATEX_MAP`] holds the ASCII fallback used when the font lacks it.
+fn extended_symbol(name: &str) -> Option<&'static str> {
+    let symbol = match name {
+        "Gamma" => "Γ",
+        "Delta" => "Δ",
+        "Theta" => "Θ",
+        "Lambda" => "Λ",
+        "Xi" => "Ξ",
+        "Pi" => "Π",
+        "Sigma" => "Σ",
+        "Upsilon" => "Υ",
+        "Phi" => "Φ",
+        "Psi" => "Ψ",
+        "Omega" => "Ω",
+        "subset" => "⊂",
+        "supset" => "⊃",
+        "subseteq" => "⊆",
+        "supseteq" => "⊇",
+        "in" => "∈",
+        "notin" => "∉",
+        "ni" => "∋",
+        "perp" => "⊥",
+        "parallel" => "∥",
+        "leftarrow" => "←",
+        "rightarrow" | "to" => "→",
+        "uparrow" => "↑",
+        "downarrow" => "↓",
+        "leftrightarrow" => "↔",
+        "Leftarrow" => "⇐",
+        "Rightarrow" | "implies" => "⇒",
+        "Leftrightarrow" | "iff" => "⇔",
+        "mapsto" => "↦",
+        "sum" => "∑",
+        "prod" => "∏",
+        "coprod" => "∐",
+        "int" => "∫",
+        "oint" => "∮",
+        "iint" => "∬",
+        "iiint" => "∭",
+        "bigcup" => "⋃",
+        "bigcap" => "⋂",
+        "bigoplus" => "⨁",
+        "bigotimes" => "⨂",
+        "infty" => "∞",
+        "partial" => "∂",
+        "nabla" => "∇",
+        "forall" => "∀",
+        "exists" => "∃",
+        "nexists" => "∄",
+        "emptyset" | "varnothing" => "∅",
+        "neg" | "lnot" => "¬",
+        "land" | "wedge" => "∧",
+        "lor" | "vee" => "∨",
+        "cap" => "∩",
+        "cup" => "∪",
+        "setminus" => "∖",
+        "angle" => "∠",
+        "triangle" => "△",
+        "square" => "□",
+        "diamond" => "⋄",
+        "aleph" => "ℵ",
+        "hbar" => "ℏ",
+        "ell" => "ℓ",
+        "prime" => "′",
+        "degree" => "°",
+        _ => return None,
+    };
+    Some(symbol)
+}
+
+/// The text a symbol command is rendered as, e.g. `sum` → `∑` or `SUM`
+pub fn command_symbol<'a>(name: &str, options: &'a RenderOptions) -> Option<&'a str> {
+    if let Some(symbol) = options.symbols.commands.get(name) {
+        return Some(symbol);
+    }
+    if let Some(symbol) = extended_symbol(name)
+        && options.glyphs.shows_all(symbol)
+    {
+        return Some(symbol);
+    }
+    LATEX_MAP.get(format!("\\{}", name).as_str()).copied()
+}
+
+/// Whether a command is rendered rather than copied as `\name`
+///
+/// Macros don't count; `math_check` tracks the ones a text defines.
+pub fn is_known_command(name: &str, options: &RenderOptions) -> bool {
+    command_symbol(name, options).is_some()
+        || is_fraction(name)
+        || is_binomial(name)
+        || is_style_command(name)
+        || is_delimiter_command(name)
+        || matches!(name, "sqrt" | "begin" | "end" | "\\" | "newcommand" | "renewcommand" | "providecommand" | "def")
+        || (options.layout != MathLayout::Linear && math_layout::is_big_operator(name))
+}
+
+/// The bracket a `\left`/`\right` delimiter stands for; None for `.`
+pub fn delimiter_char(arg: &Node) -> Option<char> {
+    match arg {
+        Node::Char('.') => None,
+        Node::Char(c) => Some(*c),
+        Node::Command { name, .. } => match name.as_str() {
+            "{" | "lbrace" => Some('{'),
+            "}" | "rbrace" => Some('}'),
+            "|" | "Vert" | "lVert" | "rVert" => Some('‖'),
+            "vert" | "lvert" | "rvert" => Some('|'),
+            "langle" => Some('⟨'),
+            "rangle" => Some('⟩'),
+            "lfloor" => Some('⌊'),
+            "rfloor" => Some('⌋'),
+            "lceil" => Some('⌈'),
+            "rceil" => Some('⌉'),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Sizing commands that only scale a delimiter
+pub fn is_delimiter_command(name: &str) -> bool {
+    matches!(name, "left" | "right" | "bigl" | "bigr" | "Bigl" | "Bigr" | "big" | "Big")
+}
+
+pub fn is_fraction(name: &str) -> bool {
+    matches!(name, "frac" | "dfrac" | "tfrac" | "cfrac")
+}
+
+pub fn is_binomial(name: &str) -> bool {
+    matches!(name, "binom" | "dbinom" | "tbinom")
+}
+
+/// The nodes of a command argument, or None for an unclosed group
+pub fn argument_nodes(arg: &Node) -> Option<&[Node]> {
+    match arg {
+        Node::Group { children, closed: true } => Some(children),
+        Node::Group { closed: false, .. } => None,
+        _ => Some(std::slice::from_ref(arg)),
+    }
+}
+
+/// Render a fraction on one line, e.g. `(a+b)/c`
+///
+/// Single-digit fractions with a Unicode glyph use it (½, ¾, ...). The
+/// numerator is parenthesized when it has several terms, the denominator
+/// whenever it is more than one number or symbol.
+fn render_fraction(num: &[Node], den: &[Node], options: &RenderOptions) -> String {
+    if let ([Node::Char(n)], [Node::Char(d)]) = (num, den)
+        && let Some(glyph) = command_symbol(&format!("frac{}{}", n, d), options)
+        && options.glyphs.shows_all(glyph)
+    {
+        return glyph.to_string();
+    }
+
+    let mut num_text = String::new();
+    render_nodes(num, options, &mut num_text);
+    let mut den_text = String::new();
+    render_nodes(den, options, &mut den_text);
+    let (num_text, den_text) = (num_text.trim(), den_text.trim());
+
+    let num_text = if has_top_level_terms(num_text) { format!("({})", num_text) } else { num_text.to_string() };
+    let den_text = if is_atom(den_text, options) { den_text.to_string() } else { format!("({})", den_text) };
+    format!("{}/{}", num_text, den_text)
+}
+
+/// Render a root: `√x`, `√(b²-4ac)`, `∛x` or `root(x,5)`, in the
+/// calculator's argument order
+///
+/// The radicand is parenthesized unless it is a single operand.
+fn render_root(index: &[Node], radicand: &[Node], options: &RenderOptions) -> String {
+    let mut index_text = String::new();
+    render_nodes(index, options, &mut index_text);
+    let mut text = String::new();
+    render_nodes(radicand, options, &mut text);
+    let text = text.trim();
 
-    if result.is_empty() {
-        None
+    let body = if is_atom(text, options) { text.to_string() } else { format!("({})", text) };
+    match index_text.trim() {
+        "" | "2" if options.glyphs.shows('√') => format!("√{}", body),
+        "" | "2" => format!("sqrt({})", text),
+        "3" if options.glyphs.shows('∛') => format!("∛{}", body),
+        "4" if options.glyphs.shows('∜') => format!("∜{}", body),
+        n => format!("root({},{})", text, n),
+    }
+}
+
+/// Whether rendered text has a sum, relation or space outside parentheses
+///
+/// A leading sign doesn't count: `-1` is one term.
+fn has_top_level_terms(text: &str) -> bool {
+    let mut depth = 0i32;
+    for (i, c) in text.chars().enumerate() {
+        match c {
+            '(' | '[' | '⟨' => depth += 1,
+            ')' | ']' | '⟩' => depth -= 1,
+            '+' | '-' | '−' | '±' | '∓' if depth == 0 && i > 0 => return true,
+            '=' | '<' | '>' | '≤' | '≥' | '≠' | '≈' if depth == 0 => return true,
+            c if c.is_whitespace() && depth == 0 => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Whether rendered text reads as a single operand: a number, one symbol or
+/// a parenthesized group, optionally with super/subscripts or under a root
+fn is_atom(text: &str, options: &RenderOptions) -> bool {
+    if let Some(radicand) = text.strip_prefix(['√', '∛', '∜']) {
+        return is_atom(radicand, options);
+    }
+    let symbols = &options.symbols;
+    let core = text.trim_end_matches(|c: char| {
+        SUPERSCRIPTS.values().chain(SUBSCRIPTS.values()).any(|&s| s == c)
+            || symbols.superscripts.values().chain(symbols.subscripts.values()).any(|&s| s == c)
+    });
+    if core.is_empty() {
+        return false;
+    }
+    if core.chars().all(|c| c.is_ascii_digit() || c == '.') || core.chars().count() == 1 {
+        return true;
+    }
+    is_parenthesized(core)
+}
+
+/// Whether the text is one `(...)` group, e.g. `(a+b)` but not `(a)(b)`
+fn is_parenthesized(text: &str) -> bool {
+    if !text.starts_with('(') || !text.ends_with(')') {
+        return false;
+    }
+    let mut depth = 0i32;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 && i + c.len_utf8() < text.len() {
+            return false;
+        }
+    }
+    depth == 0
+}
+
+/// The text in super- or subscript characters, if they all exist
+pub fn script_chars(text: &str, superscript: bool, options: &RenderOptions) -> Option<String> {
+    let (custom, map) = if superscript {
+        (&options.symbols.superscripts, &SUPERSCRIPTS)
     } else {
-        // If not all characters could be converted in a braced group,
-        // use regular text in parentheses instead for TI-Nspire compatibility
-        // The ^ and _ characters don't render on TI-Nspire, so we must avoid them
-        // Example: ^{abc} becomes (abc) instead of ^{abc} or trying unavailable superscripts
-        if !all_converted {
-            Some((format!("({})", original_text), consumed))
-        } else {
-            Some((result, consumed))
+        (&options.symbols.subscripts, &SUBSCRIPTS)
+    };
+    text.chars()
+        .map(|c| custom.get(&c).or_else(|| map.get(&c).filter(|&&s| options.glyphs.shows(s))).copied())
+        .collect()
+}
+
+/// Render the argument of ^ or _ with super/subscript characters
+fn render_script(arg: &Node, superscript: bool, options: &RenderOptions, out: &mut String) {
+    let marker = if superscript { '^' } else { '_' };
+    let text = match arg {
+        Node::Group { children, closed: true } => {
+            let mut text = String::new();
+            render_nodes(children, options, &mut text);
+            text
         }
+        Node::Group { closed: false, .. } => {
+            // Unclosed brace: keep the source
+            out.push(marker);
+            render_node(arg, options, out);
+            return;
+        }
+        _ => {
+            let mut text = String::new();
+            render_node(arg, options, &mut text);
+            text
+        }
+    };
+
+    match script_chars(&text, superscript, options) {
+        Some(converted) => out.push_str(&converted),
+        // The ^ and _ characters don't render on TI-Nspire, so when a script
+        // has characters with no super/subscript form it becomes plain text
+        // in parentheses: ^{abc} becomes (abc)
+        None => out.push_str(&format!("({})", text)),
     }
 }
 
@@ -416,6 +1347,27 @@ fn convert_script(chars: &[char], start: usize, map: &HashMap<char, char>) -> Op
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_unicode_to_latex() {
+        assert_eq!(unicode_to_latex("α ≤ x² + H₂O"), "\\alpha \\le x^2 + H_2O");
+        assert_eq!(unicode_to_latex("x¹⁰ ≠ ½ + ⅓"), "x^{10} \\ne \\frac{1}{2} + \\frac{1}{3}");
+        assert_eq!(unicode_to_latex("√(b²-4ac), ∛x, √2, ℝ"), "\\sqrt{b^2-4ac}, \\sqrt[3]{x}, \\sqrt{2}, \\mathbb{R}");
+        assert_eq!(unicode_to_latex("Δx → ∞"), "\\Delta x \\to \\infty");
+        // ASCII fallbacks, as whole words
+        assert_eq!(unicode_to_latex("x -> inf, Delta x <=> SUMᵢ₌₀ⁿ"), "x \\to \\infty, \\Delta x \\iff \\sum_{i=0}^n");
+        assert_eq!(unicode_to_latex("Deltas in info, f(abc), a <= b"), "Deltas in info, f(abc), a <= b");
+
+        for latex in [
+            "\\alpha \\leq x^{10} + H_2O",
+            "\\sqrt{b^2-4ac} \\neq \\frac{1}{2}",
+            "\\mathbb{R}^n \\cdot \\pi r_1",
+            "\\Delta x \\to \\infty, \\nabla \\int f",
+        ] {
+            let unicode = latex_to_unicode(latex);
+            assert_eq!(latex_to_unicode(&unicode_to_latex(&unicode)), unicode);
+        }
+    }
+
     #[test]
     fn test_greek_letters() {
         assert_eq!(latex_to_unicode("\\alpha + \\beta = \\gamma"), "α + β = γ");
@@ -429,6 +1381,9 @@ mod tests {
         assert_eq!(latex_to_unicode("a \\times b"), "a × b");
         assert_eq!(latex_to_unicode("x \\leq y \\leq z"), "x ≤ y ≤ z");
         assert_eq!(latex_to_unicode("a \\neq b"), "a ≠ b");
+        assert_eq!(latex_to_unicode("\\sin x + \\log_2 n"), "sin x + log₂ n");
+        assert_eq!(latex_to_unicode("\\binom{n}{k} p^2"), "C(n,k) p²");
+        assert_eq!(latex_to_unicode("\\{1, 2\\} at 50\\%"), "{1, 2} at 50%");
     }
 
     #[test]
@@ -436,9 +1391,9 @@ mod tests {
         assert_eq!(latex_to_unicode("x^2"), "x²");
         assert_eq!(latex_to_unicode("x^{10}"), "x¹⁰");
         assert_eq!(latex_to_unicode("x^2 + y^2 = z^2"), "x² + y² = z²");
-        // Note: LaTeX commands inside braces aren't parsed (would need recursion)
-        // For complex expressions, convert LaTeX first: e^{i\pi} -> e^{i}π
         assert_eq!(latex_to_unicode("e^i\\pi"), "eⁱπ");
+        // Commands inside scripts are rendered before the script is converted
+        assert_eq!(latex_to_unicode("e^{i\\pi}"), "e(iπ)");
     }
 
     #[test]
@@ -463,6 +1418,116 @@ mod tests {
     #[test]
     fn test_fractions() {
         assert_eq!(latex_to_unicode("\\frac12 + \\frac14 = \\frac34"), "½ + ¼ = ¾");
+        assert_eq!(latex_to_unicode("\\frac{1}{2}"), "½");
+        assert_eq!(latex_to_unicode("\\frac{2}{9}"), "2/9");
+        assert_eq!(latex_to_unicode("\\frac{a+b}{c}"), "(a+b)/c");
+        assert_eq!(latex_to_unicode("\\dfrac{a}{b+c}"), "a/(b+c)");
+        assert_eq!(latex_to_unicode("\\tfrac{-1}{2x}"), "-1/(2x)");
+        assert_eq!(latex_to_unicode("\\frac{x^2}{10}"), "x²/10");
+        assert_eq!(latex_to_unicode("\\frac{\\alpha}{(a+b)^2}"), "α/(a+b)²");
+    }
+
+    #[test]
+    fn test_roots() {
+        assert_eq!(latex_to_unicode("\\sqrt{2}"), "√2");
+        assert_eq!(latex_to_unicode("\\sqrt x"), "√x");
+        assert_eq!(latex_to_unicode("\\sqrt{b^2-4ac}"), "√(b²-4ac)");
+        assert_eq!(latex_to_unicode("\\sqrt[3]{x}"), "∛x");
+        assert_eq!(latex_to_unicode("\\sqrt[4]{x+1}"), "∜(x+1)");
+        assert_eq!(latex_to_unicode("\\sqrt[n]{x+1}"), "root(x+1,n)");
+        assert_eq!(latex_to_unicode("\\sqrt[5]{x+1}"), "root(x+1,5)");
+        assert_eq!(latex_to_unicode("\\sqrt"), "√");
+    }
+
+    #[test]
+    fn test_roots_in_context() {
+        assert_eq!(latex_to_unicode("\\frac{-b \\pm \\sqrt{b^2-4ac}}{2a}"), "(-b ± √(b²-4ac))/(2a)");
+        assert_eq!(latex_to_unicode("\\frac{1}{\\sqrt{2}}"), "1/√2");
+        assert_eq!(latex_to_unicode("\\sqrt{\\frac{a}{b}}"), "√(a/b)");
+        assert_eq!(latex_to_unicode("\\sqrt{x}^2"), "(√x)²");
+        assert_eq!(latex_to_unicode("\\sqrt{x+1}^2"), "(√(x+1))²");
+        assert_eq!(latex_to_unicode("x^{\\sqrt{2}}"), "x(√2)");
+        assert_eq!(latex_to_unicode("\\sqrt{x^{2}}"), "√x²");
+    }
+
+    #[test]
+    fn test_text_and_fonts() {
+        assert_eq!(latex_to_unicode("f(x) = 1 \\text{ if } x > 0"), "f(x) = 1  if  x > 0");
+        assert_eq!(latex_to_unicode("\\mathbb{N} \\subset \\mathbb{Z} \\subset \\mathbb{Q}"), "ℕ < ℤ < ℚ");
+        assert_eq!(latex_to_unicode("z \\in \\mathbb C"), "z in ℂ");
+        assert_eq!(latex_to_unicode("\\mathbb{P}"), "P");
+        assert_eq!(latex_to_unicode("\\mathbf{v} + \\mathcal{L}"), "v + L");
+        assert_eq!(latex_to_unicode("\\operatorname{Var}(X)"), "Var(X)");
+        assert_eq!(latex_to_unicode("x_{\\text{max}}"), "xₘₐₓ");
+        assert_eq!(latex_to_unicode("\\mathrm{d}x"), "dx");
+    }
+
+    #[test]
+    fn test_matrix_block() {
+        let lines = render_latex("A = $\\begin{pmatrix} 1 & 0 \\\\ x^2 & 1 \\end{pmatrix}$, done", &RenderOptions::default());
+        let RenderedLine::Block(block) = &lines[0] else { panic!("expected a block: {:?}", lines) };
+        assert_eq!(
+            block.rows,
+            vec![
+                vec!["A = ", "/", "1", "  ", "0", "\\", ", done"],
+                vec!["", "\\", "x²", "  ", "1", "/", ""],
+            ]
+        );
+        assert_eq!(block.to_padded_lines(), vec!["A = /1   0\\, done", "    \\x²  1/"]);
+    }
+
+    #[test]
+    fn test_cases_and_align() {
+        let text = "|x| = \\begin{cases}\nx & \\text{if } x \\geq 0 \\\\\n-x & \\text{otherwise}\n\\end{cases}\nnext line";
+        assert_eq!(
+            latex_to_unicode(text),
+            "|x| = /x   if  x ≥ 0\n      \\-x  otherwise\nnext line"
+        );
+
+        let lines = render_latex("\\[\\begin{align*} f(x) &= (x+1)^2 \\\\ &= x^2 + 2x + 1 \\end{align*}\\]", &RenderOptions::default());
+        assert_eq!(
+            lines,
+            vec![RenderedLine::Block(TextBlock {
+                rows: vec![
+                    vec!["f(x)".to_string(), " ".to_string(), "= (x+1)²".to_string()],
+                    vec!["".to_string(), " ".to_string(), "= x² + 2x + 1".to_string()],
+                ]
+            })]
+        );
+    }
+
+    #[test]
+    fn test_tall_brackets() {
+        // The default profile draws brackets with ASCII
+        assert_eq!(tall_bracket('{', 3, &RenderOptions::default()), vec!["/", "|", "\\"]);
+        assert_eq!(tall_bracket('[', 4, &RenderOptions::default()), vec!["[", "|", "|", "["]);
+        assert_eq!(tall_bracket('(', 1, &RenderOptions::default()), vec!["("]);
+
+        let glyphs = GlyphProfile::from_toml("available = \"⎡⎢⎣⎤⎥⎦⎧⎨⎩⎪⎫⎬⎭\"").unwrap();
+        let drawn = RenderOptions { glyphs, ..Default::default() };
+        assert_eq!(tall_bracket('{', 3, &drawn), vec!["⎧", "⎨", "⎩"]);
+        assert_eq!(tall_bracket('[', 4, &drawn), vec!["⎡", "⎢", "⎢", "⎣"]);
+        assert_eq!(tall_bracket('(', 3, &drawn), vec!["/", "|", "\\"]);
+        let array = latex_to_unicode("\\begin{array}{cc} a & b \\end{array}");
+        assert_eq!(array, "a  b");
+        // Nested in a group, an environment stays on one line
+        assert_eq!(latex_to_unicode("{\\begin{bmatrix}1&2\\\\3&4\\end{bmatrix}}"), "[1, 2; 3, 4]");
+    }
+
+    #[test]
+    fn test_sized_delimiters() {
+        assert_eq!(latex_to_unicode("\\left( \\frac{a}{b} \\right)^2"), "( a/b )²");
+        assert_eq!(latex_to_unicode("\\left\\{ x \\right."), "{ x ");
+        assert_eq!(latex_to_unicode("\\bigl\\langle v \\bigr\\rangle"), "⟨ v ⟩");
+    }
+
+    #[test]
+    fn test_nested_fractions() {
+        assert_eq!(latex_to_unicode("\\frac{\\frac{1}{2}}{3}"), "½/3");
+        assert_eq!(latex_to_unicode("\\frac{1}{\\frac{a}{b}}"), "1/(a/b)");
+        assert_eq!(latex_to_unicode("\\frac{\\frac{a}{b}+1}{c}"), "(a/b+1)/c");
+        assert_eq!(latex_to_unicode("\\frac{a}{b}^2"), "(a/b)²");
+        assert_eq!(latex_to_unicode("\\frac{1}{2"), "\\frac{1}{2");
     }
 
     #[test]
@@ -474,6 +1539,91 @@ mod tests {
         assert_eq!(latex_to_unicode("x \\to y"), "x -> y");  // Common in limits
     }
 
+    #[test]
+    fn test_device_profiles() {
+        let cx = GlyphProfile::device("cx").unwrap();
+        assert_eq!(latex_to_unicode_for("\\Delta x \\to \\infty", &cx), "Δ x → ∞");
+        assert_eq!(latex_to_unicode_for("\\sum_{i=0}^{n}", &cx), "SUMᵢ₌₀ⁿ");
+
+        let cx2 = GlyphProfile::device("cx2").unwrap();
+        assert_eq!(latex_to_unicode_for("\\sum_{i=0}^{n}", &cx2), "∑ᵢ₌₀ⁿ");
+        assert_eq!(latex_to_unicode_for("\\forall x \\in \\mathbb{R}", &cx2), "∀ x ∈ ℝ");
+        assert_eq!(latex_to_unicode_for("\\Omega", &cx2), "Ω");
+
+        // Glyphs a profile file marks as missing fall back to plain text
+        let sparse = GlyphProfile::from_toml("missing = \"⅛ ³ ∛ ℝ ⎛ ⎜ ⎝ ⎞ ⎟ ⎠\"").unwrap();
+        assert_eq!(latex_to_unicode_for("\\frac18 + \\frac12", &sparse), "1/8 + ½");
+        assert_eq!(latex_to_unicode_for("x^3 + x^2", &sparse), "x(3) + x²");
+        assert_eq!(latex_to_unicode_for("\\sqrt[3]{x} \\in \\mathbb{R}", &sparse), "root(x,3) in R");
+        let options = RenderOptions { scope: MathScope::Document, glyphs: sparse, ..Default::default() };
+        let lines = render_latex("\\begin{pmatrix} a \\\\ b \\end{pmatrix}", &options);
+        let RenderedLine::Block(block) = &lines[0] else {
+            panic!("expected a block: {:?}", lines);
+        };
+        assert_eq!(block.to_padded_lines(), vec!["/a\\", "\\b/"]);
+    }
+
+    #[test]
+    fn test_delimited_math() {
+        let text = |lines: Vec<RenderedLine>| -> Vec<String> {
+            lines
+                .into_iter()
+                .map(|line| match line {
+                    RenderedLine::Text(text) => text,
+                    other => panic!("expected text: {:?}", other),
+                })
+                .collect()
+        };
+        let options = RenderOptions::default();
+        assert_eq!(
+            text(render_latex("Save my_file_2 ($2^{10}$ bytes) for \\$5\nthen \\(x_1 \\to \\infty\\)", &options)),
+            vec!["Save my_file_2 (2¹⁰ bytes) for $5", "then x₁ -> inf"]
+        );
+        assert_eq!(text(render_latex("\\alpha stays, $\\alpha$ doesn't", &options)), vec!["\\alpha stays, α doesn't"]);
+
+        let document = RenderOptions { scope: MathScope::Document, ..Default::default() };
+        assert_eq!(text(render_latex("my_var costs \\$5", &document)), vec!["myᵥar costs $5"]);
+    }
+
+    #[test]
+    fn test_formulas_split_from_prose() {
+        let text = |s: &str| RenderedLine::Text(s.to_string());
+        let line = "As $n$ grows, $\\frac{1}{n}$ shrinks to $0$, see below";
+
+        let two_d = RenderOptions { layout: MathLayout::TwoD, ..Default::default() };
+        let lines = render_latex(line, &two_d);
+        assert_eq!(lines.len(), 3, "{:?}", lines);
+        assert_eq!(lines[0], text("As n grows,"));
+        assert!(matches!(&lines[1], RenderedLine::Block(block) if block.rows.len() == 3));
+        assert_eq!(lines[2], text("shrinks to 0, see below"));
+
+        let native = RenderOptions { layout: MathLayout::Native, ..Default::default() };
+        let lines = render_latex(line, &native);
+        assert_eq!(lines.len(), 3, "{:?}", lines);
+        assert!(matches!(&lines[1], RenderedLine::Formula(Layout::Fraction { .. })));
+        assert_eq!(lines[2], text("shrinks to 0, see below"));
+
+        // A formula on its own stays one line
+        assert_eq!(render_latex("$\\frac{1}{n}$", &native).len(), 1);
+    }
+
+    #[test]
+    fn test_macros_and_symbol_tables() {
+        let text = "$\\newcommand{\\R}{\\mathbb{R}}\\def\\abs#1{|#1|}$\nFor $x \\in \\R$: $\\abs{x} \\geq 0$";
+        let lines = render_latex(text, &RenderOptions::default());
+        assert_eq!(lines, vec![RenderedLine::Text("For x in ℝ: |x| ≥ 0".to_string())]);
+
+        let symbols = SymbolTable::from_toml(
+            "[commands]\nin = \"∈\"\nR = \"ℝ\"\nfrac18 = \"⅛\"\n[superscripts]\na = \"ᵃ\"\n[subscripts]\n2 = \"2\"",
+        )
+        .unwrap();
+        let options = RenderOptions { symbols, ..Default::default() };
+        assert_eq!(
+            render_latex("$x \\in \\R$, $\\frac18 x^a$, $\\frac{1}{x^a}$, $H_2O$", &options),
+            vec![RenderedLine::Text("x ∈ ℝ, ⅛ xᵃ, 1/xᵃ, H2O".to_string())]
+        );
+    }
+
     #[test]
     fn test_mixed() {
         assert_eq!(
@@ -483,7 +1633,7 @@ mod tests {
         // \forall uses ASCII "forall" for TI-Nspire compatibility
         assert_eq!(
             latex_to_unicode("\\forall x \\in \\mathbb{R}: x^2 \\geq 0"),
-            "forall x in \\mathbb{R}: x² ≥ 0"  // \in becomes "in", \mathbb not supported
+            "forall x in ℝ: x² ≥ 0"  // \in becomes "in"
         );
     }
 
@@ -498,4 +1648,26 @@ mod tests {
         // Unknown commands are kept as-is
         assert_eq!(latex_to_unicode("\\unknown"), "\\unknown");
     }
+
+    #[test]
+    fn test_nested_groups() {
+        assert_eq!(latex_to_unicode("x^{a_{1}}"), "x(a₁)");
+        assert_eq!(latex_to_unicode("x^{2_{1}}"), "x(2₁)");
+        assert_eq!(latex_to_unicode("x_1^2 + x^2_1"), "x₁² + x₁²");
+    }
+
+    #[test]
+    fn test_bare_groups() {
+        assert_eq!(latex_to_unicode("{a+b}c"), "a+bc");
+        assert_eq!(latex_to_unicode("{{x}}^2"), "x²");
+        assert_eq!(latex_to_unicode("\\{a, b\\}"), "{a, b}");
+        assert_eq!(latex_to_unicode("\\foo{x}"), "\\foo{x}");
+    }
+
+    #[test]
+    fn test_unbalanced_braces() {
+        assert_eq!(latex_to_unicode("x^{2"), "x^{2");
+        assert_eq!(latex_to_unicode("a} + x^"), "a} + x^");
+        assert_eq!(latex_to_unicode("\\frac{1}"), "\\frac{1}");
+    }
 }
@@ -6,4 +6,24 @@ pub mod xml;
 pub mod compression;
 pub mod converter;
 pub mod tns_writer;
+pub mod latex;
 pub mod math_render;
+pub mod math_layout;
+pub mod math_draw;
+pub mod math_check;
+pub mod glyphs;
+pub mod symbols;
+pub mod notes;
+pub mod ti_expr;
+#[cfg(feature = "unverified-pages")]
+pub mod variables;
+#[cfg(feature = "unverified-pages")]
+pub mod csv;
+#[cfg(feature = "unverified-pages")]
+pub mod graph_spec;
+#[cfg(feature = "unverified-pages")]
+pub mod tibasic;
+#[cfg(feature = "unverified-pages")]
+pub mod image;
+pub mod python;
+pub mod encoding;
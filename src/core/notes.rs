@@ -0,0 +1,336 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! Native TI Notes pages from plain text
+//!
+//! Splits text into paragraphs of styled runs and serializes them as the
+//! rich-text tree stored in a TI Notes (`TI.Notepad`) widget. Unlike the Lua
+//! text viewer, the result can be edited, selected and copied on the device.
+//!
+//! Markup:
+//! - Every input line becomes a paragraph (empty lines are kept)
+//! - `**bold**` and `*italic*` style a run; unmatched markers stay literal
+//! - `$...$` and `\\(...\\)` become Math Boxes holding a calculator
+//!   expression; math that has no calculator form falls back to Unicode text
+//!   with a warning
+//! - Macros defined in one math region can be used in all later ones
+
+use super::latex::{self, Macros, Segment};
+use super::math_render::{nodes_to_unicode, MathScope, RenderOptions};
+use super::ti_expr::latex_to_ti_expression;
+
+/// Character styling of a run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextStyle {
+    pub bold: bool,
+    pub italic: bool,
+}
+
+/// A run of text inside a paragraph
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotesSpan {
+    Text { text: String, style: TextStyle },
+    /// Math Box with a TI-Nspire expression
+    Math { expr: String },
+}
+
+/// One paragraph (input line) of a Notes page
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NotesParagraph {
+    pub spans: Vec<NotesSpan>,
+}
+
+/// Parse text into Notes paragraphs
+///
+/// Math regions (`$...$`, `\(...\)`, see [`latex::split_math`]) become Math
+/// Boxes, with the macros defined so far expanded. Math that can't become a
+/// Math Box is rendered as one line of Unicode with `options`, and a message
+/// naming the line is added to `warnings`. The prose around it is only
+/// converted with [`MathScope::Document`].
+pub fn parse_notes(text: &str, options: &RenderOptions, warnings: &mut Vec<String>) -> Vec<NotesParagraph> {
+    let mut macros = Macros::default();
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let mut spans = Vec::new();
+
+            for segment in latex::split_math(line) {
+                match segment {
+                    Segment::Text(text) => parse_inline(&text, TextStyle::default(), options, &mut macros, &mut spans),
+                    Segment::Math(math) => {
+                        let source = latex::expand_macros(&math, &mut macros);
+                        if source.trim().is_empty() {
+                            // Only macro definitions
+                            continue;
+                        }
+                        match latex_to_ti_expression(&source) {
+                            Ok(expr) => spans.push(NotesSpan::Math { expr }),
+                            Err(reason) => {
+                                warnings.push(format!(
+                                    "line {}: ${}$ kept as text, no Math Box ({})",
+                                    index + 1,
+                                    math,
+                                    reason
+                                ));
+                                let text = nodes_to_unicode(&latex::parse(&source), options);
+                                spans.push(NotesSpan::Text { text, style: TextStyle::default() });
+                            }
+                        }
+                    }
+                }
+            }
+
+            NotesParagraph { spans }
+        })
+        .collect()
+}
+
+/// Split a line into styled runs, recursing into `**` and `*` pairs
+fn parse_inline(line: &str, style: TextStyle, options: &RenderOptions, macros: &mut Macros, spans: &mut Vec<NotesSpan>) {
+    let mut plain = String::new();
+    let mut rest = line;
+
+    while let Some(pos) = rest.find('*') {
+        let marker = if rest[pos..].starts_with("**") { "**" } else { "*" };
+        let after = &rest[pos + marker.len()..];
+
+        match find_closing_marker(after, marker) {
+            Some(end) if end > 0 => {
+                plain.push_str(&rest[..pos]);
+                push_text(spans, &plain, style, options, macros);
+                plain.clear();
+
+                let inner_style = if marker == "**" {
+                    TextStyle { bold: true, ..style }
+                } else {
+                    TextStyle { italic: true, ..style }
+                };
+                parse_inline(&after[..end], inner_style, options, macros, spans);
+                rest = &after[end + marker.len()..];
+            }
+            _ => {
+                // No partner on this line - keep the marker as text
+                plain.push_str(&rest[..pos + marker.len()]);
+                rest = after;
+            }
+        }
+    }
+
+    plain.push_str(rest);
+    push_text(spans, &plain, style, options, macros);
+}
+
+/// Find the closing marker, skipping `**` when looking for a single `*`
+fn find_closing_marker(text: &str, marker: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+
+    if marker == "**" {
+        // In `***` the first star closes an inner italic run
+        let mut end = text.find("**")?;
+        while bytes.get(end + 2) == Some(&b'*') {
+            end += 1;
+        }
+        return Some(end);
+    }
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'*' {
+            if i + 1 < bytes.len() && bytes[i + 1] == b'*' {
+                i += 2;
+                continue;
+            }
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+fn push_text(spans: &mut Vec<NotesSpan>, text: &str, style: TextStyle, options: &RenderOptions, macros: &mut Macros) {
+    if !text.is_empty() {
+        let text = match options.scope {
+            MathScope::Delimited => text.to_string(),
+            MathScope::Document => nodes_to_unicode(&latex::parse_with_macros(text, macros), options),
+        };
+        spans.push(NotesSpan::Text { text, style });
+    }
+}
+
+/// Serialize paragraphs as the Notes rich-text tree (`r2dtotree`)
+///
+/// The tree is plain XML text; it is escaped again when it is stored in the
+/// widget's `np:txt` element.
+pub fn to_r2d_tree(paragraphs: &[NotesParagraph]) -> String {
+    let mut tree = String::from("<r2dtotree><node name=\"1doc\">");
+
+    for paragraph in paragraphs {
+        tree.push_str("<node name=\"1para\"><node name=\"1rline\">");
+        for span in &paragraph.spans {
+            match span {
+                NotesSpan::Text { text, style } => push_styled_word(&mut tree, text, *style),
+                NotesSpan::Math { expr } => {
+                    tree.push_str("<node name=\"1mathbox\"><node name=\"1rline\">");
+                    push_styled_word(&mut tree, expr, TextStyle::default());
+                    tree.push_str("</node></node>");
+                }
+            }
+        }
+        tree.push_str("</node></node>");
+    }
+
+    tree.push_str("</node></r2dtotree>");
+    tree
+}
+
+fn push_styled_word(tree: &mut String, text: &str, style: TextStyle) {
+    if style.bold {
+        tree.push_str("<node name=\"1bold\">");
+    }
+    if style.italic {
+        tree.push_str("<node name=\"1ital\">");
+    }
+
+    tree.push_str("<leaf name=\"1word\">");
+    tree.push_str(&super::xml::escape_xml(text));
+    tree.push_str("</leaf>");
+
+    if style.italic {
+        tree.push_str("</node>");
+    }
+    if style.bold {
+        tree.push_str("</node>");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::symbols::SymbolTable;
+
+    fn text(text: &str, bold: bool, italic: bool) -> NotesSpan {
+        NotesSpan::Text { text: text.to_string(), style: TextStyle { bold, italic } }
+    }
+
+    #[test]
+    fn test_one_paragraph_per_line() {
+        let paragraphs = parse_notes("first\n\nthird", &RenderOptions::default(), &mut Vec::new());
+        assert_eq!(paragraphs.len(), 3);
+        assert_eq!(paragraphs[0].spans, vec![text("first", false, false)]);
+        assert!(paragraphs[1].spans.is_empty());
+    }
+
+    #[test]
+    fn test_bold_and_italic() {
+        let paragraphs = parse_notes("a **bold** and *italic* word", &RenderOptions::default(), &mut Vec::new());
+        assert_eq!(
+            paragraphs[0].spans,
+            vec![
+                text("a ", false, false),
+                text("bold", true, false),
+                text(" and ", false, false),
+                text("italic", false, true),
+                text(" word", false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_styles() {
+        let paragraphs = parse_notes("**very *important***", &RenderOptions::default(), &mut Vec::new());
+        assert_eq!(
+            paragraphs[0].spans,
+            vec![text("very ", true, false), text("important", true, true)]
+        );
+    }
+
+    #[test]
+    fn test_unmatched_marker_is_literal() {
+        let paragraphs = parse_notes("2 * 3 = 6", &RenderOptions::default(), &mut Vec::new());
+        assert_eq!(paragraphs[0].spans, vec![text("2 * 3 = 6", false, false)]);
+    }
+
+    #[test]
+    fn test_latex_is_converted() {
+        let options = RenderOptions { scope: MathScope::Document, ..Default::default() };
+        let paragraphs = parse_notes("E = mc^2", &options, &mut Vec::new());
+        assert_eq!(paragraphs[0].spans, vec![text("E = mc²", false, false)]);
+    }
+
+    #[test]
+    fn test_prose_is_kept() {
+        let paragraphs = parse_notes("my_file costs \\$5, \\(x^2\\)", &RenderOptions::default(), &mut Vec::new());
+        assert_eq!(
+            paragraphs[0].spans,
+            vec![text("my_file costs $5, ", false, false), NotesSpan::Math { expr: "x^2".to_string() }]
+        );
+    }
+
+    #[test]
+    fn test_r2d_tree() {
+        let tree = to_r2d_tree(&parse_notes("x < **y**", &RenderOptions::default(), &mut Vec::new()));
+        assert_eq!(
+            tree,
+            "<r2dtotree><node name=\"1doc\"><node name=\"1para\"><node name=\"1rline\">\
+<leaf name=\"1word\">x &lt; </leaf><node name=\"1bold\"><leaf name=\"1word\">y</leaf></node>\
+</node></node></node></r2dtotree>"
+        );
+    }
+
+    #[test]
+    fn test_math_box() {
+        let paragraphs = parse_notes("Area: $\\pi r^2$ units", &RenderOptions::default(), &mut Vec::new());
+        assert_eq!(
+            paragraphs[0].spans,
+            vec![
+                text("Area: ", false, false),
+                NotesSpan::Math { expr: "π*r^2".to_string() },
+                text(" units", false, false),
+            ]
+        );
+
+        let tree = to_r2d_tree(&paragraphs);
+        assert!(tree.contains("<node name=\"1mathbox\"><node name=\"1rline\"><leaf name=\"1word\">π*r^2</leaf>"));
+    }
+
+    #[test]
+    fn test_math_box_fallback() {
+        let mut warnings = Vec::new();
+        let paragraphs = parse_notes("ok\n$a_{i+1}$", &RenderOptions::default(), &mut warnings);
+
+        assert_eq!(paragraphs[1].spans, vec![text("aᵢ₊₁", false, false)]);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("line 2: "));
+        assert!(warnings[0].contains("a_{i+1}"));
+    }
+
+    #[test]
+    fn test_macros_and_symbols() {
+        let mut warnings = Vec::new();
+        let input = "$\\newcommand{\\half}{\\frac{1}{2}}\\def\\R{\\mathbb{R}}$\nArea: $\\half r$, $x \\in \\R$";
+        let paragraphs = parse_notes(input, &RenderOptions::default(), &mut warnings);
+        assert!(paragraphs[0].spans.is_empty());
+        assert_eq!(
+            paragraphs[1].spans,
+            vec![
+                text("Area: ", false, false),
+                NotesSpan::Math { expr: "1/2*r".to_string() },
+                text(", ", false, false),
+                text("x in ℝ", false, false),
+            ]
+        );
+        assert_eq!(warnings.len(), 1);
+
+        // The fallback uses the caller's symbols
+        let symbols = SymbolTable::from_toml("[commands]\nin = \"∈\"").unwrap();
+        let options = RenderOptions { symbols, ..Default::default() };
+        let paragraphs = parse_notes(input, &options, &mut Vec::new());
+        assert_eq!(paragraphs[1].spans[3], text("x ∈ ℝ", false, false));
+    }
+
+    #[test]
+    fn test_unpaired_dollar_is_text() {
+        let paragraphs = parse_notes("costs $5", &RenderOptions::default(), &mut Vec::new());
+        assert_eq!(paragraphs[0].spans, vec![text("costs $5", false, false)]);
+    }
+}
@@ -0,0 +1,246 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! Python projects made of several modules
+//!
+//! CX II documents can hold several `.py` files that import each other.
+//! Starting from the main script, local `import foo` and `from foo import`
+//! statements are followed to sibling `foo.py` files, so a project split
+//! into helper modules can be converted in one go. Imports with no matching
+//! file (`math`, `ti_system`, ...) are left to the calculator.
+//!
+//! File names follow the calculator's rules: an ASCII identifier
+//! (letters, digits, `_`, starting with a letter) plus `.py`.
+
+use std::path::Path;
+
+use super::encoding;
+
+/// One `.py` file stored in the document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PythonModule {
+    pub filename: String,
+    pub source: String,
+}
+
+impl PythonModule {
+    pub fn new(filename: &str, source: &str) -> Self {
+        Self { filename: filename.to_string(), source: source.to_string() }
+    }
+}
+
+/// Longest file name the Python editor accepts, in bytes
+pub const MAX_FILENAME_LEN: usize = 240;
+
+/// Check a file name against the calculator's rules
+///
+/// Returns the reason the name is rejected.
+pub fn validate_filename(filename: &str) -> Result<(), String> {
+    let stem = filename.strip_suffix(".py").ok_or("name must end in .py")?;
+    if filename.len() > MAX_FILENAME_LEN {
+        return Err(format!("name is longer than {} characters", MAX_FILENAME_LEN));
+    }
+    if !stem.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err("name must start with a letter".to_string());
+    }
+    if let Some(bad) = stem.chars().find(|c| !c.is_ascii_alphanumeric() && *c != '_') {
+        return Err(format!("'{}' is not allowed; use letters, digits and _", bad));
+    }
+    Ok(())
+}
+
+/// Turn a file name into one the calculator accepts
+///
+/// Runs of disallowed characters become `_`, the `.py` extension is
+/// enforced, and a name that doesn't start with a letter gets a `py_`
+/// prefix: `my script (2).py` becomes `my_script_2.py`.
+pub fn sanitize_filename(filename: &str) -> String {
+    let stem = filename.strip_suffix(".py").unwrap_or(filename);
+    let mut clean = String::with_capacity(stem.len());
+    for c in stem.chars() {
+        if c.is_ascii_alphanumeric() {
+            clean.push(c);
+        } else if !clean.ends_with('_') {
+            clean.push('_');
+        }
+    }
+    let mut clean = clean.trim_matches('_').to_string();
+
+    if !clean.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        clean.insert_str(0, "py_");
+    }
+    clean.truncate(MAX_FILENAME_LEN - ".py".len());
+    clean.push_str(".py");
+    clean
+}
+
+/// Top-level module names imported by the source
+///
+/// Handles `import a.b as c, d`, `from a import b`, relative imports
+/// (`from . import helper`, `from .helper import f`) and parenthesized
+/// import lists. Imports inside triple-quoted strings are ignored.
+pub fn find_imports(source: &str) -> Vec<String> {
+    let mut imports: Vec<String> = Vec::new();
+    let mut long_string = None;
+    let mut lines = source.lines();
+
+    while let Some(raw) = lines.next() {
+        // Skip lines that start inside a """...""" or '''...''' string
+        let in_string = long_string.is_some();
+        long_string = encoding::open_long_string(raw, long_string);
+        if in_string {
+            continue;
+        }
+
+        let code = raw.split('#').next().unwrap_or("");
+        for statement in code.split(';') {
+            let statement = statement.trim();
+
+            if let Some(names) = statement.strip_prefix("import ") {
+                for name in names.split(',') {
+                    push_module(&mut imports, name.split_whitespace().next().unwrap_or(""));
+                }
+            } else if let Some(rest) = statement.strip_prefix("from ") {
+                let Some((module, names)) = rest.split_once(" import") else {
+                    continue;
+                };
+                let module = module.trim();
+                let relative = module.trim_start_matches('.');
+
+                // A parenthesized name list can continue over several lines
+                let mut names = names.to_string();
+                if names.contains('(') && !names.contains(')') {
+                    for next in lines.by_ref() {
+                        names.push_str(next.split('#').next().unwrap_or(""));
+                        if next.contains(')') {
+                            break;
+                        }
+                    }
+                }
+
+                if !relative.is_empty() {
+                    push_module(&mut imports, relative);
+                } else if module.starts_with('.') {
+                    // `from . import a, b` names sibling modules
+                    for name in names.trim_matches(|c: char| c.is_whitespace() || c == '(' || c == ')').split(',') {
+                        let name = name.trim_matches(|c: char| c.is_whitespace() || c == '(' || c == ')');
+                        push_module(&mut imports, name.split_whitespace().next().unwrap_or(""));
+                    }
+                }
+            }
+        }
+    }
+
+    imports
+}
+
+fn push_module(imports: &mut Vec<String>, name: &str) {
+    let top = name.split('.').next().unwrap_or("");
+    let is_identifier = top.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && top.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if is_identifier && !imports.iter().any(|i| i == top) {
+        imports.push(top.to_string());
+    }
+}
+
+/// Collect the main script and every local module it imports, recursively
+///
+/// Modules are looked up as `<name>.py` in `dir` and read with `load`. The
+/// main script comes first, followed by its dependencies in the order they
+/// are found.
+pub fn collect_modules<E>(
+    main: PythonModule,
+    dir: &Path,
+    mut load: impl FnMut(&Path) -> Result<String, E>,
+) -> Result<Vec<PythonModule>, E> {
+    let mut modules = vec![main];
+    let mut next = 0;
+
+    while next < modules.len() {
+        for name in find_imports(&modules[next].source) {
+            let filename = format!("{}.py", name);
+            if modules.iter().any(|m| m.filename.eq_ignore_ascii_case(&filename)) {
+                continue;
+            }
+            let path = dir.join(&filename);
+            if path.is_file() {
+                let source = load(&path)?;
+                modules.push(PythonModule { filename, source });
+            }
+        }
+        next += 1;
+    }
+
+    Ok(modules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_imports() {
+        let source = "import math, helpers.io as hio\n\
+from geometry import area  # shapes\n\
+from . import (stats,\n    plots)\n\
+from .units import meters\n\
+x = 1; import ti_draw\n\
+\"\"\"\nimport not_code\n\"\"\"\n\
+# import commented\n";
+        assert_eq!(
+            find_imports(source),
+            vec!["math", "helpers", "geometry", "stats", "plots", "units", "ti_draw"]
+        );
+    }
+
+    #[test]
+    fn test_find_imports_multiline_from() {
+        let source = "from shapes import (\n    area,\n    perimeter,\n)\nfrom . import (\n    stats)\nimport ti_plotlib\n";
+        assert_eq!(find_imports(source), vec!["shapes", "stats", "ti_plotlib"]);
+    }
+
+    #[test]
+    fn test_find_imports_quotes_in_strings() {
+        // A triple quote inside a short string or comment opens nothing
+        let source = "s = '\"\"\"'  # '''\nimport shapes\nt = \"\"\"a\"\"\" + \"\"\"\nimport not_code\n\"\"\"\nimport stats\n";
+        assert_eq!(find_imports(source), vec!["shapes", "stats"]);
+    }
+
+    #[test]
+    fn test_validate_filename() {
+        assert!(validate_filename("main.py").is_ok());
+        assert!(validate_filename("Lab_2.py").is_ok());
+        assert!(validate_filename("main.txt").is_err());
+        assert!(validate_filename("2nd.py").is_err());
+        assert!(validate_filename("my script.py").is_err());
+        assert!(validate_filename("größe.py").is_err());
+        assert!(validate_filename("a&b.py").is_err());
+        assert!(validate_filename(&format!("{}.py", "a".repeat(240))).is_err());
+    }
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("my script (2).py"), "my_script_2.py");
+        assert_eq!(sanitize_filename("größe.py"), "gr_e.py");
+        assert_eq!(sanitize_filename("2nd.py"), "py_2nd.py");
+        assert_eq!(sanitize_filename("script"), "script.py");
+        assert_eq!(sanitize_filename("(((.py"), "py_.py");
+        assert!(validate_filename(&sanitize_filename(&"ü".repeat(300))).is_ok());
+    }
+
+    #[test]
+    fn test_collect_modules() {
+        let dir = std::env::temp_dir().join("luna_rs_python_project");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("shapes.py"), "from util import square\n").unwrap();
+        std::fs::write(dir.join("util.py"), "import shapes\ndef square(x): return x*x\n").unwrap();
+
+        let main = PythonModule::new("main.py", "import math\nimport shapes\nimport util\n");
+        let modules = collect_modules(main, &dir, |path: &Path| std::fs::read_to_string(path)).unwrap();
+        let names: Vec<_> = modules.iter().map(|m| m.filename.as_str()).collect();
+        assert_eq!(names, vec!["main.py", "shapes.py", "util.py"]);
+
+        // Clean up
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
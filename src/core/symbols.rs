@@ -0,0 +1,134 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! User symbol tables for the math renderer
+//!
+//! A table extends or overrides the built-in command, superscript and
+//! subscript mappings of `math_render`, so a course can use its own
+//! notation without rebuilding the tool:
+//!
+//! ```toml
+//! [commands]
+//! R = "ℝ"            # \R
+//! implies = "⟹"     # replaces the built-in "=>"
+//! frac18 = "1/8"     # \frac18 and \frac{1}{8}
+//!
+//! [superscripts]
+//! a = "ᵃ"
+//!
+//! [subscripts]
+//! b = "ᵦ"
+//! ```
+//!
+//! Command names may be written with or without the leading backslash.
+//! Entries win over the built-in tables and over the device glyph profile.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// Errors that can occur while loading a symbol table
+#[derive(Debug, Error)]
+pub enum SymbolTableError {
+    #[error("TOML error: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("{section}.{key}: {message}")]
+    Invalid { section: &'static str, key: String, message: String },
+}
+
+/// Symbols added to or replacing the built-in tables
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    /// Command name without the backslash → text
+    pub commands: HashMap<String, String>,
+    pub superscripts: HashMap<char, char>,
+    pub subscripts: HashMap<char, char>,
+}
+
+/// TOML layout of a symbol table file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlTable {
+    #[serde(default)]
+    commands: HashMap<String, String>,
+    #[serde(default)]
+    superscripts: HashMap<String, String>,
+    #[serde(default)]
+    subscripts: HashMap<String, String>,
+}
+
+impl SymbolTable {
+    /// Parse a symbol table file
+    pub fn from_toml(text: &str) -> Result<Self, SymbolTableError> {
+        let toml_table: TomlTable = toml::from_str(text)?;
+
+        let mut commands = HashMap::new();
+        for (key, value) in toml_table.commands {
+            let name = key.strip_prefix('\\').unwrap_or(&key);
+            if name.is_empty() {
+                return Err(SymbolTableError::Invalid {
+                    section: "commands",
+                    key,
+                    message: "command name is empty".to_string(),
+                });
+            }
+            commands.insert(name.to_string(), value);
+        }
+
+        Ok(Self {
+            commands,
+            superscripts: char_map("superscripts", toml_table.superscripts)?,
+            subscripts: char_map("subscripts", toml_table.subscripts)?,
+        })
+    }
+}
+
+/// Check that a script table maps single characters to single characters
+fn char_map(section: &'static str, table: HashMap<String, String>) -> Result<HashMap<char, char>, SymbolTableError> {
+    table
+        .into_iter()
+        .map(|(key, value)| match (single_char(&key), single_char(&value)) {
+            (Some(from), Some(to)) => Ok((from, to)),
+            _ => Err(SymbolTableError::Invalid {
+                section,
+                key,
+                message: format!("expected one character mapped to one character, got \"{}\"", value),
+            }),
+        })
+        .collect()
+}
+
+fn single_char(text: &str) -> Option<char> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_toml() {
+        let table = SymbolTable::from_toml(
+            "[commands]\nR = \"ℝ\"\n\"\\\\implies\" = \"⟹\"\n\n[superscripts]\na = \"ᵃ\"\n\n[subscripts]\nb = \"ᵦ\"\n",
+        )
+        .unwrap();
+        assert_eq!(table.commands["R"], "ℝ");
+        assert_eq!(table.commands["implies"], "⟹");
+        assert_eq!(table.superscripts[&'a'], 'ᵃ');
+        assert_eq!(table.subscripts[&'b'], 'ᵦ');
+    }
+
+    #[test]
+    fn test_invalid_tables() {
+        assert!(matches!(
+            SymbolTable::from_toml("[superscripts]\nab = \"x\""),
+            Err(SymbolTableError::Invalid { section: "superscripts", .. })
+        ));
+        assert!(SymbolTable::from_toml("[greek]\na = \"α\"").is_err());
+    }
+}
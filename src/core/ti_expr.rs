@@ -0,0 +1,574 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! LaTeX to TI-Nspire expression syntax
+//!
+//! Turns inline LaTeX math into input the calculator can evaluate, such as
+//! the contents of a Notes Math Box or a Calculator line (`luna-rs expr`):
+//! `\frac{a+b}{2}` → `(a+b)/2`, `x^{n+1}` → `x^(n+1)`, `\sqrt[3]{x}` →
+//! `root(x,3)`, `\int_a^b f\,dx` → `∫(f,x,a,b)`, `\sum_{k=1}^n k^2` →
+//! `∑(k^2,k,1,n)`, `\binom{n}{k}` → `nCr(n,k)`, `|x|` → `abs(x)`.
+//!
+//! The body of `\sum` and `\prod` runs to the end of the term, so
+//! `\sum_{k=1}^n k + 1` is `∑(k,k,1,n) + 1`; the body of `\int` runs to its
+//! differential.
+//!
+//! Only constructs with a direct calculator equivalent are accepted; anything
+//! else is reported as an error so callers can fall back to Unicode text.
+
+use super::math_render::latex_to_unicode;
+
+/// Functions written as `\name` in LaTeX and `name(...)` on the calculator
+const FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc",
+    "arcsin", "arccos", "arctan",
+    "sinh", "cosh", "tanh",
+    "ln", "log", "exp",
+    "min", "max", "gcd", "lcm",
+];
+
+/// Convert a LaTeX math expression to TI-Nspire syntax
+///
+/// Returns a description of the first unsupported construct on failure.
+pub fn latex_to_ti_expression(latex: &str) -> Result<String, String> {
+    let mut converter = ExprConverter {
+        chars: latex.chars().collect(),
+        pos: 0,
+    };
+    let expr = converter.sequence(Until::End)?;
+    let expr = expr.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if expr.is_empty() {
+        return Err("empty expression".to_string());
+    }
+    Ok(expr)
+}
+
+/// Where a sequence of input ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Until {
+    /// The end of the input
+    End,
+    /// The `}` of the group it is in
+    Group,
+    /// A `+`, `-` or relation outside parentheses, for the body of `\sum`
+    Term,
+    /// A differential such as `dx`, for the body of `\int`
+    Differential,
+    /// A closing `|` or `\right|`, for the body of an absolute value
+    Bar,
+}
+
+struct ExprConverter {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl ExprConverter {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Convert until the end of input, or until the end given by `until`
+    fn sequence(&mut self, until: Until) -> Result<String, String> {
+        let mut out = String::new();
+
+        while let Some(c) = self.peek() {
+            let ends = match until {
+                Until::Term => ends_term(c, &out),
+                Until::Differential => self.differential().is_some(),
+                Until::Bar => self.closing_bar().is_some(),
+                Until::End | Until::Group => false,
+            };
+            if ends {
+                return Ok(out);
+            }
+            match c {
+                '}' if until != Until::End => return Ok(out),
+                '}' => return Err("unbalanced '}'".to_string()),
+                '{' => {
+                    self.pos += 1;
+                    let body = self.group_body()?;
+                    push_factor(&mut out, &body, Piece::Source);
+                }
+                '^' => {
+                    self.pos += 1;
+                    let exponent = self.argument()?;
+                    let base_end = out.len();
+                    out.push('^');
+                    out.push_str(&wrap_operand(&exponent));
+
+                    // x^2_1 is x_1^2
+                    let next = self.chars[self.pos..].iter().position(|c| !c.is_whitespace());
+                    if let Some(skip) = next
+                        && self.chars[self.pos + skip] == '_'
+                    {
+                        self.pos += skip + 1;
+                        let index = self.argument()?;
+                        attach_index(&mut out, base_end, &index)?;
+                    }
+                }
+                '_' => {
+                    self.pos += 1;
+                    let index = self.argument()?;
+                    let end = out.len();
+                    attach_index(&mut out, end, &index)?;
+                }
+                '|' => {
+                    self.pos += 1;
+                    let abs = self.absolute_value()?;
+                    push_factor(&mut out, &abs, Piece::Result);
+                }
+                '\\' => {
+                    self.pos += 1;
+                    let command = self.command()?;
+                    push_factor(&mut out, &command, Piece::Result);
+                }
+                '&' | '#' | '%' | '~' | '$' => {
+                    return Err(format!("unexpected '{}'", c));
+                }
+                '(' if self.defines_function(&out) => {
+                    // f(x)=... defines f rather than multiplying by f
+                    self.pos += 1;
+                    out.push('(');
+                }
+                _ => {
+                    self.pos += 1;
+                    push_factor(&mut out, c.encode_utf8(&mut [0; 4]), Piece::Source);
+                }
+            }
+        }
+
+        if until == Until::Group {
+            return Err("unclosed group".to_string());
+        }
+        Ok(out)
+    }
+
+    /// Body of a `{...}` group; the opening brace is already consumed
+    fn group_body(&mut self) -> Result<String, String> {
+        let body = self.sequence(Until::Group)?;
+        self.pos += 1; // closing brace
+        Ok(body)
+    }
+
+    /// A command argument: a group, a command or a single character
+    fn argument(&mut self) -> Result<String, String> {
+        self.skip_spaces();
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                self.group_body()
+            }
+            Some('\\') => {
+                self.pos += 1;
+                self.command()
+            }
+            Some(c) if c != '}' && c != '^' && c != '_' => {
+                self.pos += 1;
+                Ok(c.to_string())
+            }
+            _ => Err("missing argument".to_string()),
+        }
+    }
+
+    /// `|x|` or `\left|x\right|` as `abs(x)`; the opening bar is already
+    /// consumed
+    fn absolute_value(&mut self) -> Result<String, String> {
+        let body = self.sequence(Until::Bar)?;
+        self.pos = self.closing_bar().ok_or("unclosed '|'")?;
+        if body.trim().is_empty() {
+            return Err("empty '|...|'".to_string());
+        }
+        Ok(format!("abs({})", body.trim()))
+    }
+
+    /// The position after a `|` or `\right|` at the current position
+    fn closing_bar(&self) -> Option<usize> {
+        if self.peek() == Some('|') {
+            return Some(self.pos + 1);
+        }
+        let rest = self.chars.get(self.pos..)?;
+        if !rest.starts_with(&['\\', 'r', 'i', 'g', 'h', 't']) {
+            return None;
+        }
+        let bar = rest[6..].iter().position(|&c| c != ' ')?;
+        (rest[6 + bar] == '|').then_some(self.pos + 6 + bar + 1)
+    }
+
+    /// The `_` and `^` limits of a big operator, in either order
+    fn limits(&mut self) -> Result<(Option<String>, Option<String>), String> {
+        let (mut lower, mut upper) = (None, None);
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some('_') if lower.is_none() => {
+                    self.pos += 1;
+                    lower = Some(self.argument()?);
+                }
+                Some('^') if upper.is_none() => {
+                    self.pos += 1;
+                    upper = Some(self.argument()?);
+                }
+                _ => return Ok((lower, upper)),
+            }
+        }
+    }
+
+    /// Whether the `(` at the current position follows a single-letter name
+    /// at the start of the output and its matching `)` is followed by `=` or
+    /// `:=`, as in `f(x)=x^2`
+    fn defines_function(&self, out: &str) -> bool {
+        let mut name = out.trim().chars();
+        if !matches!((name.next(), name.next()), (Some(letter), None) if letter.is_alphabetic()) {
+            return false;
+        }
+
+        let mut depth = 0;
+        for (i, &c) in self.chars.iter().enumerate().skip(self.pos) {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 1 => {
+                    let rest: String = self.chars[i + 1..].iter().collect();
+                    let rest = rest.trim_start();
+                    return rest.starts_with('=') || rest.starts_with(":=");
+                }
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// The variable of a differential (`dx`, `d x`) at the current position,
+    /// and the position after it
+    fn differential(&self) -> Option<(char, usize)> {
+        let previous = self.pos.checked_sub(1).and_then(|i| self.chars.get(i));
+        if self.peek() != Some('d') || previous.is_some_and(|c| c.is_alphanumeric()) {
+            return None;
+        }
+        let mut i = self.pos + 1;
+        while self.chars.get(i) == Some(&' ') {
+            i += 1;
+        }
+        let variable = *self.chars.get(i).filter(|c| c.is_ascii_alphabetic())?;
+        if self.chars.get(i + 1).is_some_and(|c| c.is_alphanumeric()) {
+            return None;
+        }
+        Some((variable, i + 1))
+    }
+
+    /// Convert a command; the backslash is already consumed
+    fn command(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        if self.pos == start && self.peek().is_some() {
+            self.pos += 1;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+
+        match name.as_str() {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.argument()?;
+                let denominator = self.argument()?;
+                Ok(format!("{}/{}", wrap_operand(&numerator), wrap_operand(&denominator)))
+            }
+            "sqrt" => {
+                self.skip_spaces();
+                let degree = if self.peek() == Some('[') {
+                    self.pos += 1;
+                    let end = self.chars[self.pos..]
+                        .iter()
+                        .position(|&c| c == ']')
+                        .ok_or("unclosed '[' in \\sqrt")?;
+                    let inner: String = self.chars[self.pos..self.pos + end].iter().collect();
+                    self.pos += end + 1;
+                    Some(latex_to_ti_expression(&inner)?)
+                } else {
+                    None
+                };
+                let radicand = self.argument()?;
+                Ok(match degree {
+                    Some(n) => format!("root({},{})", radicand, n),
+                    None => format!("√({})", radicand),
+                })
+            }
+            "int" => {
+                let limits = self.limits()?;
+                let body = self.sequence(Until::Differential)?;
+                let (variable, end) = self.differential().ok_or("\\int without a differential such as dx")?;
+                self.pos = end;
+
+                let body = match body.trim() {
+                    "" => "1",
+                    body => body,
+                };
+                match limits {
+                    (Some(lower), Some(upper)) => Ok(format!("∫({},{},{},{})", body, variable, lower, upper)),
+                    (None, None) => Ok(format!("∫({},{})", body, variable)),
+                    _ => Err("\\int needs both limits or none".to_string()),
+                }
+            }
+            "sum" | "prod" => {
+                let (Some(lower), Some(upper)) = self.limits()? else {
+                    return Err(format!("\\{} needs limits such as _{{k=1}}^n", name));
+                };
+                let (variable, start) = lower
+                    .split_once('=')
+                    .ok_or_else(|| format!("\\{} needs a lower limit such as k=1, got '{}'", name, lower))?;
+                let body = self.sequence(Until::Term)?;
+                if body.trim().is_empty() {
+                    return Err(format!("\\{} without a body", name));
+                }
+
+                // Keep the space before the next term
+                let spacing = &body[body.trim_end().len()..];
+                let symbol = if name == "sum" { '∑' } else { '∏' };
+                Ok(format!("{}({},{},{},{}){}", symbol, body.trim(), variable.trim(), start.trim(), upper, spacing))
+            }
+            "binom" => {
+                let n = self.argument()?;
+                let k = self.argument()?;
+                Ok(format!("nCr({},{})", n, k))
+            }
+            "left" | "right" => {
+                self.skip_spaces();
+                match self.peek() {
+                    Some('|') if name == "left" => {
+                        self.pos += 1;
+                        self.absolute_value()
+                    }
+                    Some('|') => Err("\\right| without \\left|".to_string()),
+                    Some(c @ ('(' | ')' | '[' | ']')) => {
+                        self.pos += 1;
+                        Ok(c.to_string())
+                    }
+                    Some('.') => {
+                        self.pos += 1;
+                        Ok(String::new())
+                    }
+                    _ => Err(format!("unsupported delimiter after \\{}", name)),
+                }
+            }
+            "cdot" | "times" | "ast" => Ok("*".to_string()),
+            "div" => Ok("/".to_string()),
+            "le" | "leq" => Ok("≤".to_string()),
+            "ge" | "geq" => Ok("≥".to_string()),
+            "ne" | "neq" => Ok("≠".to_string()),
+            "infty" => Ok("∞".to_string()),
+            "," | ";" | ":" | "!" | " " | "quad" | "qquad" => Ok(String::new()),
+            _ if FUNCTIONS.contains(&name.as_str()) => {
+                self.skip_spaces();
+                if self.peek() == Some('(') {
+                    // The parenthesized argument follows as it is
+                    self.pos += 1;
+                    Ok(format!("{}(", name))
+                } else {
+                    let argument = self.argument()?;
+                    Ok(format!("{}({})", name, argument))
+                }
+            }
+            _ => {
+                // Greek letters are valid calculator variable names
+                let symbol = latex_to_unicode(&format!("\\{}", name));
+                let mut chars = symbol.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c.is_alphabetic() && !c.is_ascii() => Ok(symbol),
+                    _ => Err(format!("unsupported command \\{}", name)),
+                }
+            }
+        }
+    }
+}
+
+/// Where a piece of output passed to [`push_factor`] comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Piece {
+    /// Characters as written in the source, or the body of a group
+    Source,
+    /// The conversion of a command, such as `1/2` for `\frac12`
+    Result,
+}
+
+/// Append output, with a `*` where it multiplies what comes before
+///
+/// The calculator reads `ac` as one variable and `2(x+1)` as a call, so
+/// factors LaTeX writes side by side (`4ac`, `2\pi r`, `x(x+1)`) get an
+/// explicit `*`; spaces between them are dropped. Digits written after a
+/// letter or digit are kept together (`x2`, `12`), but a command result is
+/// always a separate factor, parenthesized if it has more than one term:
+/// `2\frac{1}{3}` is `2*(1/3)`.
+fn push_factor(out: &mut String, piece: &str, kind: Piece) {
+    let (Some(before), Some(after)) = (out.trim_end().chars().last(), piece.chars().next()) else {
+        out.push_str(piece);
+        return;
+    };
+    let ends_operand = before.is_alphanumeric() || matches!(before, ')' | '.' | '!' | '∞');
+    let starts_operand = after.is_alphanumeric() || matches!(after, '(' | '√' | '∫' | '∑' | '∏' | '∞');
+    let is_number = |c: char| c.is_ascii_digit() || c == '.';
+    let joined = kind == Piece::Source
+        && ((is_number(before) && is_number(after)) || (before.is_alphabetic() && after.is_ascii_digit()));
+
+    if ends_operand && starts_operand && !joined {
+        out.truncate(out.trim_end().len());
+        out.push('*');
+        if kind == Piece::Result && !is_single_factor(piece) {
+            out.push_str(&format!("({})", piece));
+            return;
+        }
+    }
+    out.push_str(piece);
+}
+
+/// Whether `piece` is one number, name or call, with no operator outside
+/// parentheses
+fn is_single_factor(piece: &str) -> bool {
+    let mut depth = 0;
+    piece.chars().all(|c| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        depth > 0 || c.is_alphanumeric() || matches!(c, '(' | ')' | '.' | '√' | '∫' | '∑' | '∏' | '∞')
+    })
+}
+
+/// Whether `c` ends the term in `out`: a sum or relation outside
+/// parentheses, but not a leading sign
+fn ends_term(c: char, out: &str) -> bool {
+    let depth: i32 = out
+        .chars()
+        .map(|c| match c {
+            '(' => 1,
+            ')' => -1,
+            _ => 0,
+        })
+        .sum();
+    matches!(c, '+' | '-' | '=' | '<' | '>') && depth == 0 && !out.trim().is_empty()
+}
+
+/// Append a subscript to the name that ends at `at` in `out`
+///
+/// `x_1` is the variable `x1` on the calculator; other subscripts, and
+/// subscripts on anything but a name, have no calculator form.
+fn attach_index(out: &mut String, at: usize, index: &str) -> Result<(), String> {
+    if !index.chars().all(char::is_alphanumeric) {
+        return Err(format!("subscript '{}' has no calculator form", index));
+    }
+    if !out[..at].chars().last().is_some_and(char::is_alphanumeric) {
+        return Err(format!("subscript '{}' needs a variable name before it", index));
+    }
+    out.insert_str(at, index);
+    Ok(())
+}
+
+/// Parenthesize an operand unless it is a single number or name
+fn wrap_operand(operand: &str) -> String {
+    let is_number = operand.chars().all(|c| c.is_ascii_digit() || c == '.');
+    let is_name = operand.chars().all(char::is_alphabetic);
+    if !operand.is_empty() && (is_number || is_name) {
+        operand.to_string()
+    } else {
+        format!("({})", operand)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_expression() {
+        assert_eq!(latex_to_ti_expression("x^2+1").unwrap(), "x^2+1");
+        assert_eq!(latex_to_ti_expression(" 2x - 3 ").unwrap(), "2*x - 3");
+        assert_eq!(latex_to_ti_expression("mc^2 + 3.5 x(x+1)").unwrap(), "m*c^2 + 3.5*x*(x+1)");
+    }
+
+    #[test]
+    fn test_scripts() {
+        assert_eq!(latex_to_ti_expression("x^{n+1}").unwrap(), "x^(n+1)");
+        assert_eq!(latex_to_ti_expression("a_1 + a_{12}").unwrap(), "a1 + a12");
+        assert!(latex_to_ti_expression("a_{i+1}").is_err());
+        // The index belongs to the base, in either order
+        assert_eq!(latex_to_ti_expression("x^2_1 + x_1^2").unwrap(), "x1^2 + x1^2");
+        assert_eq!(latex_to_ti_expression("a^{n} _{k}").unwrap(), "ak^n");
+        assert!(latex_to_ti_expression("(x+1)_1").is_err());
+        assert!(latex_to_ti_expression("(x+1)^2_1").is_err());
+    }
+
+    #[test]
+    fn test_absolute_value() {
+        assert_eq!(latex_to_ti_expression("|x-1|").unwrap(), "abs(x-1)");
+        assert_eq!(latex_to_ti_expression("2|x| + 1").unwrap(), "2*abs(x) + 1");
+        assert_eq!(latex_to_ti_expression("\\left| \\frac{a}{b} \\right|^2").unwrap(), "abs(a/b)^2");
+        assert_eq!(latex_to_ti_expression("|\\left|x\\right| - 1|").unwrap(), "abs(abs(x) - 1)");
+        assert_eq!(latex_to_ti_expression("\\sum_{k=1}^n |a_k|").unwrap(), "∑(abs(ak),k,1,n)");
+        assert!(latex_to_ti_expression("|x").is_err());
+        assert!(latex_to_ti_expression("||").is_err());
+        assert!(latex_to_ti_expression("x \\right|").is_err());
+    }
+
+    #[test]
+    fn test_fractions_and_roots() {
+        assert_eq!(latex_to_ti_expression("\\frac{a+b}{2}").unwrap(), "(a+b)/2");
+        assert_eq!(latex_to_ti_expression("\\frac12").unwrap(), "1/2");
+        assert_eq!(latex_to_ti_expression("\\sqrt{b^2-4ac}").unwrap(), "√(b^2-4*a*c)");
+        assert_eq!(latex_to_ti_expression("\\sqrt[3]{x}").unwrap(), "root(x,3)");
+        assert_eq!(latex_to_ti_expression("2\\frac{1}{3}").unwrap(), "2*(1/3)");
+        assert_eq!(latex_to_ti_expression("x\\frac12").unwrap(), "x*(1/2)");
+        assert_eq!(latex_to_ti_expression("x_1\\frac{1}{2}").unwrap(), "x1*(1/2)");
+    }
+
+    #[test]
+    fn test_function_definition() {
+        assert_eq!(latex_to_ti_expression("f(x)=x^2").unwrap(), "f(x)=x^2");
+        assert_eq!(latex_to_ti_expression("g(t) := 2t").unwrap(), "g(t) := 2*t");
+        assert_eq!(latex_to_ti_expression("2x(x+1)=0").unwrap(), "2*x*(x+1)=0");
+        assert_eq!(latex_to_ti_expression("ab(x)=0").unwrap(), "a*b*(x)=0");
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(latex_to_ti_expression("2\\pi r").unwrap(), "2*π*r");
+        assert_eq!(latex_to_ti_expression("a \\cdot b \\leq c").unwrap(), "a * b ≤ c");
+        assert_eq!(latex_to_ti_expression("\\sin x + \\cos(x)").unwrap(), "sin(x) + cos(x)");
+        assert_eq!(latex_to_ti_expression("2\\sin{x} \\cos (2x)").unwrap(), "2*sin(x)*cos(2*x)");
+        assert_eq!(latex_to_ti_expression("\\left(x+1\\right)^2").unwrap(), "(x+1)^2");
+    }
+
+    #[test]
+    fn test_calculus() {
+        assert_eq!(latex_to_ti_expression("\\int_a^b f\\,dx").unwrap(), "∫(f,x,a,b)");
+        assert_eq!(latex_to_ti_expression("\\int_0^{\\pi} \\sin(t) dt + 1").unwrap(), "∫(sin(t),t,0,π) + 1");
+        assert_eq!(latex_to_ti_expression("\\int x^2 \\, d x").unwrap(), "∫(x^2,x)");
+        assert_eq!(latex_to_ti_expression("\\sum_{k=1}^n k^2").unwrap(), "∑(k^2,k,1,n)");
+        assert_eq!(latex_to_ti_expression("\\sum_{k=1}^n 2k").unwrap(), "∑(2*k,k,1,n)");
+        assert_eq!(latex_to_ti_expression("\\int_0^1 3x^2 \\, dx").unwrap(), "∫(3*x^2,x,0,1)");
+        assert_eq!(latex_to_ti_expression("\\sum_{k=0}^{\\infty} (-1)^k x + 1").unwrap(), "∑((-1)^k*x,k,0,∞) + 1");
+        assert_eq!(latex_to_ti_expression("\\prod^{n}_{i=1} i").unwrap(), "∏(i,i,1,n)");
+        assert_eq!(latex_to_ti_expression("\\binom{n}{k} p^k").unwrap(), "nCr(n,k)*p^k");
+    }
+
+    #[test]
+    fn test_unsupported() {
+        assert_eq!(
+            latex_to_ti_expression("\\oint f").unwrap_err(),
+            "unsupported command \\oint"
+        );
+        assert!(latex_to_ti_expression("\\int_0 f dx").is_err());
+        assert!(latex_to_ti_expression("\\int f").is_err());
+        assert!(latex_to_ti_expression("\\sum_k k").is_err());
+        assert!(latex_to_ti_expression("x^{2").is_err());
+        assert!(latex_to_ti_expression("a & b").is_err());
+        assert!(latex_to_ti_expression("").is_err());
+    }
+}
@@ -0,0 +1,274 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! TI-Basic programs and functions from text source
+//!
+//! Reads `Define` blocks in the calculator's own syntax:
+//!
+//! ```text
+//! Define LibPub area(r)=Func
+//!   Return π*r^2
+//! EndFunc
+//!
+//! Define hello()=
+//! Prgm
+//!   Disp "Hello"
+//! EndPrgm
+//! ```
+//!
+//! Each block becomes a stored program or function in the document.
+//! `LibPub` and `LibPriv` are kept, so a document with `LibPub` definitions
+//! can be copied to MyLib and used as a library.
+
+use thiserror::Error;
+
+use super::variables::{self, DocVariable, VarValue};
+
+/// Errors that can occur while reading TI-Basic source
+#[derive(Debug, Error)]
+pub enum TiBasicError {
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+    #[error("No Define blocks found")]
+    Empty,
+}
+
+/// Whether a definition is a program or a function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefinitionKind {
+    Program,
+    Function,
+}
+
+/// Library visibility of a definition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LibAccess {
+    /// Only visible inside the document
+    None,
+    /// Listed in the catalog when the document is in MyLib
+    LibPub,
+    /// Usable from other library objects but not listed
+    LibPriv,
+}
+
+/// One `Define ... EndPrgm`/`EndFunc` block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TiBasicDefinition {
+    pub name: String,
+    pub params: Vec<String>,
+    pub kind: DefinitionKind,
+    pub access: LibAccess,
+    /// Lines between `Prgm`/`Func` and the end marker, as written
+    pub body: Vec<String>,
+}
+
+impl TiBasicDefinition {
+    /// Full `Define` statement as the calculator stores it
+    pub fn source(&self) -> String {
+        let access = match self.access {
+            LibAccess::None => "",
+            LibAccess::LibPub => "LibPub ",
+            LibAccess::LibPriv => "LibPriv ",
+        };
+        let (start, end) = match self.kind {
+            DefinitionKind::Program => ("Prgm", "EndPrgm"),
+            DefinitionKind::Function => ("Func", "EndFunc"),
+        };
+
+        let mut source = format!("Define {}{}({})={}\n", access, self.name, self.params.join(","), start);
+        for line in &self.body {
+            source.push_str(line);
+            source.push('\n');
+        }
+        source.push_str(end);
+        source
+    }
+
+    /// The definition as a document variable
+    pub fn to_variable(&self) -> DocVariable {
+        let value = match self.kind {
+            DefinitionKind::Program => VarValue::Program(self.source()),
+            DefinitionKind::Function => VarValue::Function(self.source()),
+        };
+        DocVariable::new(&self.name, value)
+    }
+}
+
+/// Parse all `Define` blocks in the source
+///
+/// Blank lines and lines starting with `©` (the TI-Basic comment mark) are
+/// allowed between blocks.
+pub fn parse_tibasic(source: &str) -> Result<Vec<TiBasicDefinition>, TiBasicError> {
+    let mut definitions: Vec<TiBasicDefinition> = Vec::new();
+    let mut lines = source.lines().enumerate().peekable();
+
+    while let Some((index, raw)) = lines.next() {
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('©') {
+            continue;
+        }
+        let syntax = |line: usize, message: String| TiBasicError::Syntax { line: line + 1, message };
+
+        // `Define f()=` may put Prgm/Func on the next line
+        let mut header = line.to_string();
+        if header.ends_with('=')
+            && let Some((_, next)) = lines.peek()
+        {
+            let keyword = next.trim();
+            if keyword.eq_ignore_ascii_case("Prgm") || keyword.eq_ignore_ascii_case("Func") {
+                header.push_str(keyword);
+                lines.next();
+            }
+        }
+
+        let mut definition = parse_header(&header).map_err(|m| syntax(index, m))?;
+        if definitions.iter().any(|d| d.name.eq_ignore_ascii_case(&definition.name)) {
+            return Err(syntax(index, format!("{} is defined twice", definition.name)));
+        }
+
+        let end_marker = match definition.kind {
+            DefinitionKind::Program => "EndPrgm",
+            DefinitionKind::Function => "EndFunc",
+        };
+
+        loop {
+            let Some((_, body_line)) = lines.next() else {
+                return Err(syntax(index, format!("{} has no {}", definition.name, end_marker)));
+            };
+            if body_line.trim().eq_ignore_ascii_case(end_marker) {
+                break;
+            }
+            definition.body.push(body_line.to_string());
+        }
+
+        definitions.push(definition);
+    }
+
+    if definitions.is_empty() {
+        return Err(TiBasicError::Empty);
+    }
+    Ok(definitions)
+}
+
+/// Parse `Define [LibPub|LibPriv] name(params)=Prgm|Func`
+fn parse_header(line: &str) -> Result<TiBasicDefinition, String> {
+    let rest = strip_keyword(line, "Define").ok_or("expected a Define block")?;
+
+    let (access, rest) = if let Some(rest) = strip_keyword(rest, "LibPub") {
+        (LibAccess::LibPub, rest)
+    } else if let Some(rest) = strip_keyword(rest, "LibPriv") {
+        (LibAccess::LibPriv, rest)
+    } else {
+        (LibAccess::None, rest)
+    };
+
+    let (signature, keyword) = rest.split_once('=').ok_or("expected '=' after the name")?;
+    let kind = match keyword.trim().to_ascii_lowercase().as_str() {
+        "prgm" => DefinitionKind::Program,
+        "func" => DefinitionKind::Function,
+        "" => return Err("expected Prgm or Func after '='".to_string()),
+        other => return Err(format!("expected Prgm or Func, got '{}'", other)),
+    };
+
+    let (name, params) = signature
+        .trim()
+        .strip_suffix(')')
+        .and_then(|s| s.split_once('('))
+        .ok_or("expected name(parameters)")?;
+    let name = name.trim();
+    if !variables::is_valid_name(name) {
+        return Err(format!("invalid name '{}'", name));
+    }
+
+    let params: Vec<String> = params
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(str::to_string)
+        .collect();
+    if let Some(bad) = params.iter().find(|p| !variables::is_valid_name(p)) {
+        return Err(format!("invalid parameter '{}' in {}", bad, name));
+    }
+
+    Ok(TiBasicDefinition {
+        name: name.to_string(),
+        params,
+        kind,
+        access,
+        body: Vec::new(),
+    })
+}
+
+/// Strip a case-insensitive keyword followed by whitespace
+fn strip_keyword<'a>(text: &'a str, keyword: &str) -> Option<&'a str> {
+    let head = text.get(..keyword.len())?;
+    let rest = &text[keyword.len()..];
+    if head.eq_ignore_ascii_case(keyword) && rest.starts_with(char::is_whitespace) {
+        Some(rest.trim_start())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "© Geometry helpers
+Define LibPub area(r)=Func
+  Return π*r^2
+EndFunc
+
+Define hello()=
+Prgm
+  Disp \"Hello\"
+EndPrgm
+";
+
+    #[test]
+    fn test_parse_definitions() {
+        let definitions = parse_tibasic(SOURCE).unwrap();
+        assert_eq!(definitions.len(), 2);
+
+        assert_eq!(definitions[0].name, "area");
+        assert_eq!(definitions[0].params, vec!["r"]);
+        assert_eq!(definitions[0].kind, DefinitionKind::Function);
+        assert_eq!(definitions[0].access, LibAccess::LibPub);
+        assert_eq!(definitions[0].body, vec!["  Return π*r^2"]);
+
+        assert_eq!(definitions[1].name, "hello");
+        assert_eq!(definitions[1].kind, DefinitionKind::Program);
+        assert_eq!(definitions[1].access, LibAccess::None);
+    }
+
+    #[test]
+    fn test_source_roundtrip() {
+        let definitions = parse_tibasic(SOURCE).unwrap();
+        assert_eq!(
+            definitions[0].source(),
+            "Define LibPub area(r)=Func\n  Return π*r^2\nEndFunc"
+        );
+        assert_eq!(definitions[1].to_variable().value.type_name(), "prgm");
+    }
+
+    #[test]
+    fn test_libpriv() {
+        let definitions = parse_tibasic("define libpriv helper(a,b)=func\nReturn a+b\nendfunc").unwrap();
+        assert_eq!(definitions[0].access, LibAccess::LibPriv);
+        assert_eq!(definitions[0].params, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = parse_tibasic("Define f()=Prgm\nDisp 1\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: f has no EndPrgm");
+
+        let err = parse_tibasic("Disp 1\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 1: expected a Define block");
+
+        let err = parse_tibasic("Define f()=Prgm\nEndPrgm\nDefine F()=Func\nEndFunc\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 3: F is defined twice");
+
+        assert!(matches!(parse_tibasic("\n© nothing\n"), Err(TiBasicError::Empty)));
+    }
+}
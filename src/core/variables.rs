@@ -0,0 +1,391 @@
+// MIT License - New code for Luna-RS
+// See LICENSE.MIT for full license text
+
+//! Document variables stored in a problem's `<sym>` symbol table
+//!
+//! Values are kept in TI-Nspire expression syntax (`{1,2,3}`, `"text"`) so
+//! the calculator parses them the same way it parses typed input.
+//!
+//! Variables can also be loaded from a TOML or JSON file, where top-level
+//! keys are variable names:
+//!
+//! ```toml
+//! g = 9.81
+//! unit = "m/s^2"
+//! trials = [1.2, 1.4, 1.3]
+//! rotation = [[0, -1], [1, 0]]
+//! ```
+
+use std::fmt;
+
+use thiserror::Error;
+
+/// Errors that can occur while loading variables from a file
+#[derive(Debug, Error)]
+pub enum VariablesError {
+    #[error("TOML error: {0}")]
+    Toml(#[from] toml::de::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Variable {name}: {message}")]
+    Invalid { name: String, message: String },
+    #[error("Variables must be given as a table of name = value pairs")]
+    NotATable,
+}
+
+/// Format of a variables file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarsFormat {
+    Toml,
+    Json,
+}
+
+/// Maximum length of a TI-Nspire variable name
+pub const MAX_NAME_LEN: usize = 16;
+
+/// Names of built-in functions, commands and system variables, which can't
+/// be used for variables (compared without case)
+const RESERVED_NAMES: &[&str] = &[
+    "abs", "and", "angle", "ans", "approx", "augment", "avgrc", "ceiling", "char", "colaugment",
+    "coldim", "colnorm", "conj", "constructmat", "cos", "cosh", "cot", "coth", "crossp", "csc",
+    "csch", "cumulativesum", "cycle", "define", "delvar", "det", "diag", "dim", "disp", "div",
+    "dotp", "else", "elseif", "endfor", "endfunc", "endif", "endloop", "endprgm", "endtry",
+    "endwhile", "exit", "exp", "expr", "false", "floor", "for", "format", "fpart", "func", "gcd",
+    "getdenom", "getnum", "goto", "identity", "if", "imag", "int", "intdiv", "ipart", "lbl",
+    "lcm", "left", "list", "ln", "local", "log", "loop", "max", "mean", "median", "mid", "min",
+    "mod", "ncr", "norm", "not", "npr", "or", "ord", "pi", "prgm", "product", "rand", "randint",
+    "real", "ref", "remain", "request", "return", "right", "root", "rotate", "round", "rowdim",
+    "rref", "sec", "sech", "seq", "shift", "sign", "sin", "sinh", "solve", "sorta", "sortd",
+    "sqrt", "stdev", "stop", "string", "submat", "sum", "tan", "tanh", "then", "true", "try",
+    "variance", "when", "while", "xor",
+];
+
+/// Value of a document variable
+#[derive(Debug, Clone, PartialEq)]
+pub enum VarValue {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    /// Empty list element (`_` on the calculator)
+    Void,
+    List(Vec<VarValue>),
+    /// Rows of equal length
+    Matrix(Vec<Vec<VarValue>>),
+    /// TI-Basic program; holds the full `Define ... EndPrgm` source
+    Program(String),
+    /// TI-Basic function; holds the full `Define ... EndFunc` source
+    Function(String),
+}
+
+impl VarValue {
+    /// Type name written to the symbol table
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            VarValue::Number(_) => "num",
+            VarValue::Text(_) => "str",
+            VarValue::Bool(_) => "bool",
+            VarValue::Void => "void",
+            VarValue::List(_) => "list",
+            VarValue::Matrix(_) => "mat",
+            VarValue::Program(_) => "prgm",
+            VarValue::Function(_) => "func",
+        }
+    }
+}
+
+impl fmt::Display for VarValue {
+    /// Format the value in TI-Nspire expression syntax
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VarValue::Number(n) => write!(f, "{}", n),
+            VarValue::Text(s) => write!(f, "\"{}\"", s.replace('"', "\"\"")),
+            VarValue::Bool(b) => write!(f, "{}", b),
+            VarValue::Void => write!(f, "_"),
+            VarValue::List(items) => {
+                write!(f, "{{")?;
+                write_items(f, items)?;
+                write!(f, "}}")
+            }
+            VarValue::Matrix(rows) => {
+                write!(f, "[")?;
+                for row in rows {
+                    write!(f, "[")?;
+                    write_items(f, row)?;
+                    write!(f, "]")?;
+                }
+                write!(f, "]")
+            }
+            VarValue::Program(source) | VarValue::Function(source) => write!(f, "{}", source),
+        }
+    }
+}
+
+fn write_items(f: &mut fmt::Formatter<'_>, items: &[VarValue]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ",")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+/// A named variable in the problem's symbol table
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocVariable {
+    pub name: String,
+    pub value: VarValue,
+}
+
+impl DocVariable {
+    pub fn new(name: &str, value: VarValue) -> Self {
+        Self { name: name.to_string(), value }
+    }
+}
+
+/// Check a name against the calculator's variable naming rules
+///
+/// Names start with a letter, continue with letters, digits or `_`, are at
+/// most `MAX_NAME_LEN` characters long and aren't a built-in name such as
+/// `sin`, `ans` or `list`.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => {}
+        _ => return false,
+    }
+    name.len() <= MAX_NAME_LEN
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !is_reserved_name(name)
+}
+
+/// Whether `name` is a built-in function, command or system variable
+pub fn is_reserved_name(name: &str) -> bool {
+    RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(name))
+}
+
+/// Turn arbitrary text into a valid variable name
+///
+/// Runs of other characters become `_`, names that don't start with a letter
+/// or are reserved get a `v` prefix, and the result is truncated to
+/// `MAX_NAME_LEN`. Names are lowercased since the calculator doesn't
+/// distinguish case.
+pub fn sanitize_name(text: &str) -> String {
+    let mut name = String::new();
+    for c in text.trim().chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name = name.trim_matches('_');
+
+    let mut name = if name.starts_with(|c: char| c.is_ascii_alphabetic()) && !is_reserved_name(name) {
+        name.to_string()
+    } else {
+        format!("v{}", name)
+    };
+    name.truncate(MAX_NAME_LEN);
+    name
+}
+
+/// Load variables from a TOML or JSON document
+///
+/// Numbers, strings and booleans map to the matching calculator types.
+/// Arrays of scalars become lists (`null` becomes a void element), and
+/// arrays of equally long arrays become matrices.
+pub fn load_variables(text: &str, format: VarsFormat) -> Result<Vec<DocVariable>, VariablesError> {
+    let entries: Vec<(String, Scalar)> = match format {
+        VarsFormat::Toml => {
+            let table: toml::Table = toml::from_str(text)?;
+            table.into_iter().map(|(k, v)| (k, Scalar::from_toml(v))).collect()
+        }
+        VarsFormat::Json => match serde_json::from_str(text)? {
+            serde_json::Value::Object(map) => {
+                map.into_iter().map(|(k, v)| (k, Scalar::from_json(v))).collect()
+            }
+            _ => return Err(VariablesError::NotATable),
+        },
+    };
+
+    entries
+        .into_iter()
+        .map(|(name, value)| {
+            let invalid = |message: String| VariablesError::Invalid { name: name.clone(), message };
+            if !is_valid_name(&name) {
+                return Err(invalid("not a valid calculator variable name".to_string()));
+            }
+            let value = value.into_value().map_err(invalid)?;
+            Ok(DocVariable::new(&name, value))
+        })
+        .collect()
+}
+
+/// Common shape of TOML and JSON values before conversion
+enum Scalar {
+    Number(f64),
+    Text(String),
+    Bool(bool),
+    Null,
+    Array(Vec<Scalar>),
+    Unsupported(&'static str),
+}
+
+impl Scalar {
+    fn from_toml(value: toml::Value) -> Self {
+        match value {
+            toml::Value::Integer(i) => Scalar::Number(i as f64),
+            toml::Value::Float(f) => Scalar::Number(f),
+            toml::Value::String(s) => Scalar::Text(s),
+            toml::Value::Boolean(b) => Scalar::Bool(b),
+            toml::Value::Array(items) => Scalar::Array(items.into_iter().map(Scalar::from_toml).collect()),
+            toml::Value::Datetime(_) => Scalar::Unsupported("dates"),
+            toml::Value::Table(_) => Scalar::Unsupported("tables"),
+        }
+    }
+
+    fn from_json(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Number(n) => n.as_f64().map_or(Scalar::Unsupported("this number"), Scalar::Number),
+            serde_json::Value::String(s) => Scalar::Text(s),
+            serde_json::Value::Bool(b) => Scalar::Bool(b),
+            serde_json::Value::Null => Scalar::Null,
+            serde_json::Value::Array(items) => Scalar::Array(items.into_iter().map(Scalar::from_json).collect()),
+            serde_json::Value::Object(_) => Scalar::Unsupported("objects"),
+        }
+    }
+
+    fn into_value(self) -> Result<VarValue, String> {
+        match self {
+            Scalar::Array(items) if items.iter().any(|i| matches!(i, Scalar::Array(_))) => {
+                let rows = items
+                    .into_iter()
+                    .map(|row| match row {
+                        Scalar::Array(cells) => cells.into_iter().map(Scalar::into_element).collect(),
+                        _ => Err("matrix rows must all be arrays".to_string()),
+                    })
+                    .collect::<Result<Vec<Vec<_>>, _>>()?;
+                if rows.iter().any(|r| r.len() != rows[0].len() || r.is_empty()) {
+                    return Err("matrix rows must be non-empty and equally long".to_string());
+                }
+                Ok(VarValue::Matrix(rows))
+            }
+            Scalar::Array(items) => Ok(VarValue::List(
+                items.into_iter().map(Scalar::into_element).collect::<Result<_, _>>()?,
+            )),
+            Scalar::Null => Err("null is only allowed inside lists".to_string()),
+            scalar => scalar.into_element(),
+        }
+    }
+
+    /// Convert a list or matrix element
+    fn into_element(self) -> Result<VarValue, String> {
+        match self {
+            Scalar::Number(n) if n.is_finite() => Ok(VarValue::Number(n)),
+            Scalar::Number(n) => Err(format!("{} can't be stored", n)),
+            Scalar::Text(s) => Ok(VarValue::Text(s)),
+            Scalar::Bool(b) => Ok(VarValue::Bool(b)),
+            Scalar::Null => Ok(VarValue::Void),
+            Scalar::Array(_) => Err("arrays nested more than two levels deep".to_string()),
+            Scalar::Unsupported(what) => Err(format!("{} are not supported", what)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_syntax() {
+        assert_eq!(VarValue::Number(3.0).to_string(), "3");
+        assert_eq!(VarValue::Number(-1.5).to_string(), "-1.5");
+        assert_eq!(VarValue::Text("say \"hi\"".to_string()).to_string(), "\"say \"\"hi\"\"\"");
+        assert_eq!(
+            VarValue::List(vec![VarValue::Number(1.0), VarValue::Void, VarValue::Text("a".to_string())])
+                .to_string(),
+            "{1,_,\"a\"}"
+        );
+    }
+
+    #[test]
+    fn test_is_valid_name() {
+        assert!(is_valid_name("time"));
+        assert!(is_valid_name("x_2"));
+        assert!(!is_valid_name("2x"));
+        assert!(!is_valid_name("my var"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("abcdefghijklmnopq"));
+        assert!(!is_valid_name("sin"));
+        assert!(!is_valid_name("Ans"));
+        assert!(!is_valid_name("list"));
+        assert!(is_valid_name("sine"));
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("Time (s)"), "time_s");
+        assert_eq!(sanitize_name("2nd trial"), "v2nd_trial");
+        assert_eq!(sanitize_name(""), "v");
+        assert_eq!(sanitize_name("a very long column header"), "a_very_long_colu");
+        assert_eq!(sanitize_name("Pi"), "vpi");
+        assert_eq!(sanitize_name("list"), "vlist");
+    }
+
+    #[test]
+    fn test_matrix_syntax() {
+        let matrix = VarValue::Matrix(vec![
+            vec![VarValue::Number(1.0), VarValue::Number(2.0)],
+            vec![VarValue::Number(3.0), VarValue::Number(4.0)],
+        ]);
+        assert_eq!(matrix.to_string(), "[[1,2][3,4]]");
+        assert_eq!(VarValue::Bool(true).to_string(), "true");
+    }
+
+    #[test]
+    fn test_load_toml() {
+        let text = "g = 9.81\nunit = \"m/s^2\"\nok = true\ntrials = [1, 2.5]\nrot = [[0, -1], [1, 0]]\n";
+        let variables = load_variables(text, VarsFormat::Toml).unwrap();
+        let find = |name: &str| variables.iter().find(|v| v.name == name).unwrap().value.to_string();
+
+        assert_eq!(variables.len(), 5);
+        assert_eq!(find("g"), "9.81");
+        assert_eq!(find("unit"), "\"m/s^2\"");
+        assert_eq!(find("ok"), "true");
+        assert_eq!(find("trials"), "{1,2.5}");
+        assert_eq!(find("rot"), "[[0,-1][1,0]]");
+    }
+
+    #[test]
+    fn test_load_json() {
+        let text = r#"{"data": [1, null, "x"], "m": [[1], [2]]}"#;
+        let variables = load_variables(text, VarsFormat::Json).unwrap();
+        assert_eq!(variables[0], DocVariable::new("data", VarValue::List(vec![
+            VarValue::Number(1.0),
+            VarValue::Void,
+            VarValue::Text("x".to_string()),
+        ])));
+        assert_eq!(variables[1].value.type_name(), "mat");
+    }
+
+    #[test]
+    fn test_quoted_numbers_stay_text() {
+        let variables = load_variables("code = \"007\"\nn = \"1e3\"\n", VarsFormat::Toml).unwrap();
+        assert!(variables.iter().all(|v| matches!(v.value, VarValue::Text(_))));
+
+        let variables = load_variables(r#"{"code": "007", "items": ["1", 2]}"#, VarsFormat::Json).unwrap();
+        assert_eq!(variables[0].value, VarValue::Text("007".to_string()));
+        assert_eq!(variables[1].value.to_string(), "{\"1\",2}");
+    }
+
+    #[test]
+    fn test_load_errors() {
+        assert!(matches!(load_variables("[1, 2]", VarsFormat::Json), Err(VariablesError::NotATable)));
+        assert!(matches!(load_variables("\"2x\" = 1", VarsFormat::Toml), Err(VariablesError::Invalid { .. })));
+        assert!(matches!(load_variables("ans = 1", VarsFormat::Toml), Err(VariablesError::Invalid { .. })));
+        assert!(load_variables("m = [[1, 2], [3]]", VarsFormat::Toml).is_err());
+        assert!(load_variables("t = { a = 1 }", VarsFormat::Toml).is_err());
+        assert!(load_variables(r#"{"n": null}"#, VarsFormat::Json).is_err());
+    }
+}
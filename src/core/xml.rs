@@ -22,16 +22,23 @@
 
 use thiserror::Error;
 
-use super::math_render::latex_to_unicode;
+#[cfg(feature = "unverified-pages")]
+use super::graph_spec::GraphSpec;
+#[cfg(feature = "unverified-pages")]
+use super::image;
+use super::math_draw;
+use super::math_render::{render_latex, RenderOptions, RenderedLine};
+#[cfg(feature = "unverified-pages")]
+use super::variables::DocVariable;
 
 /// Errors that can occur during XML processing
 #[derive(Debug, Error)]
 pub enum XMLError {
     #[error("Invalid script content: {0}")]
     InvalidContent(String),
-    #[allow(dead_code)]
     #[error("XML generation failed: {0}")]
     GenerationFailed(String),
+    #[allow(dead_code)]
     #[error("UTF-8 encoding error: {0}")]
     EncodingError(String),
 }
@@ -56,7 +63,15 @@ pub struct ScriptData {
 ///
 /// The TI-Nspire calculator expects Lua scripts to be wrapped in a specific
 /// XML structure with CDATA sections.
-pub fn wrap_lua_script(script: &str, _document_name: &str) -> Result<Vec<u8>, XMLError> {
+pub fn wrap_lua_script(script: &str, document_name: &str) -> Result<Vec<u8>, XMLError> {
+    wrap_encoded_lua_script(script.as_bytes(), document_name)
+}
+
+/// Wrap an already encoded Lua script in the required XML format
+///
+/// `script` is the script in the byte encoding it should be stored in:
+/// plain UTF-8, or TI's encoding from `escape_unicode`.
+pub fn wrap_encoded_lua_script(script: &[u8], _document_name: &str) -> Result<Vec<u8>, XMLError> {
     // From luna.c lines 289-307: The Lua header with compressed XML structure
     const LUA_HEADER: &[u8] = b"\x54\x49\x58\x43\x30\x31\x30\x30\x2D\x31\x2E\x30\x3F\x3E\x3C\x70\x72\
 \x6F\x62\x20\x78\x6D\x6C\x6E\x73\x3D\x22\x75\x72\x6E\x3A\x54\x49\x2E\
@@ -79,7 +94,7 @@ pub fn wrap_lua_script(script: &str, _document_name: &str) -> Result<Vec<u8>, XM
     const LUA_FOOTER: &[u8] = b"]]>\x0E\x08\x0E\x05\x0E\x02\x0E\x00";
 
     // Fix CDATA end sequences in the script (from luna.c lines 124-148)
-    let fixed_script = fix_cdata_end_seq(script)?;
+    let fixed_script = fix_cdata_end_seq(script);
 
     // Calculate total size and allocate buffer
     let total_size = LUA_HEADER.len() + fixed_script.len() + LUA_FOOTER.len();
@@ -87,7 +102,7 @@ pub fn wrap_lua_script(script: &str, _document_name: &str) -> Result<Vec<u8>, XM
 
     // Combine header + script + footer
     result.extend_from_slice(LUA_HEADER);
-    result.extend_from_slice(fixed_script.as_bytes());
+    result.extend_from_slice(&fixed_script);
     result.extend_from_slice(LUA_FOOTER);
 
     Ok(result)
@@ -126,6 +141,343 @@ TI.PythonEditor\" type=\"TI.PythonEditor\" ver=\"1.0\"><py:data><py:name>";
     Ok(result)
 }
 
+/// Prefix of every TI problem XML stream (before the root element)
+const TI_XML_PROLOG: &[u8] = b"TIXC0100-1.0?>";
+
+/// Marker byte that replaces closing tags in TI's compact XML
+const TI_CLOSE_TAG: u8 = 0x0E;
+
+/// Writer for TI's compact XML dialect
+///
+/// Problem XML does not spell out closing tags. Every distinct element name
+/// gets an index in order of first appearance, and `</name>` is written as
+/// `0x0E <index>`. This is the encoding used by the hard-coded Lua and
+/// Python headers above, so documents built with this writer can mix freely
+/// with them.
+pub struct TiXmlWriter {
+    buffer: Vec<u8>,
+    tags: Vec<String>,
+    open: Vec<u8>,
+}
+
+impl TiXmlWriter {
+    /// Start a new XML stream with the TI prolog
+    pub fn new() -> Self {
+        Self {
+            buffer: TI_XML_PROLOG.to_vec(),
+            tags: Vec::new(),
+            open: Vec::new(),
+        }
+    }
+
+    /// Open an element with the given attributes
+    pub fn open(&mut self, name: &str, attributes: &[(&str, &str)]) -> Result<(), XMLError> {
+        let index = match self.tags.iter().position(|t| t == name) {
+            Some(index) => index,
+            None => {
+                self.tags.push(name.to_string());
+                self.tags.len() - 1
+            }
+        };
+        let index = u8::try_from(index).map_err(|_| {
+            XMLError::GenerationFailed("Too many distinct XML elements".to_string())
+        })?;
+
+        self.buffer.push(b'<');
+        self.buffer.extend_from_slice(name.as_bytes());
+        for (key, value) in attributes {
+            self.buffer.push(b' ');
+            self.buffer.extend_from_slice(key.as_bytes());
+            self.buffer.extend_from_slice(b"=\"");
+            self.buffer.extend_from_slice(escape_xml(value).as_bytes());
+            self.buffer.push(b'"');
+        }
+        self.buffer.push(b'>');
+        self.open.push(index);
+        Ok(())
+    }
+
+    /// Close the innermost open element
+    pub fn close(&mut self) -> Result<(), XMLError> {
+        let index = self.open.pop().ok_or_else(|| {
+            XMLError::GenerationFailed("Closing tag without matching open tag".to_string())
+        })?;
+        self.buffer.push(TI_CLOSE_TAG);
+        self.buffer.push(index);
+        Ok(())
+    }
+
+    /// Write escaped character data
+    pub fn text(&mut self, text: &str) {
+        self.buffer.extend_from_slice(escape_xml(text).as_bytes());
+    }
+
+    /// Write a CDATA section, splitting any `]]>` inside the data
+    #[cfg_attr(not(feature = "unverified-pages"), allow(dead_code))]
+    pub fn cdata(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(b"<![CDATA[");
+        self.buffer.extend_from_slice(&fix_cdata_end_seq(data));
+        self.buffer.extend_from_slice(b"]]>");
+    }
+
+    /// Write `<name>text</name>`
+    pub fn element(&mut self, name: &str, text: &str) -> Result<(), XMLError> {
+        self.open(name, &[])?;
+        self.text(text);
+        self.close()
+    }
+
+    /// Finish the stream, checking that every element was closed
+    pub fn finish(self) -> Result<Vec<u8>, XMLError> {
+        if !self.open.is_empty() {
+            return Err(XMLError::GenerationFailed(format!(
+                "{} XML element(s) left open",
+                self.open.len()
+            )));
+        }
+        Ok(self.buffer)
+    }
+}
+
+impl Default for TiXmlWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Escape the five XML special characters
+pub fn escape_xml(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&apos;"),
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// Named image resource of a Lua script app, in TI.Image format
+#[cfg(feature = "unverified-pages")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptResource {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// An application page (card) inside a problem
+pub enum Widget {
+    /// Lua script app, as written by `wrap_encoded_lua_script`, with image
+    /// resources; `script` is already encoded (see `escape_unicode`)
+    #[cfg(feature = "unverified-pages")]
+    LuaScript { script: Vec<u8>, resources: Vec<ScriptResource> },
+    /// Python editor page for a `.py` file stored in the archive
+    PythonEditor { filename: String },
+    /// Python Shell page that runs a stored `.py` file when it is shown
+    #[cfg(feature = "unverified-pages")]
+    PythonShell { filename: String },
+    /// TI Notes page; `tree` is the rich-text tree from `notes::to_r2d_tree`
+    Notes { tree: String },
+    /// Lists & Spreadsheet page with one column per list variable
+    #[cfg(feature = "unverified-pages")]
+    ListsSpreadsheet { columns: Vec<String> },
+    /// Graphs page with functions, window settings and sliders
+    #[cfg(feature = "unverified-pages")]
+    Graphs(GraphSpec),
+    /// Program Editor page showing a stored TI-Basic program or function
+    #[cfg(feature = "unverified-pages")]
+    ProgramEditor { name: String },
+}
+
+impl Widget {
+    fn write(&self, writer: &mut TiXmlWriter) -> Result<(), XMLError> {
+        match self {
+            #[cfg(feature = "unverified-pages")]
+            Widget::LuaScript { script, resources } => {
+                writer.open(
+                    "wdgt",
+                    &[("xmlns:sc", "urn:TI.ScriptApp"), ("type", "TI.ScriptApp"), ("ver", "1.0")],
+                )?;
+                writer.element("sc:mFlags", "0")?;
+                writer.element("sc:value", "-1")?;
+                writer.open("sc:script", &[("version", "512"), ("id", "0")])?;
+                writer.cdata(script);
+                writer.close()?;
+                // Lua reads each resource as `_R.IMG.name`
+                for resource in resources {
+                    writer.open("sc:rsrc", &[("name", &resource.name), ("type", "IMG")])?;
+                    writer.cdata(image::lua_escape(&resource.data).as_bytes());
+                    writer.close()?;
+                }
+                writer.close()
+            }
+            Widget::PythonEditor { filename } => {
+                if filename.len() > 240 {
+                    return Err(XMLError::InvalidContent(
+                        "Python script filenames limited to 240 characters".to_string(),
+                    ));
+                }
+                writer.open(
+                    "wdgt",
+                    &[("xmlns:py", "urn:TI.PythonEditor"), ("type", "TI.PythonEditor"), ("ver", "1.0")],
+                )?;
+                writer.open("py:data", &[])?;
+                writer.element("py:name", filename)?;
+                writer.element("py:dirf", "-10000000")?;
+                writer.close()?;
+                writer.element("py:mFlags", "1024")?;
+                writer.element("py:value", "10")?;
+                writer.close()
+            }
+            #[cfg(feature = "unverified-pages")]
+            Widget::PythonShell { filename } => {
+                writer.open(
+                    "wdgt",
+                    &[("xmlns:psh", "urn:TI.PythonShell"), ("type", "TI.PythonShell"), ("ver", "1.0")],
+                )?;
+                writer.open("psh:data", &[])?;
+                // The shell runs the bound script as soon as the page opens
+                writer.element("psh:name", filename)?;
+                writer.element("psh:run", "1")?;
+                writer.close()?;
+                writer.element("psh:mFlags", "0")?;
+                writer.element("psh:value", "10")?;
+                writer.close()
+            }
+            Widget::Notes { tree } => {
+                writer.open(
+                    "wdgt",
+                    &[("xmlns:np", "urn:TI.Notepad"), ("type", "TI.Notepad"), ("ver", "2.0")],
+                )?;
+                writer.element("np:mFlags", "1024")?;
+                writer.element("np:value", "3")?;
+                writer.element("np:txt", tree)?;
+                writer.close()
+            }
+            #[cfg(feature = "unverified-pages")]
+            Widget::ListsSpreadsheet { columns } => {
+                writer.open(
+                    "wdgt",
+                    &[
+                        ("xmlns:ls", "urn:TI.ListsAndSpreadsheet"),
+                        ("type", "TI.ListsAndSpreadsheet"),
+                        ("ver", "1.0"),
+                    ],
+                )?;
+                writer.element("ls:mFlags", "0")?;
+                writer.open("ls:cols", &[])?;
+                for (index, name) in columns.iter().enumerate() {
+                    // Each column is bound to the list variable of the same name
+                    writer.open("ls:col", &[("idx", &index.to_string()), ("var", name)])?;
+                    writer.close()?;
+                }
+                writer.close()?;
+                writer.close()
+            }
+            #[cfg(feature = "unverified-pages")]
+            Widget::Graphs(spec) => {
+                writer.open(
+                    "wdgt",
+                    &[("xmlns:gr", "urn:TI.Graph"), ("type", "TI.Graph"), ("ver", "1.0")],
+                )?;
+                writer.element("gr:mFlags", "0")?;
+
+                let w = &spec.window;
+                let (xmin, xmax, ymin, ymax) =
+                    (w.xmin.to_string(), w.xmax.to_string(), w.ymin.to_string(), w.ymax.to_string());
+                writer.open("gr:wnd", &[("xmin", &xmin), ("xmax", &xmax), ("ymin", &ymin), ("ymax", &ymax)])?;
+                writer.close()?;
+
+                for function in &spec.functions {
+                    writer.open("gr:fn", &[("name", &function.name), ("var", &function.variable)])?;
+                    writer.text(&function.expr);
+                    writer.close()?;
+                }
+
+                // Sliders drive the document variable of the same name
+                for slider in &spec.sliders {
+                    let (min, max, step) =
+                        (slider.min.to_string(), slider.max.to_string(), slider.step.to_string());
+                    writer.open(
+                        "gr:sldr",
+                        &[("var", &slider.name), ("min", &min), ("max", &max), ("step", &step)],
+                    )?;
+                    writer.close()?;
+                }
+
+                writer.close()
+            }
+            #[cfg(feature = "unverified-pages")]
+            Widget::ProgramEditor { name } => {
+                writer.open(
+                    "wdgt",
+                    &[("xmlns:pe", "urn:TI.ProgramEditor"), ("type", "TI.ProgramEditor"), ("ver", "1.0")],
+                )?;
+                writer.element("pe:mFlags", "0")?;
+                // The editor opens the document variable with this name
+                writer.element("pe:prog", name)?;
+                writer.close()
+            }
+        }
+    }
+}
+
+/// Build a problem XML with one page per widget
+///
+/// Produces the same `<prob>`/`<sym>`/`<card>` skeleton as the hard-coded
+/// Python header, repeated once per page, with an empty symbol table.
+pub fn build_problem(widgets: &[Widget]) -> Result<Vec<u8>, XMLError> {
+    write_problem(widgets, |_| Ok(()))
+}
+
+/// Build a problem XML like `build_problem`, with `variables` in the
+/// problem's symbol table, which is shared by all pages
+#[cfg(feature = "unverified-pages")]
+pub fn build_problem_with_variables(variables: &[DocVariable], widgets: &[Widget]) -> Result<Vec<u8>, XMLError> {
+    write_problem(widgets, |writer| {
+        for variable in variables {
+            writer.open("var", &[("name", &variable.name), ("type", variable.value.type_name())])?;
+            writer.element("expr", &variable.value.to_string())?;
+            writer.close()?;
+        }
+        Ok(())
+    })
+}
+
+/// Write the problem skeleton, with `symbols` writing the contents of `<sym>`
+fn write_problem(
+    widgets: &[Widget],
+    symbols: impl FnOnce(&mut TiXmlWriter) -> Result<(), XMLError>,
+) -> Result<Vec<u8>, XMLError> {
+    if widgets.is_empty() {
+        return Err(XMLError::InvalidContent("A problem needs at least one page".to_string()));
+    }
+
+    let mut writer = TiXmlWriter::new();
+    writer.open("prob", &[("xmlns", "urn:TI.Problem"), ("ver", "1.0"), ("pbname", "")])?;
+    writer.open("sym", &[])?;
+    symbols(&mut writer)?;
+    writer.close()?;
+
+    for widget in widgets {
+        writer.open(
+            "card",
+            &[("clay", "0"), ("h1", "10000"), ("h2", "10000"), ("w1", "10000"), ("w2", "10000")],
+        )?;
+        writer.element("isDummyCard", "0")?;
+        writer.element("flag", "0")?;
+        widget.write(&mut writer)?;
+        writer.close()?;
+    }
+
+    writer.close()?;
+    writer.finish()
+}
+
 /// Convert plain text to a Lua script that displays the text on TI-Nspire
 ///
 /// Since TI-Nspire doesn't have a native "plain text note" format,
@@ -138,15 +490,44 @@ TI.PythonEditor\" type=\"TI.PythonEditor\" ver=\"1.0\"><py:data><py:name>";
 /// - Symbols: \infty, \sum, \int, \partial, etc.
 /// - Superscripts: x^2 → x², x^{10} → x¹⁰
 /// - Subscripts: x_1 → x₁, x_{10} → x₁₀
-pub fn text_to_lua_script(text: &str) -> String {
-    // Convert LaTeX notation to Unicode
-    let text = latex_to_unicode(text);
+pub fn text_to_lua_script(text: &str, options: &RenderOptions) -> String {
+    // Convert LaTeX notation to Unicode. Rows of aligned blocks (matrices,
+    // cases, ...) start with \x01 and have tab-separated cells, so the viewer
+    // can lay them out in columns instead of word-wrapping them.
+    // Formulas drawn natively are stored in a Lua table and their line is
+    // \x02 plus the index in that table.
+    let mut formulas = Vec::new();
+    let lines: Vec<String> = render_latex(text, options)
+        .into_iter()
+        .flat_map(|line| match line {
+            RenderedLine::Formula(layout) => {
+                formulas.push(math_draw::layout_to_lua(&layout));
+                vec![format!("\x02{}", formulas.len())]
+            }
+            RenderedLine::Text(text) => vec![text],
+            RenderedLine::Block(block) => block
+                .rows
+                .iter()
+                .map(|row| {
+                    let cells: Vec<String> = row.iter().map(|cell| cell.replace(['\t', '\x01'], " ")).collect();
+                    format!("\x01{}", cells.join("\t"))
+                })
+                .collect(),
+        })
+        .collect();
+    let text = lines.join("\n");
 
     // Escape special characters for Lua long string literal
     // We use [=[ ... ]=] syntax to handle most cases
     // If the text contains ]=], we need to use more equals signs
     let delimiter = find_safe_delimiter(&text);
 
+    let math = if formulas.is_empty() {
+        String::new()
+    } else {
+        format!("\nlocal formulas = {{\n    {},\n}}\n{}", formulas.join(",\n    "), math_draw::LUA_MATH_RUNTIME)
+    };
+
     format!(
         r#"-- Text Note (generated by Luna-RS)
 local text = [{delim}[{text}]{delim}]
@@ -158,14 +539,35 @@ local MARGIN_TOP = 20
 local scroll = 0
 local max_scroll = 0
 local wrapped_lines = {{}}
-
+{math}
 -- Wrap text to fit screen width
+-- Lines starting with \1 are rows of an aligned block: their tab-separated
+-- cells are placed in columns as wide as the widest cell, and never wrapped.
+-- Lines starting with \2 stand for a formula from the formulas table.
 function wrap_text(gc, txt, max_width)
     wrapped_lines = {{}}
+    local block = nil
     for line in (txt .. "\n"):gmatch("([^\r\n]*)\r?\n") do
-        if line == "" then
+        if line:sub(1, 1) == "\1" then
+            local cells = {{}}
+            for cell in (line:sub(2) .. "\t"):gmatch("([^\t]*)\t") do
+                table.insert(cells, cell)
+            end
+            block = block or {{widths = {{}}}}
+            for i, cell in ipairs(cells) do
+                block.widths[i] = math.max(block.widths[i] or 0, gc:getStringWidth(cell))
+            end
+            table.insert(wrapped_lines, {{cells = cells, block = block}})
+        elseif line:sub(1, 1) == "\2" then
+            block = nil
+            local formula = formulas[tonumber(line:sub(2))]
+            math_measure(gc, formula, 1)
+            table.insert(wrapped_lines, {{formula = formula}})
+        elseif line == "" then
+            block = nil
             table.insert(wrapped_lines, "")
         else
+            block = nil
             local current = ""
             for word in line:gmatch("%S+") do
                 local test = current == "" and word or (current .. " " .. word)
@@ -208,14 +610,29 @@ function on.paint(gc)
     end
 
     local y = MARGIN_TOP - scroll
+    local total = 0
     for _, line in ipairs(wrapped_lines) do
-        if y + LINE_HEIGHT > 0 and y < h then
-            gc:drawString(line, MARGIN_X, y)
+        local formula = type(line) == "table" and line.formula
+        local height = formula and formula.asc + formula.desc + 6 or LINE_HEIGHT
+        if y + height > 0 and y < h then
+            if formula then
+                math_draw(gc, formula, MARGIN_X, y + 3 + formula.asc, 1)
+                gc:setFont("sansserif", "r", FONT_SIZE)
+            elseif type(line) == "table" then
+                local x = MARGIN_X
+                for i, cell in ipairs(line.cells) do
+                    gc:drawString(cell, x, y)
+                    x = x + line.block.widths[i]
+                end
+            else
+                gc:drawString(line, MARGIN_X, y)
+            end
         end
-        y = y + LINE_HEIGHT
+        y = y + height
+        total = total + height
     end
 
-    max_scroll = math.max(0, #wrapped_lines * LINE_HEIGHT - h + MARGIN_TOP + 10)
+    max_scroll = math.max(0, total - h + MARGIN_TOP + 10)
 end
 
 function on.arrowKey(key)
@@ -240,7 +657,8 @@ end
 platform.window:invalidate()
 "#,
         delim = delimiter,
-        text = text
+        text = text,
+        math = math
     )
 }
 
@@ -273,7 +691,7 @@ fn find_safe_delimiter(text: &str) -> String {
 #[allow(dead_code)]
 pub fn wrap_plain_text(text: &str, document_name: &str) -> Result<Vec<u8>, XMLError> {
     // Convert text to Lua script
-    let lua_script = text_to_lua_script(text);
+    let lua_script = text_to_lua_script(text, &RenderOptions::default());
 
     // Use the existing Lua wrapper
     wrap_lua_script(&lua_script, document_name)
@@ -318,13 +736,13 @@ pub fn get_ti_encrypted_header() -> &'static [u8] {
 /// Fix CDATA end sequences in Lua scripts
 ///
 /// Replaces occurrences of `]]>` with `]]><![CDATA[` to split CDATA sections
-/// and prevent premature ending of the CDATA block.
+/// and prevent premature ending of the CDATA block. Works on bytes, since
+/// scripts may already be in TI's encoding.
 /// Based on luna.c lines 124-148.
-fn fix_cdata_end_seq(script: &str) -> Result<String, XMLError> {
+fn fix_cdata_end_seq(script_bytes: &[u8]) -> Vec<u8> {
     const CDATA_RESTART: &str = "]]><![CDATA[";
     
-    let script_bytes = script.as_bytes();
-    let mut result = Vec::with_capacity(script.len());
+    let mut result = Vec::with_capacity(script_bytes.len());
     let mut i = 0;
     
     while i < script_bytes.len() {
@@ -347,14 +765,13 @@ fn fix_cdata_end_seq(script: &str) -> Result<String, XMLError> {
         }
     }
     
-    String::from_utf8(result).map_err(|e| XMLError::EncodingError(e.to_string()))
+    result
 }
 
 /// Convert UTF-8 string to TI-specific encoding
 ///
 /// Based on luna.c `escape_unicode()` function (lines 83-122).
 /// Converts UTF-8 characters to the encoding format expected by TI calculators.
-#[allow(dead_code)]
 pub fn escape_unicode(input: &str) -> Result<Vec<u8>, XMLError> {
     let input_bytes = input.as_bytes();
     let mut result = Vec::with_capacity(input.len() * 2); // Estimate
@@ -401,7 +818,6 @@ pub fn escape_unicode(input: &str) -> Result<Vec<u8>, XMLError> {
 ///
 /// Based on luna.c `utf82unicode()` function (lines 45-80).
 /// Returns (unicode_value, next_index).
-#[allow(dead_code)]
 fn utf8_to_unicode(bytes: &[u8], index: usize) -> Result<(u32, usize), XMLError> {
     if index >= bytes.len() {
         return Ok((0, index));
@@ -457,6 +873,7 @@ fn utf8_to_unicode(bytes: &[u8], index: usize) -> Result<(u32, usize), XMLError>
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::core::math_render::MathLayout;
 
     #[test]
     fn test_wrap_lua_script() {
@@ -499,9 +916,9 @@ mod tests {
 
     #[test]
     fn test_fix_cdata_end_seq() {
-        let input = "test]]>more";
-        let result = fix_cdata_end_seq(input).unwrap();
-        assert_eq!(result, "test]]]]><![CDATA[>more");
+        let input = b"test]]>more";
+        let result = fix_cdata_end_seq(input);
+        assert_eq!(result, b"test]]]]><![CDATA[>more");
     }
 
     #[test]
@@ -527,10 +944,61 @@ mod tests {
         assert_eq!(result, b"Hello");
     }
 
+    // Golden bytes are encoded by hand from the table in luna.c's
+    // escape_unicode (lines 98-112), not taken from this function's output:
+    // code points below 0x800 take two bytes (high, low), the rest of the
+    // BMP 0x80 plus two bytes, and anything above 0x08 plus three bytes.
+
+    #[test]
+    fn test_escape_unicode_german() {
+        let result = escape_unicode("Größe: 5 m² – fertig").unwrap();
+        let mut expected = b"Gr\x00\xF6\x00\xDFe: 5 m\x00\xB2 ".to_vec();
+        expected.extend_from_slice(b"\x80\x20\x13 fertig");
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_escape_unicode_russian() {
+        let result = escape_unicode("Привет").unwrap();
+        assert_eq!(result, b"\x04\x1F\x04\x40\x04\x38\x04\x32\x04\x35\x04\x42");
+    }
+
+    #[test]
+    fn test_escape_unicode_table() {
+        let cases: [(&str, &[u8]); 5] = [
+            ("\u{FEFF}é", b"\x00\xE9"),
+            ("\u{07FF}", b"\x07\xFF"),
+            ("\u{0800}", b"\x80\x08\x00"),
+            ("中 π", b"\x80\x4E\x2D \x03\xC0"),
+            ("😀", b"\x08\x01\xF6\x00"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(escape_unicode(input).unwrap(), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_wrap_encoded_lua_script_golden() {
+        let script = escape_unicode("print(\"Äpfel\")").unwrap();
+        let result = wrap_encoded_lua_script(&script, "").unwrap();
+
+        let header_len = result.windows(9).position(|w| w == b"<![CDATA[").unwrap() + 9;
+        assert_eq!(&result[..header_len], &wrap_lua_script("", "").unwrap()[..header_len]);
+        assert_eq!(&result[header_len..], b"print(\"\x00\xC4pfel\")]]>\x0E\x08\x0E\x05\x0E\x02\x0E\x00");
+    }
+
+    #[test]
+    fn test_cdata_fix_after_escaping() {
+        // U+5D5D encodes as 80 5D 5D, which forms "]]>" with a following '>'
+        let script = escape_unicode("\u{5D5D}>").unwrap();
+        let result = wrap_encoded_lua_script(&script, "").unwrap();
+        assert!(result.windows(16).any(|w| w == b"\x80]]]]><![CDATA[>"));
+    }
+
     #[test]
     fn test_text_to_lua_script() {
         let text = "Hello, TI-Nspire!\nThis is a plain text note.";
-        let lua_script = text_to_lua_script(text);
+        let lua_script = text_to_lua_script(text, &RenderOptions::default());
         
         // Check that the Lua script contains the text
         assert!(lua_script.contains("Hello, TI-Nspire!"));
@@ -541,11 +1009,59 @@ mod tests {
         assert!(lua_script.contains("platform.window:invalidate()"));
     }
 
+    #[test]
+    fn test_text_to_lua_script_with_matrix() {
+        let text = "M = $\\begin{bmatrix} 1 & 2 \\\\ 3 & 4 \\end{bmatrix}$\nafter";
+        let lua_script = text_to_lua_script(text, &RenderOptions::default());
+
+        // Block rows are marked and split into cells for the viewer
+        assert!(lua_script.contains("\x01M = \t[\t1\t  \t2\t]\n\x01\t[\t3\t  \t4\t]\nafter"));
+        assert!(lua_script.contains("line.block.widths[i]"));
+    }
+
+    #[test]
+    fn test_text_to_lua_script_2d() {
+        let options = RenderOptions { layout: MathLayout::TwoD, ..Default::default() };
+        let lua_script = text_to_lua_script("$\\frac{1}{n}$\n$x^2$", &options);
+        assert!(lua_script.contains("\x01 \t1\t \n\x01-\t-\t-\n\x01 \tn\t \nx²"));
+    }
+
+    #[test]
+    fn test_text_to_lua_script_native_math() {
+        let options = RenderOptions { layout: MathLayout::Native, ..Default::default() };
+        let lua_script = text_to_lua_script("Area:\n\\[\\int_0^1 x\\,dx = \\frac{1}{2}\\]", &options);
+
+        assert!(lua_script.contains("[[Area:\n\x021]]"));
+        assert!(lua_script.contains("local formulas = {\n    {t=\"row\", {t=\"op\", k=\"int\""));
+        assert!(lua_script.contains("function math_draw(gc, box, x, y, level)"));
+        assert!(lua_script.contains("math_measure(gc, formula, 1)"));
+
+        // Without formulas the runtime is left out
+        let plain = text_to_lua_script("plain $\\alpha$", &options);
+        assert!(!plain.contains("math_draw(gc, box"));
+    }
+
+    #[test]
+    #[ignore = "needs luac on the PATH"]
+    fn test_text_to_lua_script_compiles() {
+        let options = RenderOptions { layout: MathLayout::Native, ..Default::default() };
+        let script = text_to_lua_script(
+            "Area $\\int_0^1 x\\,dx$ is $\\frac{1}{2}$\n\\[\\sqrt[3]{\\begin{pmatrix} a \\\\ b \\end{pmatrix}}\\]",
+            &options,
+        );
+        let path = std::env::temp_dir().join("test_text_viewer.lua");
+        std::fs::write(&path, script).unwrap();
+
+        let output = std::process::Command::new("luac").arg("-p").arg(&path).output().expect("luac is not installed");
+        let _ = std::fs::remove_file(&path);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+
     #[test]
     fn test_text_to_lua_script_with_special_chars() {
         // Test text containing ]] which needs special handling
         let text = "Test with ]] brackets";
-        let lua_script = text_to_lua_script(text);
+        let lua_script = text_to_lua_script(text, &RenderOptions::default());
         
         // Should use [=[ ]=] delimiter since text contains ]]
         assert!(lua_script.contains("[=["));
@@ -595,4 +1111,163 @@ mod tests {
         let result_str = String::from_utf8_lossy(&result);
         assert!(result_str.contains("Hello"));
     }
+
+    #[test]
+    fn test_ti_xml_writer_matches_python_header() {
+        // Rebuild the hard-coded Python wrapper with the writer
+        let mut writer = TiXmlWriter::new();
+        writer.open("prob", &[("xmlns", "urn:TI.Problem"), ("ver", "1.0"), ("pbname", "")]).unwrap();
+        writer.open("sym", &[]).unwrap();
+        writer.close().unwrap();
+        writer.open("card", &[("clay", "0"), ("h1", "10000"), ("h2", "10000"), ("w1", "10000"), ("w2", "10000")]).unwrap();
+        writer.element("isDummyCard", "0").unwrap();
+        writer.element("flag", "0").unwrap();
+        writer.open("wdgt", &[("xmlns:py", "urn:TI.PythonEditor"), ("type", "TI.PythonEditor"), ("ver", "1.0")]).unwrap();
+        writer.open("py:data", &[]).unwrap();
+        writer.element("py:name", "test.py").unwrap();
+        writer.element("py:dirf", "-10000000").unwrap();
+        writer.close().unwrap();
+        writer.element("py:mFlags", "1024").unwrap();
+        writer.element("py:value", "10").unwrap();
+        writer.close().unwrap();
+        writer.close().unwrap();
+        writer.close().unwrap();
+
+        let expected = wrap_python_script("test.py", "").unwrap();
+        assert_eq!(writer.finish().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_ti_xml_writer_reuses_tag_index() {
+        let mut writer = TiXmlWriter::new();
+        writer.open("a", &[]).unwrap();
+        writer.element("b", "1 < 2").unwrap();
+        writer.element("b", "x").unwrap();
+        writer.close().unwrap();
+
+        let result = writer.finish().unwrap();
+        assert_eq!(result, b"TIXC0100-1.0?><a><b>1 &lt; 2\x0E\x01<b>x\x0E\x01\x0E\x00");
+    }
+
+    #[test]
+    fn test_ti_xml_writer_unclosed() {
+        let mut writer = TiXmlWriter::new();
+        writer.open("a", &[]).unwrap();
+        assert!(writer.finish().is_err());
+        assert!(TiXmlWriter::new().close().is_err());
+    }
+
+    #[test]
+    fn test_build_problem_notes() {
+        let widget = Widget::Notes { tree: "<r2dtotree/>".to_string() };
+        let result = build_problem(&[widget]).unwrap();
+        let result_str = String::from_utf8_lossy(&result);
+
+        assert!(result_str.starts_with("TIXC0100-1.0?><prob"));
+        assert!(result_str.contains("<sym>\x0E\x01"));
+        assert!(result_str.contains("type=\"TI.Notepad\""));
+        assert!(result_str.contains("<np:txt>&lt;r2dtotree/&gt;"));
+        assert!(build_problem(&[]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_build_problem_lists() {
+        use super::super::variables::VarValue;
+
+        let variables = vec![DocVariable::new(
+            "xs",
+            VarValue::List(vec![VarValue::Number(1.0), VarValue::Text("a".to_string())]),
+        )];
+        let widget = Widget::ListsSpreadsheet { columns: vec!["xs".to_string()] };
+        let result = build_problem_with_variables(&variables, &[widget]).unwrap();
+        let result_str = String::from_utf8_lossy(&result);
+
+        assert!(result_str.contains("<sym><var name=\"xs\" type=\"list\"><expr>{1,&quot;a&quot;}"));
+        assert!(result_str.contains("type=\"TI.ListsAndSpreadsheet\""));
+        assert!(result_str.contains("<ls:col idx=\"0\" var=\"xs\">"));
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_build_problem_graphs() {
+        use super::super::graph_spec::SpecFormat;
+
+        let spec = GraphSpec::parse("f1(x)=a*x<2\nslider a = 1\n", SpecFormat::Text).unwrap();
+        let result = build_problem_with_variables(&spec.slider_variables(), &[Widget::Graphs(spec)]).unwrap();
+        let result_str = String::from_utf8_lossy(&result);
+
+        assert!(result_str.contains("<var name=\"a\" type=\"num\"><expr>1"));
+        assert!(result_str.contains("type=\"TI.Graph\""));
+        assert!(result_str.contains("<gr:wnd xmin=\"-10\" xmax=\"10\" ymin=\"-6.67\" ymax=\"6.67\">"));
+        assert!(result_str.contains("<gr:fn name=\"f1\" var=\"x\">a*x&lt;2"));
+        assert!(result_str.contains("<gr:sldr var=\"a\" min=\"0\" max=\"10\" step=\"1\">"));
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_build_problem_program_editor() {
+        use super::super::tibasic::parse_tibasic;
+
+        let definitions = parse_tibasic("Define LibPub hi()=Prgm\nDisp \"a<b\"\nEndPrgm").unwrap();
+        let variables = vec![definitions[0].to_variable()];
+        let widgets = vec![Widget::ProgramEditor { name: "hi".to_string() }];
+        let result = build_problem_with_variables(&variables, &widgets).unwrap();
+        let result_str = String::from_utf8_lossy(&result);
+
+        assert!(result_str.contains(
+            "<var name=\"hi\" type=\"prgm\"><expr>Define LibPub hi()=Prgm\nDisp &quot;a&lt;b&quot;\nEndPrgm"
+        ));
+        assert!(result_str.contains("type=\"TI.ProgramEditor\""));
+        assert!(result_str.contains("<pe:prog>hi"));
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_build_problem_lua_resources() {
+        let resources = vec![ScriptResource { name: "dot".to_string(), data: vec![1, 2] }];
+        let widget = Widget::LuaScript { script: b"-- draw".to_vec(), resources };
+        let result = build_problem(&[widget]).unwrap();
+        let result_str = String::from_utf8_lossy(&result);
+
+        assert!(result_str.contains("<sc:rsrc name=\"dot\" type=\"IMG\"><![CDATA[\\001\\002]]>"));
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_build_problem_python_shell() {
+        let widgets = vec![
+            Widget::PythonShell { filename: "main.py".to_string() },
+            Widget::PythonEditor { filename: "main.py".to_string() },
+        ];
+        let result = build_problem(&widgets).unwrap();
+        let result_str = String::from_utf8_lossy(&result);
+
+        let shell = result_str.find("type=\"TI.PythonShell\"").unwrap();
+        let editor = result_str.find("type=\"TI.PythonEditor\"").unwrap();
+        assert!(shell < editor);
+        assert!(result_str.contains("<psh:data><psh:name>main.py\x0E\x07<psh:run>1"));
+    }
+
+    #[test]
+    fn test_build_problem_python_matches_header() {
+        let widget = Widget::PythonEditor { filename: "main.py".to_string() };
+        let built = build_problem(&[widget]).unwrap();
+        assert_eq!(built, wrap_python_script("main.py", "").unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "unverified-pages")]
+    fn test_build_problem_lua_with_variables() {
+        use super::super::variables::VarValue;
+
+        let variables = vec![DocVariable::new("g", VarValue::Number(9.81))];
+        let widget = Widget::LuaScript { script: b"print(\"]]>\")".to_vec(), resources: Vec::new() };
+        let result = build_problem_with_variables(&variables, &[widget]).unwrap();
+        let result_str = String::from_utf8_lossy(&result);
+
+        assert!(result_str.contains("<var name=\"g\" type=\"num\"><expr>9.81"));
+        assert!(result_str.contains("type=\"TI.ScriptApp\""));
+        assert!(result_str.contains("<sc:script version=\"512\" id=\"0\"><![CDATA[print(\"]]]]><![CDATA[>\")]]>"));
+    }
 }
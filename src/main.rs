@@ -3,35 +3,73 @@
 
 mod core;
 
-use std::path::Path;
-use core::converter::Converter;
+use std::path::{Path, PathBuf};
+use core::converter::{ConversionError, ConversionOptions, Converter};
+#[cfg(feature = "unverified-pages")]
+use core::converter::ShellPage;
+use core::glyphs::GlyphProfile;
+#[cfg(feature = "unverified-pages")]
+use core::graph_spec::SpecFormat;
+#[cfg(feature = "unverified-pages")]
+use core::image::ResourceFile;
+use core::math_render::{unicode_to_latex, MathLayout, MathScope};
+use core::python::{self, PythonModule};
+use core::symbols::SymbolTable;
+use core::ti_expr::latex_to_ti_expression;
+#[cfg(feature = "unverified-pages")]
+use core::variables::{self, DocVariable, VarsFormat};
+
+/// Parsed command line
+struct CliArgs {
+    input_path: PathBuf,
+    output_path: PathBuf,
+    options: ConversionOptions,
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
+    let args: Vec<String> = std::env::args().skip(1).collect();
 
-    if args.len() < 3 {
-        print_usage();
-        std::process::exit(1);
+    if args.first().is_some_and(|arg| arg == "expr") {
+        run_expr(&args[1..]);
+    }
+    if args.first().is_some_and(|arg| arg == "latex") {
+        run_latex(&args[1..]);
     }
 
-    let input_path = Path::new(&args[1]);
-    let output_path = Path::new(&args[2]);
-
-    let content = match std::fs::read_to_string(input_path) {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("Error reading {}: {}", input_path.display(), e);
+    let cli = match parse_args(&args) {
+        Ok(cli) => cli,
+        Err(message) => {
+            if !message.is_empty() {
+                eprintln!("Error: {}", message);
+                eprintln!();
+            }
+            print_usage();
             std::process::exit(1);
         }
     };
 
-    let converter = Converter::new();
+    let input_path = cli.input_path.as_path();
+    let output_path = cli.output_path.as_path();
+
+    let converter = Converter::with_options(cli.options);
 
     // Detect type from extension
     let ext = input_path.extension()
         .and_then(|e| e.to_str())
         .unwrap_or("")
         .to_lowercase();
+    // .toml is also the format of --vars, --symbols and --device files, so
+    // only *.graph.toml is taken as a graph spec
+    #[cfg(feature = "unverified-pages")]
+    let is_graph_toml = input_path.to_string_lossy().to_lowercase().ends_with(".graph.toml");
+
+    let content = match read_source(&converter, input_path, ext == "py") {
+        Ok(c) => c,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     let result = match ext.as_str() {
         "lua" => converter.convert_lua_to_tns(&content, output_path, ""),
@@ -39,13 +77,48 @@ fn main() {
             let filename = input_path.file_name()
                 .and_then(|n| n.to_str())
                 .unwrap_or("script.py");
-            converter.convert_python_to_tns(&content, filename, output_path, "")
+            // Pull in local modules the script imports
+            let dir = input_path.parent().unwrap_or(Path::new("."));
+            let main = PythonModule::new(filename, &content);
+            let modules = match python::collect_modules(main, dir, |path| read_source(&converter, path, true)) {
+                Ok(modules) => modules,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            for module in &modules[1..] {
+                println!("Including module {}", module.filename);
+            }
+            converter.convert_python_project_to_tns(&modules, output_path, "")
         }
+        #[cfg(feature = "unverified-pages")]
+        "csv" => converter.convert_csv_to_tns(&content, output_path, ""),
+        #[cfg(feature = "unverified-pages")]
+        "graph" => converter.convert_graph_spec_to_tns(&content, SpecFormat::Text, output_path, ""),
+        #[cfg(feature = "unverified-pages")]
+        "toml" if is_graph_toml => converter.convert_graph_spec_to_tns(&content, SpecFormat::Toml, output_path, ""),
+        #[cfg(feature = "unverified-pages")]
+        "toml" => Err(ConversionError::InvalidInput(format!(
+            "{} is not a graph spec; name graph specs *.graph.toml",
+            input_path.display()
+        ))),
+        #[cfg(feature = "unverified-pages")]
+        "tib" | "tibasic" => converter.convert_tibasic_to_tns(&content, output_path, ""),
+        // These pages are only written by builds with the feature
+        #[cfg(not(feature = "unverified-pages"))]
+        "csv" | "graph" | "tib" | "tibasic" => Err(ConversionError::InvalidInput(format!(
+            ".{} input needs a build with the unverified-pages feature",
+            ext
+        ))),
         _ => converter.convert_text_to_tns(&content, output_path, ""),
     };
 
     match result {
-        Ok(()) => {
+        Ok(report) => {
+            for warning in &report.warnings {
+                eprintln!("Warning: {}", warning);
+            }
             println!("Created {}", output_path.display());
             std::process::exit(0);
         }
@@ -56,22 +129,288 @@ fn main() {
     }
 }
 
+/// `luna-rs expr`: print LaTeX math as calculator input
+///
+/// The arguments are one expression. Without arguments every line of
+/// standard input is converted, so a formula sheet can be piped through;
+/// lines that fail are reported and left empty in the output.
+fn run_expr(args: &[String]) -> ! {
+    if !args.is_empty() {
+        match latex_to_ti_expression(&args.join(" ")) {
+            Ok(expr) => {
+                println!("{}", expr);
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut failed = false;
+    for (index, line) in std::io::stdin().lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        };
+        if line.trim().is_empty() {
+            println!();
+            continue;
+        }
+        match latex_to_ti_expression(&line) {
+            Ok(expr) => println!("{}", expr),
+            Err(e) => {
+                eprintln!("Error: line {}: {}", index + 1, e);
+                println!();
+                failed = true;
+            }
+        }
+    }
+    std::process::exit(if failed { 1 } else { 0 });
+}
+
+/// `luna-rs latex`: print Unicode math, as on a converted page, as LaTeX
+///
+/// The arguments are one formula. Without arguments every line of standard
+/// input is converted.
+fn run_latex(args: &[String]) -> ! {
+    if !args.is_empty() {
+        println!("{}", unicode_to_latex(&args.join(" ")));
+        std::process::exit(0);
+    }
+
+    for line in std::io::stdin().lines() {
+        match line {
+            Ok(line) => println!("{}", unicode_to_latex(&line)),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    std::process::exit(0);
+}
+
+/// Read an input file through the converter's normalization
+///
+/// Prints a note for everything that was changed in the text.
+fn read_source(converter: &Converter, path: &Path, python: bool) -> Result<String, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Reading {}: {}", path.display(), e))?;
+    let source = converter
+        .load_source(&bytes, python)
+        .map_err(|e| format!("Reading {}: {}", path.display(), e))?;
+    for change in &source.changes {
+        println!("Note: {}: {}", path.display(), change);
+    }
+    Ok(source.text)
+}
+
+/// Split the command line into options and the two positional paths
+///
+/// Returns an empty message when only the usage should be shown.
+fn parse_args(args: &[String]) -> Result<CliArgs, String> {
+    let mut options = ConversionOptions::default();
+    let mut positional = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--notes-app" => options.notes_app = true,
+            "--strict" => options.strict = true,
+            "--sanitize" => options.sanitize_filenames = true,
+            "--raw-utf8" => options.raw_utf8 = true,
+            "--expand-tabs" => {
+                let value = args.next().ok_or("--expand-tabs needs a width")?;
+                let width = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|w| (1..=16).contains(w))
+                    .ok_or_else(|| format!("Tab width must be 1 to 16, got '{}'", value))?;
+                options.tab_width = Some(width);
+            }
+            "--math-layout" => {
+                let value = args.next().ok_or("--math-layout needs linear, 2d or native")?;
+                options.math.layout = match value.as_str() {
+                    "linear" => MathLayout::Linear,
+                    "2d" => MathLayout::TwoD,
+                    "native" => MathLayout::Native,
+                    _ => return Err(format!("Math layout must be linear, 2d or native, got '{}'", value)),
+                };
+            }
+            "--math-scope" => {
+                let value = args.next().ok_or("--math-scope needs delimited or document")?;
+                options.math.scope = match value.as_str() {
+                    "delimited" => MathScope::Delimited,
+                    "document" => MathScope::Document,
+                    _ => return Err(format!("Math scope must be delimited or document, got '{}'", value)),
+                };
+            }
+            "--symbols" => {
+                let path = args.next().ok_or("--symbols needs a file")?;
+                options.math.symbols = load_symbol_table(path)?;
+            }
+            "--device" => {
+                let value = args.next().ok_or("--device needs a calculator model or a profile file")?;
+                options.math.glyphs = load_glyph_profile(value)?;
+            }
+            #[cfg(feature = "unverified-pages")]
+            "--shell" => options.python_shell = ShellPage::Last,
+            #[cfg(feature = "unverified-pages")]
+            "--shell-first" => options.python_shell = ShellPage::First,
+            #[cfg(feature = "unverified-pages")]
+            "--delimiter" => {
+                let value = args.next().ok_or("--delimiter needs a value")?;
+                options.csv_delimiter = Some(parse_delimiter(value)?);
+            }
+            #[cfg(feature = "unverified-pages")]
+            "--vars" => {
+                let path = args.next().ok_or("--vars needs a file")?;
+                options.variables = load_vars_file(path)?;
+            }
+            #[cfg(feature = "unverified-pages")]
+            "--resource" => {
+                let value = args.next().ok_or("--resource needs name=path")?;
+                options.resources.push(load_resource_file(value)?);
+            }
+            "-h" | "--help" => return Err(String::new()),
+            // Everything after -- is a path, even `expr` or `--notes-app`
+            "--" => positional.extend(args.by_ref()),
+            flag if flag.starts_with("--") => {
+                return Err(format!("Unknown option {}", flag));
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    match positional.as_slice() {
+        [input, output] => Ok(CliArgs {
+            input_path: PathBuf::from(input),
+            output_path: PathBuf::from(output),
+            options,
+        }),
+        [] => Err(String::new()),
+        _ => Err("Expected an input and an output file".to_string()),
+    }
+}
+
+/// Accept a single character, or `tab` / `\t` for tab-separated files
+#[cfg(feature = "unverified-pages")]
+fn parse_delimiter(value: &str) -> Result<char, String> {
+    match value {
+        "tab" | "\\t" => Ok('\t'),
+        _ => {
+            let mut chars = value.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(c),
+                _ => Err(format!("Delimiter must be a single character, got '{}'", value)),
+            }
+        }
+    }
+}
+
+/// Read document variables from a `.json` or `.toml` file
+#[cfg(feature = "unverified-pages")]
+fn load_vars_file(path: &str) -> Result<Vec<DocVariable>, String> {
+    let format = if path.to_lowercase().ends_with(".json") {
+        VarsFormat::Json
+    } else {
+        VarsFormat::Toml
+    };
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Reading {}: {}", path, e))?;
+    variables::load_variables(&text, format).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// A built-in glyph profile by model name, or a `.toml` profile file
+fn load_glyph_profile(value: &str) -> Result<GlyphProfile, String> {
+    if !value.to_lowercase().ends_with(".toml") {
+        return GlyphProfile::device(value).map_err(|e| e.to_string());
+    }
+    let text = std::fs::read_to_string(value).map_err(|e| format!("Reading {}: {}", value, e))?;
+    GlyphProfile::from_toml(&text).map_err(|e| format!("{}: {}", value, e))
+}
+
+/// Read a symbol table that extends the LaTeX mappings
+fn load_symbol_table(path: &str) -> Result<SymbolTable, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("Reading {}: {}", path, e))?;
+    SymbolTable::from_toml(&text).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Read an image given as `name=path`
+#[cfg(feature = "unverified-pages")]
+fn load_resource_file(value: &str) -> Result<ResourceFile, String> {
+    let (name, path) = value
+        .split_once('=')
+        .ok_or_else(|| format!("--resource expects name=path, got '{}'", value))?;
+    let data = std::fs::read(path).map_err(|e| format!("Reading {}: {}", path, e))?;
+    Ok(ResourceFile { name: name.to_string(), data })
+}
+
 fn print_usage() {
     eprintln!("Luna-RS v0.1.0 - TI-Nspire .tns file converter");
     eprintln!();
     eprintln!("USAGE:");
-    eprintln!("    luna-rs <input> <output.tns>");
+    eprintln!("    luna-rs [OPTIONS] <input> <output.tns>");
+    eprintln!("    luna-rs expr [latex]   Print LaTeX math as calculator input (without an");
+    eprintln!("                           argument, one expression per line of stdin)");
+    eprintln!("    luna-rs latex [text]   Print Unicode math as LaTeX (without an argument,");
+    eprintln!("                           one formula per line of stdin)");
+    eprintln!("    luna-rs [OPTIONS] -- <input> <output.tns>");
+    eprintln!("                           Paths after -- are never options, `expr` or `latex`");
     eprintln!();
     eprintln!("SUPPORTED INPUT TYPES:");
     eprintln!("    .lua  - Lua script (OS 3.0.2+)");
-    eprintln!("    .py   - Python script (CX II OS 5.2+)");
+    eprintln!("    .py   - Python script (CX II OS 5.2+), with the local modules it imports");
     eprintln!("    .txt  - Plain text with LaTeX math support");
+    if cfg!(feature = "unverified-pages") {
+        eprintln!("    .csv  - Table data for a Lists & Spreadsheet page");
+        eprintln!("    .graph, .graph.toml - Function spec for a Graphs page");
+        eprintln!("    .tib, .tibasic - TI-Basic programs and functions (Define blocks)");
+    }
+    eprintln!();
+    eprintln!("OPTIONS:");
+    eprintln!("    --notes-app        Put text in a native Notes page instead of a Lua viewer");
+    eprintln!("    --math-layout <linear|2d|native>  Math in text as one line (default), as stacked");
+    eprintln!("                       fractions and limits, or drawn with lines by the viewer");
+    eprintln!("    --math-scope <delimited|document>  Read LaTeX only between $...$, \\(...\\) and");
+    eprintln!("                       \\[...\\] (default), or everywhere in the text");
+    eprintln!("    --device <model|file.toml>  Use the math symbols the device font has: clickpad,");
+    eprintln!("                       touchpad (default), cx, cx2 or a glyph profile file");
+    eprintln!("    --symbols <file>   Add or replace LaTeX symbols with a .toml table");
+    eprintln!("    --strict           Fail on LaTeX problems in text instead of warning about them");
+    if cfg!(feature = "unverified-pages") {
+        eprintln!("    --delimiter <c>    CSV field delimiter (default ',', use 'tab' for TSV)");
+        eprintln!("    --vars <file>      Store variables from a .toml or .json file in the document");
+    }
+    eprintln!("    --sanitize         Rename Python files the calculator would reject");
+    eprintln!("    --expand-tabs <n>  Expand tab indentation in Python files to n-column stops");
+    eprintln!("    --raw-utf8         Keep Lua scripts as UTF-8 instead of TI's character encoding");
+    if cfg!(feature = "unverified-pages") {
+        eprintln!("    --shell            Add a Python Shell page that runs the script");
+        eprintln!("    --shell-first      Like --shell, with the shell as the first page");
+        eprintln!("    --resource <name=path>  Embed a PNG/BMP/PPM image in a Lua script as _R.IMG.name");
+    }
     eprintln!();
     eprintln!("EXAMPLES:");
     eprintln!("    luna-rs script.lua output.tns");
     eprintln!("    luna-rs notes.txt notes.tns");
+    eprintln!("    luna-rs --notes-app notes.txt notes.tns");
+    eprintln!("    luna-rs --math-layout 2d formulas.txt formulas.tns");
+    eprintln!("    luna-rs --device cx2 formulas.txt formulas.tns");
+    if cfg!(feature = "unverified-pages") {
+        eprintln!("    luna-rs --delimiter ';' data.csv data.tns");
+        eprintln!("    luna-rs parabola.graph parabola.tns");
+        eprintln!("    luna-rs --shell-first game.py game.tns");
+        eprintln!("    luna-rs --vars constants.toml physics.lua physics.tns");
+        eprintln!("    luna-rs --resource sprite=sprite.png game.lua game.tns");
+    }
+    eprintln!("    luna-rs expr '\\int_0^1 x^2 \\, dx'");
+    eprintln!("    luna-rs latex 'x² + α ≤ ∑'");
     eprintln!();
-    eprintln!("LATEX MATH NOTATION:");
+    eprintln!("LATEX MATH NOTATION (between $...$; \\$ is a dollar sign):");
     eprintln!("    Greek: \\alpha, \\beta, \\gamma → α, β, γ");
     eprintln!("    Superscripts: x^2 → x²");
     eprintln!("    Subscripts: H_2O → H₂O");